@@ -0,0 +1,335 @@
+use std::fs;
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    command::tools::scope::{scope_allows, scope_allows_space},
+    config::{JwtAlgorithm, JwtConfig},
+    json::input::{Command, CommandCategory, TokenScope},
+};
+
+// コマンドの実行者
+// roles は JWT の roles クレームから取ったもの。None なら user DB のロールで判断する
+// scope は API トークンで認証したときのスコープ
+// request_id は監査ログとログ出力でリクエストを追うための ID
+// session_id はセッションで認証したときのセッション ID（ハッシュ）
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub user: String,
+    pub roles: Option<Vec<String>>,
+    pub scope: Option<TokenScope>,
+    pub request_id: String,
+    pub session_id: Option<String>,
+}
+
+impl Caller {
+    // セッションで認証したユーザー。request_id は新しく振る
+    pub fn new(user: &str) -> Self {
+        Caller {
+            user: user.to_string(),
+            roles: None,
+            scope: None,
+            request_id: Uuid::new_v4().to_string(),
+            session_id: None,
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        match &self.roles {
+            Some(roles) => roles.iter().any(|r| r == "admin"),
+            None => self.user == "admin",
+        }
+    }
+
+    // API トークンのスコープ内のコマンドか
+    pub fn allows(&self, cmd: &Command) -> bool {
+        self.scope.as_ref().is_none_or(|s| scope_allows(s, cmd))
+    }
+
+    // Command を経由しないエンドポイント（タイル・エクスポート）用
+    pub fn allows_space(&self, category: CommandCategory, space_name: &str) -> bool {
+        self.scope
+            .as_ref()
+            .is_none_or(|s| scope_allows_space(s, category, &[space_name]))
+    }
+}
+
+// local_users でなければ JWT のユーザー名にこれを付け、ローカルユーザーと区別する
+const JWT_USER_PREFIX: &str = "jwt:";
+
+struct JwtKey {
+    algorithm: Algorithm,
+    kid: Option<String>,
+    key: DecodingKey,
+}
+
+// 設定した鍵で Authorization: Bearer の JWT を検証する
+pub struct JwtVerifier {
+    keys: Vec<JwtKey>,
+    user_claim: String,
+    roles_claim: Option<String>,
+    local_users: bool,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway_secs: u64,
+}
+
+impl JwtVerifier {
+    // 鍵が1つも無ければ None（JWT を受け付けない）
+    pub fn new(config: &JwtConfig) -> Result<Option<Self>, String> {
+        if config.keys.is_empty() {
+            return Ok(None);
+        }
+
+        let mut keys = Vec::new();
+        for (i, key) in config.keys.iter().enumerate() {
+            let pem = || {
+                let path = key.public_key_file.as_ref().ok_or_else(|| {
+                    format!(
+                        "jwt.keys[{}] ({:?}) needs public_key_file",
+                        i, key.algorithm
+                    )
+                })?;
+                fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+            };
+            let (algorithm, decoding_key) = match key.algorithm {
+                JwtAlgorithm::HS256 => {
+                    let secret = key
+                        .secret
+                        .as_ref()
+                        .ok_or_else(|| format!("jwt.keys[{}] (HS256) needs secret", i))?;
+                    (
+                        Algorithm::HS256,
+                        DecodingKey::from_secret(secret.as_bytes()),
+                    )
+                }
+                JwtAlgorithm::RS256 => (
+                    Algorithm::RS256,
+                    DecodingKey::from_rsa_pem(&pem()?)
+                        .map_err(|e| format!("Invalid RSA key in jwt.keys[{}]: {}", i, e))?,
+                ),
+                JwtAlgorithm::ES256 => (
+                    Algorithm::ES256,
+                    DecodingKey::from_ec_pem(&pem()?)
+                        .map_err(|e| format!("Invalid EC key in jwt.keys[{}]: {}", i, e))?,
+                ),
+            };
+            keys.push(JwtKey {
+                algorithm,
+                kid: key.kid.clone(),
+                key: decoding_key,
+            });
+        }
+
+        Ok(Some(JwtVerifier {
+            keys,
+            user_claim: config.user_claim.clone(),
+            roles_claim: config.roles_claim.clone(),
+            local_users: config.local_users,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            leeway_secs: config.leeway_secs,
+        }))
+    }
+
+    // 署名・exp・nbf（設定があれば iss・aud も）を確かめてクレームから実行者を作る
+    pub fn verify(&self, token: &str) -> Result<Caller, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway_secs;
+        validation.validate_nbf = true;
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        // kid 付きの鍵はヘッダの kid が一致するときだけ試す
+        let mut last_error = format!(
+            "No key configured for {:?} (kid {:?})",
+            header.alg, header.kid
+        );
+        let claims = self
+            .keys
+            .iter()
+            .filter(|k| k.algorithm == header.alg)
+            .filter(|k| k.kid.is_none() || k.kid == header.kid)
+            .find_map(|k| match decode::<Value>(token, &k.key, &validation) {
+                Ok(data) => Some(data.claims),
+                Err(e) => {
+                    last_error = e.to_string();
+                    None
+                }
+            })
+            .ok_or(last_error)?;
+
+        let user = claims
+            .get(&self.user_claim)
+            .and_then(Value::as_str)
+            .filter(|u| !u.is_empty())
+            .ok_or_else(|| format!("Missing '{}' claim", self.user_claim))?;
+
+        // 文字列の配列か、空白区切りの文字列を受け付ける
+        let roles = self
+            .roles_claim
+            .as_ref()
+            .and_then(|c| claims.get(c))
+            .map(|v| match v {
+                Value::Array(list) => list
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect(),
+                Value::String(s) => s.split_whitespace().map(str::to_string).collect(),
+                _ => Vec::new(),
+            });
+
+        if self.local_users {
+            return Ok(Caller {
+                roles,
+                ..Caller::new(user)
+            });
+        }
+        // 保存済みのロールには頼らない（roles クレームが無ければロールなし）
+        Ok(Caller {
+            roles: Some(roles.unwrap_or_default()),
+            ..Caller::new(&format!("{}{}", JWT_USER_PREFIX, user))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::JwtKeyConfig, io::tools::time::now_millis};
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::json;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn verifier(local_users: bool, kid: Option<&str>) -> JwtVerifier {
+        let config = JwtConfig {
+            keys: vec![JwtKeyConfig {
+                algorithm: JwtAlgorithm::HS256,
+                kid: kid.map(str::to_string),
+                secret: Some(SECRET.to_string()),
+                public_key_file: None,
+            }],
+            roles_claim: Some("roles".to_string()),
+            local_users,
+            issuer: Some("gateway".to_string()),
+            audience: Some("kasane".to_string()),
+            ..JwtConfig::default()
+        };
+        JwtVerifier::new(&config).unwrap().unwrap()
+    }
+
+    fn sign(claims: Value, kid: Option<&str>, secret: &str) -> String {
+        let header = Header {
+            kid: kid.map(str::to_string),
+            ..Header::default()
+        };
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    // 有効な iss / aud / exp を持つクレームに extra を足す
+    fn claims(extra: Value) -> Value {
+        let mut claims = json!({
+            "iss": "gateway",
+            "aud": "kasane",
+            "exp": now_millis() / 1000 + 600,
+        });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        claims
+    }
+
+    fn verify(v: &JwtVerifier, extra: Value) -> Result<Caller, String> {
+        v.verify(&sign(claims(extra), None, SECRET))
+    }
+
+    #[test]
+    fn non_local_users_are_prefixed_and_get_only_claimed_roles() {
+        let v = verifier(false, None);
+
+        let caller = verify(&v, json!({"sub": "bob", "roles": "reader writer"})).unwrap();
+        assert_eq!(caller.user, "jwt:bob");
+        assert_eq!(
+            caller.roles,
+            Some(vec!["reader".to_string(), "writer".to_string()])
+        );
+
+        // roles クレームが無ければロールなし。名前が admin でも admin にはならない
+        let caller = verify(&v, json!({"sub": "admin"})).unwrap();
+        assert_eq!(caller.user, "jwt:admin");
+        assert_eq!(caller.roles, Some(Vec::new()));
+        assert!(!caller.is_admin());
+
+        let caller = verify(&v, json!({"sub": "carol", "roles": ["admin", 1]})).unwrap();
+        assert_eq!(caller.roles, Some(vec!["admin".to_string()]));
+        assert!(caller.is_admin());
+        assert!(caller.scope.is_none());
+    }
+
+    #[test]
+    fn local_users_keep_their_name_and_stored_roles() {
+        let v = verifier(true, None);
+
+        let caller = verify(&v, json!({"sub": "bob"})).unwrap();
+        assert_eq!(caller.user, "bob");
+        assert_eq!(caller.roles, None);
+
+        let caller = verify(&v, json!({"sub": "bob", "roles": ["reader"]})).unwrap();
+        assert_eq!(caller.roles, Some(vec!["reader".to_string()]));
+    }
+
+    #[test]
+    fn invalid_tokens_are_rejected() {
+        let v = verifier(false, None);
+        let sub = || json!({"sub": "bob"});
+
+        assert!(
+            v.verify(&sign(
+                claims(sub()),
+                None,
+                "another-secret-another-secret!!"
+            ))
+            .is_err()
+        );
+        assert!(verify(&v, json!({"sub": ""})).is_err());
+        assert!(verify(&v, json!({"roles": ["admin"]})).is_err());
+        assert!(verify(&v, json!({"sub": "bob", "iss": "other"})).is_err());
+        assert!(verify(&v, json!({"sub": "bob", "aud": "other"})).is_err());
+        // leeway (60秒) を超えて期限切れ
+        assert!(verify(&v, json!({"sub": "bob", "exp": now_millis() / 1000 - 120})).is_err());
+        assert!(verify(&v, json!({"sub": "bob", "nbf": now_millis() / 1000 + 600})).is_err());
+        assert!(v.verify("not-a-jwt").is_err());
+    }
+
+    #[test]
+    fn keys_with_kid_need_a_matching_header() {
+        let v = verifier(false, Some("k1"));
+        let token = |kid| sign(claims(json!({"sub": "bob"})), kid, SECRET);
+
+        assert!(v.verify(&token(Some("k1"))).is_ok());
+        assert!(v.verify(&token(Some("k2"))).is_err());
+        assert!(v.verify(&token(None)).is_err());
+    }
+
+    #[test]
+    fn no_keys_disables_jwt() {
+        assert!(JwtVerifier::new(&JwtConfig::default()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fs::OpenOptions, io::Write};
+
+use log::{info, warn};
+
+use crate::{
+    config::AdminConfig,
+    io::{
+        full::Storage,
+        tools::{role::ADMIN_ROLE, token::random_token},
+    },
+};
+
+const GENERATED_PASSWORD_LEN: usize = 24;
+// 以前のバージョンが admin に設定していた固定の初期パスワード
+const LEGACY_ADMIN_PASSWORD: &str = "nekocute";
+
+// 初回起動時に admin を作成する
+// パスワードは設定（ファイル・環境変数・コマンドライン）から取り、無ければ生成してファイルに書き出す
+pub fn bootstrap_admin(storage: &Storage, admin: &AdminConfig) -> Result<(), String> {
+    if storage.user_exists("admin").map_err(|e| e.to_string())? {
+        // 固定の初期パスワードのままなら、変更するまで他のコマンドを使えなくする
+        if storage
+            .flag_known_password("admin", LEGACY_ADMIN_PASSWORD)
+            .map_err(|e| e.to_string())?
+        {
+            warn!("admin still has the old default password. It must be changed before use");
+        }
+        return Ok(());
+    }
+
+    let password = match &admin.password {
+        Some(password) => password.clone(),
+        None => {
+            let password = random_token(GENERATED_PASSWORD_LEN);
+            write_secret(admin, &password)?;
+            password
+        }
+    };
+
+    storage
+        .insert_user(
+            "admin",
+            &password,
+            admin.force_password_change,
+            &[ADMIN_ROLE.to_string()],
+        )
+        .map_err(|e| e.to_string())?;
+
+    match &admin.password {
+        Some(_) => info!("Created admin user with the configured password"),
+        None => warn!(
+            "Created admin user. The generated password was written to {}",
+            admin.password_file.display()
+        ),
+    }
+    Ok(())
+}
+
+// 既存のファイルは上書きしない。所有者だけが読めるように作る
+fn write_secret(admin: &AdminConfig, password: &str) -> Result<(), String> {
+    let path = &admin.password_file;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    writeln!(file, "{}", password).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::Caller,
+        command::process,
+        error::Error,
+        io::{
+            StorageTrait,
+            tools::temp_storage::{TempDir, TempStorage},
+        },
+        json::input::{ChangePassword, Command},
+    };
+
+    fn config(dir: &TempDir, password: Option<&str>) -> AdminConfig {
+        AdminConfig {
+            password: password.map(str::to_string),
+            password_file: dir.path().join("admin_password.txt"),
+            force_password_change: true,
+        }
+    }
+
+    #[test]
+    fn generated_password_is_written_once_and_must_be_changed() {
+        let s = TempStorage::default();
+        let dir = TempDir::default();
+        let admin = config(&dir, None);
+        bootstrap_admin(&s, &admin).unwrap();
+
+        let written = std::fs::read_to_string(&admin.password_file).unwrap();
+        let password = written.trim();
+        assert_eq!(password.len(), GENERATED_PASSWORD_LEN);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&admin.password_file)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(s.verify_user("admin", password).unwrap());
+
+        // 変更するまで他のコマンドは使えない
+        let caller = Caller::new("admin");
+        assert!(matches!(
+            process(Command::ShowSpaces, s.shared(), &caller),
+            Err(Error::PasswordChangeRequired { .. })
+        ));
+        let change = Command::ChangePassword(ChangePassword {
+            old_password: password.to_string(),
+            new_password: "another-password".to_string(),
+        });
+        process(change, s.shared(), &caller).unwrap();
+        process(Command::ShowSpaces, s.shared(), &caller).unwrap();
+
+        // 2回目の起動ではファイルも admin も触らない
+        bootstrap_admin(&s, &admin).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&admin.password_file).unwrap(),
+            written
+        );
+        assert!(s.verify_user("admin", "another-password").unwrap());
+    }
+
+    #[test]
+    fn configured_password_is_used_without_a_file() {
+        let s = TempStorage::default();
+        let dir = TempDir::default();
+        let admin = AdminConfig {
+            force_password_change: false,
+            ..config(&dir, Some("configured-pass"))
+        };
+        bootstrap_admin(&s, &admin).unwrap();
+
+        assert!(s.verify_user("admin", "configured-pass").unwrap());
+        assert!(!s.must_change_password("admin").unwrap());
+        assert!(!admin.password_file.exists());
+    }
+
+    #[test]
+    fn existing_password_file_is_not_overwritten() {
+        let s = TempStorage::default();
+        let dir = TempDir::default();
+        let admin = config(&dir, None);
+        std::fs::write(&admin.password_file, "keep").unwrap();
+
+        assert!(bootstrap_admin(&s, &admin).is_err());
+        assert!(!s.user_exists("admin").unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&admin.password_file).unwrap(),
+            "keep"
+        );
+    }
+
+    #[test]
+    fn old_default_password_must_be_changed() {
+        let s = TempStorage::default();
+        let dir = TempDir::default();
+        s.insert_user(
+            "admin",
+            LEGACY_ADMIN_PASSWORD,
+            false,
+            &[ADMIN_ROLE.to_string()],
+        )
+        .unwrap();
+        bootstrap_admin(&s, &config(&dir, None)).unwrap();
+
+        assert!(s.must_change_password("admin").unwrap());
+        assert!(!dir.path().join("admin_password.txt").exists());
+    }
+}
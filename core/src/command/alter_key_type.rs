@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    io::{StorageTrait, full::Storage, tools::range::bitmask_to_id_string},
+    json::{
+        input::AlterKeyType,
+        output::{self, Output},
+    },
+};
+
+pub fn alter_key_type(v: AlterKeyType, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
+    let report = s.alter_key_type(&v.space_name, &v.key_name, v.key_type, v.drop_failed, user)?;
+
+    let failed = report
+        .failed
+        .iter()
+        .map(|bits| bitmask_to_id_string(bits))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Output::AlterKeyType(output::AlterKeyType {
+        applied: report.applied,
+        converted: report.converted,
+        failed,
+        dropped_constraint: report.dropped_constraint.map(Box::new),
+    }))
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::valid_password::valid_password,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::ChangePassword, output::Output},
+};
+
+// 自分のセッション・API トークンは、このリクエストのセッションを除いてすべて失効する
+pub fn change_password(
+    v: ChangePassword,
+    s: Arc<Storage>,
+    caller: &Caller,
+) -> Result<Output, Error> {
+    valid_password(&v.new_password).map_err(|reason| Error::PasswordValidationError {
+        reason,
+        location: "command::change_password",
+    })?;
+    s.change_password(
+        &caller.user,
+        &v.old_password,
+        &v.new_password,
+        caller.session_id.as_deref(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::tools::{temp_storage::TempStorage, token::token_hash},
+        json::input::TokenScope,
+    };
+
+    fn change(old: &str, new: &str) -> ChangePassword {
+        ChangePassword {
+            old_password: old.to_string(),
+            new_password: new.to_string(),
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_current_session() {
+        let s = TempStorage::default();
+        s.create_user("bob", "old-password", &[]).unwrap();
+        let current = s.create_session("bob").unwrap();
+        let other = s.create_session("bob").unwrap();
+        let (token, _) = s
+            .create_token("bob", "t", None, TokenScope::default())
+            .unwrap();
+        let caller = Caller {
+            session_id: Some(token_hash(&current)),
+            ..Caller::new("bob")
+        };
+
+        assert!(matches!(
+            change_password(
+                change("wrong-password", "new-password"),
+                s.shared(),
+                &caller
+            ),
+            Err(Error::AuthenticationFailed { .. })
+        ));
+        assert!(matches!(
+            change_password(change("old-password", "short"), s.shared(), &caller),
+            Err(Error::PasswordValidationError { .. })
+        ));
+        assert!(s.session_user(&other).unwrap().is_some());
+
+        change_password(change("old-password", "new-password"), s.shared(), &caller).unwrap();
+        assert!(s.verify_user("bob", "new-password").unwrap());
+        assert_eq!(s.session_user(&current).unwrap().as_deref(), Some("bob"));
+        assert_eq!(s.session_user(&other).unwrap(), None);
+        assert!(s.token_user(&token).unwrap().is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    io::{StorageTrait, full::Storage, tools::range::range},
+    json::{input::CopyKey, output::Output},
+};
+
+pub fn copy_key(v: CopyKey, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
+    let ids = match v.range.map(range).transpose() {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Error::RangeError { message: e });
+        }
+    };
+    let to_space_name = v.to_space_name.as_deref().unwrap_or(&v.space_name);
+    s.copy_key(
+        &v.space_name,
+        &v.key_name,
+        to_space_name,
+        &v.to_key_name,
+        ids,
+        user,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{
+            KeyOption, ValueEntry,
+            tools::{range::id_string_to_bitmask, temp_storage::TempStorage},
+        },
+        json::input::{Aggregation, IdInput, KeyMode, KeyType, Pyramid, Range},
+    };
+    use kasane_logic::id::DimensionRange;
+
+    fn id(s: &str) -> Vec<u8> {
+        id_string_to_bitmask(s).unwrap()
+    }
+
+    fn create_key(s: &Storage, space: &str) {
+        let option = KeyOption {
+            pyramid: Some(Pyramid {
+                zooms: vec![1],
+                aggregations: vec![Aggregation::Count],
+            }),
+            history: true,
+            constraint: None,
+        };
+        s.create_key(space, "a", KeyType::INT, KeyMode::UniqueKey, option)
+            .unwrap();
+    }
+
+    fn insert(s: &Storage, space: &str, cells: &[&str], value: i32) {
+        let ids = cells.iter().map(|c| id(c)).collect();
+        s.insert_value(space, "a", ids, ValueEntry::INT(value), None, "alice")
+            .unwrap();
+    }
+
+    fn history(s: &Storage, space: &str, cell: &str) -> Vec<(Option<i32>, Option<i32>)> {
+        let int = |v: &Option<ValueEntry>| {
+            v.as_ref().map(|v| match v {
+                ValueEntry::INT(i) => *i,
+                _ => unreachable!(),
+            })
+        };
+        s.value_history(space, "a", vec![id(cell)])
+            .unwrap()
+            .iter()
+            .map(|(_, e)| (int(&e.old), int(&e.new)))
+            .collect()
+    }
+
+    #[test]
+    fn copies_a_range_into_another_space_with_one_history_entry_per_cell() {
+        let s = TempStorage::default();
+        s.create_space("from", "alice").unwrap();
+        s.create_space("to", "alice").unwrap();
+        create_key(&s, "from");
+        create_key(&s, "to");
+        insert(&s, "from", &["3/5/2/7_0/-", "3/5/2/6_0/-"], 1);
+        insert(&s, "from", &["3/0/0/0_0/-"], 2);
+        insert(&s, "to", &["3/5/2/7_0/-"], 9);
+
+        // 2/2/1/3 の範囲だけをコピーする
+        let range = Range::IdSet(vec![IdInput {
+            z: 2,
+            f: DimensionRange::Single(2),
+            x: DimensionRange::Single(1),
+            y: DimensionRange::Single(3),
+            i: 0,
+            t: DimensionRange::Any,
+        }]);
+        let v = CopyKey {
+            space_name: "from".to_string(),
+            key_name: "a".to_string(),
+            to_space_name: Some("to".to_string()),
+            to_key_name: "a".to_string(),
+            range: Some(range),
+        };
+        copy_key(v, s.shared(), "bob").unwrap();
+
+        // 上書きは old→new の1件、新規は None→new の1件だけ
+        assert_eq!(
+            history(&s, "to", "3/5/2/7_0/-"),
+            [(None, Some(9)), (Some(9), Some(1))]
+        );
+        assert_eq!(history(&s, "to", "3/5/2/6_0/-"), [(None, Some(1))]);
+        assert!(history(&s, "to", "3/0/0/0_0/-").is_empty());
+
+        let cells = s
+            .select_pyramid("to", "a", 1, vec![id("1/1/0/1_0/-")])
+            .unwrap();
+        assert_eq!(cells.len(), 1);
+        assert_eq!(cells[0].1.count, 2);
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::io::{StorageTrait, full::Storage};
+use crate::io::{KeyOption, StorageTrait, full::Storage};
 use crate::json::input::CreateKey;
 use crate::json::output::Output;
 use crate::{command::tools::valid_name::valid_name, error::Error};
@@ -13,6 +13,11 @@ pub fn create_key(v: CreateKey, s: Arc<Storage>) -> Result<Output, Error> {
             location: "command::addkey::addkey",
         })
     } else {
-        s.create_key(&v.space_name, &v.key_name, v.key_type, v.key_mode)
+        let option = KeyOption {
+            pyramid: v.pyramid,
+            history: v.history,
+            constraint: v.constraint,
+        };
+        s.create_key(&v.space_name, &v.key_name, v.key_type, v.key_mode, option)
     }
 }
@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::{require_admin::require_admin, valid_name::valid_name},
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::CreateRole, output::Output},
+};
+
+pub fn create_role(v: CreateRole, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::create_role")?;
+    if !valid_name(&v.role_name) {
+        return Err(Error::ParseError {
+            message: format!("Invalid role_name '{}'", v.role_name),
+            location: "command::create_role",
+        });
+    }
+    s.create_role(&v.role_name, &v.privileges)
+}
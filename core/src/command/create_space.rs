@@ -7,7 +7,8 @@ use crate::{
     json::{input::CreateSpace, output::Output},
 };
 
-pub fn create_space(v: CreateSpace, s: Arc<Storage>) -> Result<Output, Error> {
+// 実行ユーザーが所有者になる
+pub fn create_space(v: CreateSpace, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
     if !valid_name(&v.space_name) {
         Err(Error::SpaceNameValidationError {
             name: v.space_name,
@@ -15,6 +16,6 @@ pub fn create_space(v: CreateSpace, s: Arc<Storage>) -> Result<Output, Error> {
             location: "command::addspace::addspace",
         })
     } else {
-        s.create_space(&v.space_name)
+        s.create_space(&v.space_name, user)
     }
 }
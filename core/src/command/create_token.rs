@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::{require_admin::require_admin, scope::scope_within, valid_len::valid_len},
+    error::Error,
+    io::{StorageTrait, full::Storage, tools::time::now_millis},
+    json::{
+        input::CreateToken,
+        output::{CreatedToken, Output},
+    },
+};
+
+pub fn create_token(v: CreateToken, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    let owner = match &v.user_name {
+        Some(name) if *name != caller.user => {
+            require_admin(caller, "command::create_token")?;
+            name.as_str()
+        }
+        _ => caller.user.as_str(),
+    };
+    if v.token_name.is_empty() || !valid_len(&v.token_name) {
+        return Err(Error::ParseError {
+            message: format!("Invalid token_name '{}'", v.token_name),
+            location: "command::create_token",
+        });
+    }
+
+    // API トークンで認証していれば、そのスコープより広いトークンは作れない
+    if let Some(outer) = &caller.scope
+        && !scope_within(&v.scope, outer)
+    {
+        return Err(Error::PermissionDenied {
+            user_name: caller.user.clone(),
+            location: "command::create_token",
+        });
+    }
+
+    let expires_at = v
+        .expires_in_secs
+        .map(|secs| now_millis().saturating_add(secs.saturating_mul(1000)));
+    let (token, record) = s.create_token(owner, &v.token_name, expires_at, v.scope)?;
+    Ok(Output::CreateToken(CreatedToken {
+        token_id: record.id,
+        token,
+        expires_at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::{revoke_token::revoke_token, show_tokens::show_tokens},
+        io::tools::temp_storage::TempStorage,
+        json::input::{CommandCategory, RevokeToken, TokenScope},
+    };
+
+    fn request(user: Option<&str>, scope: TokenScope, expires_in_secs: Option<u64>) -> CreateToken {
+        CreateToken {
+            token_name: "map".to_string(),
+            user_name: user.map(str::to_string),
+            expires_in_secs,
+            scope,
+        }
+    }
+
+    fn created(output: Output) -> CreatedToken {
+        match output {
+            Output::CreateToken(v) => v,
+            _ => unreachable!(),
+        }
+    }
+
+    fn spaces(names: &[&str]) -> TokenScope {
+        TokenScope {
+            spaces: Some(names.iter().map(|s| s.to_string()).collect()),
+            categories: None,
+        }
+    }
+
+    fn storage() -> TempStorage {
+        let s = TempStorage::default();
+        s.create_user("bob", "bob-password", &[]).unwrap();
+        s
+    }
+
+    #[test]
+    fn token_carries_its_scope_until_revoked() {
+        let s = storage();
+        let bob = Caller::new("bob");
+        let token =
+            created(create_token(request(None, spaces(&["a"]), None), s.shared(), &bob).unwrap());
+
+        let record = s.token_user(&token.token).unwrap().unwrap();
+        assert_eq!(record.user_name, "bob");
+        assert_eq!(record.scope.spaces, Some(vec!["a".to_string()]));
+        // 保存するのはハッシュだけ
+        assert_ne!(token.token_id, token.token);
+
+        // 他のユーザーのトークンは admin でなければ失効させられない
+        let revoke = || RevokeToken {
+            token_id: token.token_id.clone(),
+        };
+        assert!(revoke_token(revoke(), s.shared(), &Caller::new("carol")).is_err());
+        revoke_token(revoke(), s.shared(), &bob).unwrap();
+        assert!(s.token_user(&token.token).unwrap().is_none());
+    }
+
+    #[test]
+    fn scoped_callers_cannot_widen_or_act_for_others() {
+        let s = storage();
+        let scoped = Caller {
+            scope: Some(TokenScope {
+                spaces: Some(vec!["a".to_string()]),
+                categories: Some(vec![CommandCategory::Session, CommandCategory::Value]),
+            }),
+            ..Caller::new("bob")
+        };
+
+        let narrower = TokenScope {
+            categories: Some(vec![CommandCategory::Value]),
+            ..spaces(&["a"])
+        };
+        create_token(request(None, narrower, None), s.shared(), &scoped).unwrap();
+        assert!(matches!(
+            create_token(
+                request(None, spaces(&["a", "b"]), None),
+                s.shared(),
+                &scoped
+            ),
+            Err(Error::PermissionDenied { .. })
+        ));
+        assert!(matches!(
+            create_token(
+                request(None, TokenScope::default(), None),
+                s.shared(),
+                &scoped
+            ),
+            Err(Error::PermissionDenied { .. })
+        ));
+        assert!(matches!(
+            create_token(
+                request(Some("alice"), TokenScope::default(), None),
+                s.shared(),
+                &Caller::new("bob")
+            ),
+            Err(Error::PermissionDenied { .. })
+        ));
+
+        // admin は他のユーザーのトークンを作れて、一覧では全員分が見える
+        let admin = Caller {
+            roles: Some(vec!["admin".to_string()]),
+            ..Caller::new("root")
+        };
+        create_token(
+            request(Some("bob"), TokenScope::default(), None),
+            s.shared(),
+            &admin,
+        )
+        .unwrap();
+        let count = |caller: &Caller| match show_tokens(s.shared(), caller).unwrap() {
+            Output::ShowTokens(v) => v.tokens.len(),
+            _ => unreachable!(),
+        };
+        assert_eq!(count(&admin), 2);
+        assert_eq!(count(&Caller::new("carol")), 0);
+    }
+
+    #[test]
+    fn expired_tokens_are_not_accepted() {
+        let s = storage();
+        let bob = Caller::new("bob");
+        let live = created(
+            create_token(
+                request(None, TokenScope::default(), Some(3600)),
+                s.shared(),
+                &bob,
+            )
+            .unwrap(),
+        );
+        assert!(live.expires_at.unwrap() > now_millis());
+        assert!(s.token_user(&live.token).unwrap().is_some());
+
+        let (token, _) = s
+            .create_token("bob", "old", Some(now_millis() - 1), TokenScope::default())
+            .unwrap();
+        assert!(s.token_user(&token).unwrap().is_none());
+    }
+}
@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
 use crate::{
-    command::tools::valid_name::valid_name,
+    auth::Caller,
+    command::tools::{require_admin::require_admin, valid_name::valid_name},
     error::Error,
-    io::{StorageTrait, full::Storage},
+    io::{StorageTrait, full::Storage, tools::role::DEFAULT_ROLE},
     json::{input::CreateUser, output::Output},
 };
 
-pub fn create_user(v: CreateUser, s: Arc<Storage>) -> Result<Output, Error> {
+pub fn create_user(v: CreateUser, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::create_user")?;
     if !valid_name(&v.user_name) {
         Err(Error::SpaceNameValidationError {
             name: v.user_name,
@@ -15,6 +17,7 @@ pub fn create_user(v: CreateUser, s: Arc<Storage>) -> Result<Output, Error> {
             location: "command::addspace::addspace",
         })
     } else {
-        s.create_user(&v.user_name, &v.password)
+        let roles = v.roles.unwrap_or_else(|| vec![DEFAULT_ROLE.to_string()]);
+        s.create_user(&v.user_name, &v.password, &roles)
     }
 }
@@ -6,12 +6,12 @@ use crate::{
     json::{input::DeleteValue, output::Output},
 };
 
-pub fn delete_value(v: DeleteValue, s: Arc<Storage>) -> Result<Output, Error> {
+pub fn delete_value(v: DeleteValue, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
     let range = match range(v.range) {
         Ok(v) => v,
         Err(e) => {
             return Err(Error::RangeError { message: e });
         }
     };
-    s.delete_value(&v.space_name, &v.key_name, range)
+    s.delete_value(&v.space_name, &v.key_name, range, user)
 }
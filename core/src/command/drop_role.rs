@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::DropRole, output::Output},
+};
+
+pub fn drop_role(v: DropRole, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::drop_role")?;
+    s.drop_role(&v.role_name)
+}
@@ -1,18 +1,20 @@
 use std::sync::Arc;
 
 use crate::{
-    command::tools::valid_len::valid_len,
+    auth::Caller,
+    command::tools::{require_admin::require_admin, valid_len::valid_len},
     error::Error,
     io::{StorageTrait, full::Storage},
     json::{input::DropUser, output::Output},
 };
 
-pub fn drop_user(v: DropUser, s: Arc<Storage>) -> Result<Output, Error> {
+pub fn drop_user(v: DropUser, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::drop_user")?;
     if valid_len(&v.user_name) {
         s.drop_user(&v.user_name)
     } else {
-        return Err(Error::UserNotFound {
+        Err(Error::UserNotFound {
             user_name: v.user_name,
-        });
+        })
     }
 }
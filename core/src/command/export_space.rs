@@ -0,0 +1,17 @@
+use std::{io::Write, sync::Arc};
+
+use crate::{
+    command::export_values::export_error,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::input::ExportSpace,
+};
+
+// Key 定義と値を1行1レコードの NDJSON として out に書き出す
+pub fn export_space(v: ExportSpace, s: Arc<Storage>, mut out: impl Write) -> Result<(), Error> {
+    s.export_space(&v.space_name, &mut |record| {
+        serde_json::to_writer(&mut out, &record).map_err(export_error)?;
+        out.write_all(b"\n").map_err(export_error)
+    })?;
+    out.flush().map_err(export_error)
+}
@@ -0,0 +1,248 @@
+use std::{io::Write, sync::Arc};
+
+use kasane_logic::id::SpaceTimeId;
+
+use crate::{
+    error::Error,
+    io::{
+        StorageTrait, ValueEntry,
+        full::Storage,
+        tools::range::{bitmask_to_id, bitmask_to_stid, range},
+    },
+    json::input::{ExportFormat, ExportValues},
+};
+
+// 1行分のセル情報。値は key_names の順
+pub struct ExportRow<'a> {
+    pub stid: SpaceTimeId,
+    pub f: i32,
+    pub x: u32,
+    pub y: u32,
+    pub values: &'a [Option<ValueEntry>],
+}
+
+// value DB のカーソルから1セルずつ取り出して out に書き出す
+pub fn export_values(
+    v: ExportValues,
+    s: Arc<Storage>,
+    out: impl Write + Send,
+) -> Result<(), Error> {
+    let ids = match v.range.map(range).transpose() {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Error::RangeError { message: e });
+        }
+    };
+
+    // 書き出しを始める前に Key の存在を確かめる
+    for keyname in &v.key_names {
+        s.key_type(&v.space_name, keyname)?;
+    }
+
+    match v.format {
+        ExportFormat::Csv => export_csv(&v.space_name, &v.key_names, ids, s, out),
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => {
+            crate::command::tools::parquet::export_parquet(&v.space_name, &v.key_names, ids, s, out)
+        }
+        #[cfg(not(feature = "parquet"))]
+        ExportFormat::Parquet => Err(Error::FeatureNotEnabled {
+            feature: "parquet",
+            location: "command::export_values",
+        }),
+    }
+}
+
+// scan_values のビット列を出力用のセル情報に直して f に渡す
+pub fn scan_rows(
+    s: &Arc<Storage>,
+    spacename: &str,
+    keynames: &[String],
+    ids: Option<Vec<Vec<u8>>>,
+    mut f: impl FnMut(ExportRow) -> Result<(), Error>,
+) -> Result<(), Error> {
+    s.scan_values(spacename, keynames, ids, &mut |bits, values| {
+        let id = bitmask_to_id(bits);
+        let stid = bitmask_to_stid(bits)?;
+        f(ExportRow {
+            stid,
+            f: id.f,
+            x: id.x,
+            y: id.y,
+            values,
+        })
+    })
+}
+
+pub fn export_error(e: impl ToString) -> Error {
+    Error::ExportError {
+        message: e.to_string(),
+        location: "command::export_values",
+    }
+}
+
+fn export_csv(
+    spacename: &str,
+    keynames: &[String],
+    ids: Option<Vec<Vec<u8>>>,
+    s: Arc<Storage>,
+    out: impl Write,
+) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(out);
+
+    let mut header = vec!["id_string", "z", "f", "x", "y", "lat", "lng", "alt"];
+    header.extend(keynames.iter().map(String::as_str));
+    writer.write_record(&header).map_err(export_error)?;
+
+    scan_rows(&s, spacename, keynames, ids, |row| {
+        let center = row.stid.center();
+        let mut record = vec![
+            row.stid.to_string(),
+            row.stid.z().to_string(),
+            row.f.to_string(),
+            row.x.to_string(),
+            row.y.to_string(),
+            center.latitude.to_string(),
+            center.longitude.to_string(),
+            center.altitude.to_string(),
+        ];
+        record.extend(row.values.iter().map(|v| match v {
+            None => String::new(),
+            Some(v) => match v.to_plain_json() {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            },
+        }));
+        writer.write_record(&record).map_err(export_error)
+    })?;
+
+    writer.flush().map_err(export_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::import_csv::import_csv,
+        io::tools::{range::id_string_to_bitmask, temp_storage::TempStorage},
+        json::input::{ImportCsv, KeyMode, KeyType, OnConflict, Range},
+    };
+
+    fn id(s: &str) -> Vec<u8> {
+        id_string_to_bitmask(s).unwrap()
+    }
+
+    fn storage() -> TempStorage {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        for (key, keytype) in [("a", KeyType::INT), ("b", KeyType::TEXT)] {
+            s.create_key("s", key, keytype, KeyMode::UniqueKey, Default::default())
+                .unwrap();
+        }
+        let put = |key: &str, cells: &[&str], value: ValueEntry| {
+            let ids = cells.iter().map(|c| id(c)).collect();
+            s.insert_value("s", key, ids, value, None, "alice").unwrap();
+        };
+        put(
+            "a",
+            &["10/0/909/403_0/-", "10/0/897/406_0/-"],
+            ValueEntry::INT(5),
+        );
+        put(
+            "b",
+            &["10/0/909/403_0/-"],
+            ValueEntry::TEXT("x, \"y\"".to_string()),
+        );
+        put("b", &["10/1/0/0_0/-"], ValueEntry::TEXT("z".to_string()));
+        s
+    }
+
+    fn export(s: &TempStorage, keys: &[&str], range: Option<Range>) -> Result<String, Error> {
+        let v = ExportValues {
+            space_name: "s".to_string(),
+            key_names: keys.iter().map(|k| k.to_string()).collect(),
+            range,
+            format: ExportFormat::Csv,
+        };
+        let mut out = Vec::new();
+        export_values(v, s.shared(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn records(csv: &str) -> Vec<Vec<String>> {
+        csv::Reader::from_reader(csv.as_bytes())
+            .records()
+            .map(|r| r.unwrap().iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn csv_has_a_row_per_cell_and_empty_missing_values() {
+        let s = storage();
+        let csv = export(&s, &["a", "b"], None).unwrap();
+        assert!(csv.starts_with("id_string,z,f,x,y,lat,lng,alt,a,b\n"));
+
+        let rows = records(&csv);
+        let cells: Vec<(&str, &str, &str)> = rows
+            .iter()
+            .map(|r| (r[0].as_str(), r[8].as_str(), r[9].as_str()))
+            .collect();
+        assert_eq!(cells.len(), 3);
+        assert!(cells.contains(&("10/0/909/403_0/-", "5", "x, \"y\"")));
+        assert!(cells.contains(&("10/0/897/406_0/-", "5", "")));
+        assert!(cells.contains(&("10/1/0/0_0/-", "", "z")));
+    }
+
+    #[test]
+    fn range_limits_rows_and_missing_key_fails_before_writing() {
+        let s = storage();
+        let range = Range::IdSet(vec![serde_json::from_str(
+            r#"{"z":10,"f":{"Single":0},"x":{"Single":909},"y":{"Single":403},"i":0,"t":"Any"}"#,
+        )
+        .unwrap()]);
+        let rows = records(&export(&s, &["a"], Some(range)).unwrap());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][0], "10/0/909/403_0/-");
+
+        assert!(matches!(
+            export(&s, &["a", "nope"], None),
+            Err(Error::KeyNotFound { .. })
+        ));
+    }
+
+    // 書き出したセルの中心座標を ImportCsv で読み戻すと同じセルに入る
+    #[test]
+    fn exported_csv_imports_back_into_the_same_cells() {
+        let s = storage();
+        s.create_key(
+            "s",
+            "c",
+            KeyType::INT,
+            KeyMode::UniqueKey,
+            Default::default(),
+        )
+        .unwrap();
+        let csv = export(&s, &["a"], None).unwrap();
+
+        let v = ImportCsv {
+            space_name: "s".to_string(),
+            key_name: "c".to_string(),
+            data: csv,
+            lat_column: "lat".to_string(),
+            lng_column: "lng".to_string(),
+            alt_column: Some("alt".to_string()),
+            value_column: "a".to_string(),
+            zoom: 10,
+            on_conflict: OnConflict::Error,
+            batch_size: None,
+        };
+        import_csv(v, s.shared(), "alice").unwrap();
+
+        let a = export(&s, &["a"], None).unwrap();
+        let c = export(&s, &["c"], None).unwrap();
+        assert_eq!(
+            a.lines().skip(1).collect::<Vec<_>>(),
+            c.lines().skip(1).collect::<Vec<_>>()
+        );
+    }
+}
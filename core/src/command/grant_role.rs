@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::GrantRole, output::Output},
+};
+
+pub fn grant_role(v: GrantRole, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::grant_role")?;
+    s.grant_role(&v.user_name, &v.role_name)
+}
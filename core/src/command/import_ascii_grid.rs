@@ -0,0 +1,113 @@
+use std::{collections::HashMap, sync::Arc};
+
+use kasane_logic::id::coordinates::Point;
+
+use crate::{
+    command::tools::{ascii_grid::parse_ascii_grid, spot::spot_bitmask},
+    error::Error,
+    io::{ImportRow, StorageTrait, ValueEntry, full::Storage},
+    json::{
+        input::{ImportAsciiGrid, KeyType},
+        output::{ImportGrid, Output, RowError},
+    },
+};
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+pub fn import_ascii_grid(v: ImportAsciiGrid, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
+    let keytype = s.key_type(&v.space_name, &v.key_name)?;
+    if keytype != KeyType::FLOAT && keytype != KeyType::DOUBLE {
+        return Err(Error::TypeMismatchValue {
+            expected_type: "FLOAT|DOUBLE".to_string(),
+            received_type: format!("{:?}", keytype),
+            location: "command::import_ascii_grid",
+        });
+    }
+
+    let grid = parse_ascii_grid(&v.data).map_err(|message| Error::ParseError {
+        message,
+        location: "command::import_ascii_grid",
+    })?;
+
+    // 同じ空間IDに入るラスタセルの合計と個数。row は最初に入ったラスタの行番号
+    let mut cells: HashMap<Vec<u8>, (usize, f64, usize)> = HashMap::new();
+    let mut errors: Vec<RowError> = Vec::new();
+    let mut nodata = 0;
+    for row in 0..grid.nrows {
+        for col in 0..grid.ncols {
+            let value = grid.values[row * grid.ncols + col];
+            if grid.is_nodata(value) {
+                nodata += 1;
+                continue;
+            }
+
+            let (latitude, longitude) = grid.center(row, col);
+            let point = Point {
+                latitude,
+                longitude,
+                altitude: v.altitude,
+            };
+            match spot_bitmask(v.zoom, point) {
+                Ok(ids) => {
+                    for id in ids {
+                        let cell = cells.entry(id).or_insert((row + 1, 0.0, 0));
+                        cell.1 += value;
+                        cell.2 += 1;
+                    }
+                }
+                // 範囲外のセルは行ごとに最初の1件だけ報告する
+                Err(message) => {
+                    if errors.last().is_none_or(|e| e.row != row + 1) {
+                        errors.push(RowError {
+                            row: row + 1,
+                            message,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    let mut rows: Vec<ImportRow> = cells
+        .into_iter()
+        .map(|(id, (row, sum, count))| {
+            let avg = sum / count as f64;
+            let value = match keytype {
+                KeyType::FLOAT => ValueEntry::FLOAT(avg as f32),
+                _ => ValueEntry::DOUBLE(avg),
+            };
+            ImportRow {
+                row,
+                id,
+                value: Some(value),
+            }
+        })
+        .collect();
+    // ビット列順に書き込む
+    rows.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let ids_total = rows.len();
+    let report = s.import_rows(
+        &v.space_name,
+        &v.key_name,
+        rows,
+        v.on_conflict,
+        v.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+        user,
+    )?;
+
+    errors.extend(report.errors.into_iter().map(|(row, e)| RowError {
+        row,
+        message: e.to_string(),
+    }));
+    errors.sort_by_key(|e| e.row);
+
+    Ok(Output::ImportGrid(ImportGrid {
+        cells_total: grid.values.len(),
+        cells_nodata: nodata,
+        ids_total,
+        ids_imported: report.imported,
+        ids_skipped: report.skipped,
+        errors,
+    }))
+}
@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use kasane_logic::id::coordinates::Point;
+
+use crate::{
+    command::tools::{import_rows::import_output, spot::spot_bitmask},
+    error::Error,
+    io::{ImportRow, StorageTrait, ValueEntry, full::Storage, tools::convert::convert_value},
+    json::{
+        input::ImportCsv,
+        output::{Output, RowError},
+    },
+};
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+pub fn import_csv(v: ImportCsv, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
+    // 値の文字列は Key の型へ変換する
+    let keytype = s.key_type(&v.space_name, &v.key_name)?;
+
+    let mut reader = csv::Reader::from_reader(v.data.as_bytes());
+    let headers = reader.headers().map_err(|e| Error::ParseError {
+        message: e.to_string(),
+        location: "command::import_csv",
+    })?;
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim() == name)
+            .ok_or(Error::ParseError {
+                message: format!("Column '{}' not found", name),
+                location: "command::import_csv",
+            })
+    };
+    let lat = column(&v.lat_column)?;
+    let lng = column(&v.lng_column)?;
+    let alt = v.alt_column.as_deref().map(column).transpose()?;
+    let val = column(&v.value_column)?;
+
+    let mut rows = Vec::new();
+    let mut parse_errors = Vec::new();
+    let mut total = 0;
+    for (n, record) in reader.records().enumerate() {
+        let row = n + 1;
+        total += 1;
+        let parsed = record.map_err(|e| e.to_string()).and_then(|record| {
+            let number = |i: usize, name: &str| {
+                let field = record.get(i).unwrap_or("").trim();
+                field
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid {} '{}'", name, field))
+            };
+            let point = Point {
+                latitude: number(lat, "latitude")?,
+                longitude: number(lng, "longitude")?,
+                altitude: match alt {
+                    Some(i) => number(i, "altitude")?,
+                    None => 0.0,
+                },
+            };
+
+            let field = record.get(val).unwrap_or("");
+            let value = if field.is_empty() {
+                None
+            } else {
+                Some(
+                    convert_value(&ValueEntry::TEXT(field.to_string()), keytype)
+                        .ok_or_else(|| format!("Cannot convert '{}' to {:?}", field, keytype))?,
+                )
+            };
+
+            let ids = spot_bitmask(v.zoom, point)?;
+            Ok(ids
+                .into_iter()
+                .map(|id| ImportRow {
+                    row,
+                    id,
+                    value: value.clone(),
+                })
+                .collect::<Vec<_>>())
+        });
+
+        match parsed {
+            Ok(r) => rows.extend(r),
+            Err(message) => parse_errors.push(RowError { row, message }),
+        }
+    }
+
+    let report = s.import_rows(
+        &v.space_name,
+        &v.key_name,
+        rows,
+        v.on_conflict,
+        v.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+        user,
+    )?;
+
+    Ok(import_output(total, report, parse_errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::tools::temp_storage::TempStorage,
+        json::{
+            input::{KeyMode, KeyType, OnConflict},
+            output::ImportRows,
+        },
+    };
+
+    const DATA: &str = "\
+value, y ,x
+5,35.68,139.76
+7,34.69,135.50
+x,35.0,135.0
+3,95.0,135.0
+20,33.0,130.0
+";
+
+    fn storage() -> TempStorage {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        let option = serde_json::from_str(r#"{"constraint":{"min":0,"max":10}}"#).unwrap();
+        s.create_key("s", "a", KeyType::INT, KeyMode::UniqueKey, option)
+            .unwrap();
+        s
+    }
+
+    fn import(s: &TempStorage, data: &str, on_conflict: OnConflict) -> ImportRows {
+        let v = ImportCsv {
+            space_name: "s".to_string(),
+            key_name: "a".to_string(),
+            data: data.to_string(),
+            lat_column: "y".to_string(),
+            lng_column: "x".to_string(),
+            alt_column: None,
+            value_column: "value".to_string(),
+            zoom: 10,
+            on_conflict,
+            batch_size: Some(1),
+        };
+        match import_csv(v, s.shared(), "alice").unwrap() {
+            Output::ImportRows(r) => r,
+            _ => unreachable!(),
+        }
+    }
+
+    fn value_at(s: &TempStorage, lat: f64, lng: f64) -> Option<ValueEntry> {
+        let point = Point {
+            latitude: lat,
+            longitude: lng,
+            altitude: 0.0,
+        };
+        let ids = spot_bitmask(10, point).unwrap();
+        let values = s
+            .select_value("s", vec!["a".to_string()], ids.clone(), None)
+            .unwrap();
+        values.get(&ids[0]).map(|v| v[0].1.clone())
+    }
+
+    #[test]
+    fn mapped_columns_are_imported_and_bad_rows_reported() {
+        let s = storage();
+        let report = import(&s, DATA, OnConflict::Error);
+
+        assert_eq!(report.rows_total, 5);
+        assert_eq!(report.rows_imported, 2);
+        // 変換できない値・範囲外の緯度・制約違反は行番号付きで報告し、他の行は取り込む
+        let rows: Vec<usize> = report.errors.iter().map(|e| e.row).collect();
+        assert_eq!(rows, [3, 4, 5]);
+        assert!(report.errors[1].message.contains("Latitude"));
+        assert!(matches!(
+            value_at(&s, 35.68, 139.76),
+            Some(ValueEntry::INT(5))
+        ));
+        assert!(matches!(
+            value_at(&s, 34.69, 135.50),
+            Some(ValueEntry::INT(7))
+        ));
+        assert!(value_at(&s, 33.0, 130.0).is_none());
+    }
+
+    #[test]
+    fn existing_cells_follow_on_conflict() {
+        let s = storage();
+        import(&s, DATA, OnConflict::Error);
+        let again = "value,y,x\n1,35.68,139.76\n";
+
+        let report = import(&s, again, OnConflict::Error);
+        assert_eq!((report.rows_imported, report.errors.len()), (0, 1));
+        let report = import(&s, again, OnConflict::Skip);
+        assert_eq!((report.rows_imported, report.rows_skipped), (0, 1));
+        assert!(matches!(
+            value_at(&s, 35.68, 139.76),
+            Some(ValueEntry::INT(5))
+        ));
+
+        let report = import(&s, again, OnConflict::Overwrite);
+        assert_eq!(report.rows_imported, 1);
+        assert!(matches!(
+            value_at(&s, 35.68, 139.76),
+            Some(ValueEntry::INT(1))
+        ));
+    }
+
+    #[test]
+    fn missing_column_fails_before_writing() {
+        let s = storage();
+        let v = ImportCsv {
+            space_name: "s".to_string(),
+            key_name: "a".to_string(),
+            data: "lat,lng\n35,135\n".to_string(),
+            lat_column: "lat".to_string(),
+            lng_column: "lng".to_string(),
+            alt_column: None,
+            value_column: "value".to_string(),
+            zoom: 10,
+            on_conflict: OnConflict::Error,
+            batch_size: None,
+        };
+        assert!(matches!(
+            import_csv(v, s.shared(), "alice"),
+            Err(Error::ParseError { .. })
+        ));
+    }
+}
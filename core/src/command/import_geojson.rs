@@ -0,0 +1,127 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::Value;
+
+use crate::{
+    command::tools::rasterize::rasterize,
+    error::Error,
+    io::{ImportRow, StorageTrait, ValueEntry, full::Storage, tools::convert::convert_value},
+    json::{
+        input::{GeoJsonFeature, ImportGeoJson, KeyType, OnOverlap},
+        output::{ImportFeatures, Output, RowError},
+    },
+};
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+pub fn import_geojson(v: ImportGeoJson, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
+    let keytype = s.key_type(&v.space_name, &v.key_name)?;
+
+    let features = match v.data.get("features") {
+        Some(Value::Array(features)) => features,
+        _ => {
+            return Err(Error::ParseError {
+                message: "data must be a GeoJSON FeatureCollection".to_string(),
+                location: "command::import_geojson",
+            });
+        }
+    };
+
+    // セルごとに、どの行（rows の添字）が値を持つかを覚えておく
+    let mut rows: Vec<ImportRow> = Vec::new();
+    let mut owner: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut errors = Vec::new();
+    for (n, feature) in features.iter().enumerate() {
+        let row = n + 1;
+        let parsed = serde_json::from_value::<GeoJsonFeature>(feature.clone())
+            .map_err(|e| e.to_string())
+            .and_then(|feature| {
+                let value = property_value(&feature, &v.property, keytype)?;
+                let ids = rasterize(v.zoom, &feature.geometry, v.altitude_min, v.altitude_max)?;
+                Ok((value, ids))
+            });
+        let (value, ids) = match parsed {
+            Ok(r) => r,
+            Err(message) => {
+                errors.push(RowError { row, message });
+                continue;
+            }
+        };
+
+        if v.on_overlap == OnOverlap::Error
+            && let Some(i) = ids.iter().find_map(|id| owner.get(id))
+        {
+            errors.push(RowError {
+                row,
+                message: format!("Feature overlaps feature {}", rows[*i].row),
+            });
+            continue;
+        }
+
+        for id in ids {
+            match owner.get(&id) {
+                Some(&i) => {
+                    if v.on_overlap == OnOverlap::Last {
+                        rows[i].row = row;
+                        rows[i].value = value.clone();
+                    }
+                }
+                None => {
+                    owner.insert(id.clone(), rows.len());
+                    rows.push(ImportRow {
+                        row,
+                        id,
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    let report = s.import_rows(
+        &v.space_name,
+        &v.key_name,
+        rows,
+        v.on_conflict,
+        v.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+        user,
+    )?;
+
+    // 書き込み時のエラーはセル単位なので、Feature ごとに最初の1件だけ残す
+    errors.extend(report.errors.into_iter().map(|(row, e)| RowError {
+        row,
+        message: e.to_string(),
+    }));
+    errors.sort_by_key(|e| e.row);
+    errors.dedup_by_key(|e| e.row);
+
+    Ok(Output::ImportFeatures(ImportFeatures {
+        features_total: features.len(),
+        cells_imported: report.imported,
+        cells_skipped: report.skipped,
+        errors,
+    }))
+}
+
+// property が無い・null の Feature は Key の default を使う
+fn property_value(
+    feature: &GeoJsonFeature,
+    property: &str,
+    keytype: KeyType,
+) -> Result<Option<ValueEntry>, String> {
+    let value = match feature.properties.as_ref().and_then(|p| p.get(property)) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(value) => value,
+    };
+    if keytype == KeyType::JSON {
+        return Ok(Some(ValueEntry::JSON(value.clone())));
+    }
+
+    let text = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    convert_value(&ValueEntry::TEXT(text.clone()), keytype)
+        .map(Some)
+        .ok_or_else(|| format!("Cannot convert '{}' to {:?}", text, keytype))
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use crate::{
+    command::tools::valid_name::valid_name,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::ImportSpace, ndjson::SpaceRecord, output::Output},
+};
+
+pub fn import_space(v: ImportSpace, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
+    if !valid_name(&v.space_name) {
+        return Err(Error::SpaceNameValidationError {
+            name: v.space_name,
+            reason: "only a-z, A-Z, 0-9, - _ . @ + = allowed, max 256 characters",
+            location: "command::import_space::import_space",
+        });
+    }
+
+    // 空行は無視し、壊れた行は行番号付きで報告する
+    let mut records = Vec::new();
+    for (n, line) in v.data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: SpaceRecord = serde_json::from_str(line).map_err(|e| Error::ParseError {
+            message: format!("line {}: {}", n + 1, e),
+            location: "command::import_space",
+        })?;
+        if let SpaceRecord::Key { key_name, .. } = &record
+            && !valid_name(key_name)
+        {
+            return Err(Error::KeyNameValidationError {
+                name: key_name.clone(),
+                reason: "only a-z, A-Z, 0-9, - _ . @ + = allowed, max 256 characters",
+                location: "command::import_space::import_space",
+            });
+        }
+        records.push(record);
+    }
+
+    s.import_space(&v.space_name, records, v.on_conflict, user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::export_space::export_space,
+        io::{ValueEntry, tools::range::id_string_to_bitmask, tools::temp_storage::TempStorage},
+        json::input::{ExportSpace, KeyMode, KeyType, OnConflict},
+    };
+
+    fn export(s: &TempStorage, space: &str) -> String {
+        let mut out = Vec::new();
+        let v = ExportSpace {
+            space_name: space.to_string(),
+        };
+        export_space(v, s.shared(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn import(s: &TempStorage, data: &str, on_conflict: OnConflict) -> Result<Output, Error> {
+        let v = ImportSpace {
+            space_name: "dst".to_string(),
+            data: data.to_string(),
+            on_conflict,
+        };
+        import_space(v, s.shared(), "alice")
+    }
+
+    fn report(output: Output) -> (usize, usize, usize) {
+        match output {
+            Output::ImportSpace(r) => (r.keys_created, r.values_written, r.values_skipped),
+            _ => unreachable!(),
+        }
+    }
+
+    // 制約・履歴付きの Key と、期限付きの値を含む Space
+    fn source(s: &TempStorage) {
+        s.create_space("src", "alice").unwrap();
+        let option = serde_json::from_str(
+            r#"{"history":true,"pyramid":{"zooms":[1],"aggregations":["Count"]},"constraint":{"min":0,"max":10}}"#,
+        )
+        .unwrap();
+        s.create_key("src", "a", KeyType::INT, KeyMode::UniqueKey, option)
+            .unwrap();
+        s.create_key(
+            "src",
+            "b",
+            KeyType::TEXT,
+            KeyMode::UniqueKey,
+            Default::default(),
+        )
+        .unwrap();
+        let id = |c: &str| id_string_to_bitmask(c).unwrap();
+        s.insert_value(
+            "src",
+            "a",
+            vec![id("3/5/2/7_0/-")],
+            ValueEntry::INT(3),
+            None,
+            "alice",
+        )
+        .unwrap();
+        s.insert_value(
+            "src",
+            "a",
+            vec![id("3/5/2/6_0/-")],
+            ValueEntry::INT(4),
+            Some(u64::MAX),
+            "alice",
+        )
+        .unwrap();
+        s.insert_value(
+            "src",
+            "b",
+            vec![id("2/0/1/1_0/-")],
+            ValueEntry::TEXT("x".to_string()),
+            None,
+            "alice",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn export_then_import_reproduces_the_space() {
+        let s = TempStorage::default();
+        source(&s);
+        let data = export(&s, "src");
+        assert_eq!(data.lines().count(), 5);
+
+        let output = import(&s, &data, OnConflict::Error).unwrap();
+        assert_eq!(report(output), (2, 3, 0));
+        assert_eq!(export(&s, "dst"), data);
+    }
+
+    #[test]
+    fn conflicts_follow_on_conflict() {
+        let s = TempStorage::default();
+        source(&s);
+        let data = export(&s, "src");
+        import(&s, &data, OnConflict::Error).unwrap();
+
+        assert!(matches!(
+            import(&s, &data, OnConflict::Error),
+            Err(Error::KeyAlreadyExists { .. })
+        ));
+        assert_eq!(
+            report(import(&s, &data, OnConflict::Skip).unwrap()),
+            (0, 0, 3)
+        );
+        assert_eq!(
+            report(import(&s, &data, OnConflict::Overwrite).unwrap()),
+            (0, 3, 0)
+        );
+        assert_eq!(export(&s, "dst"), data);
+    }
+
+    #[test]
+    fn broken_lines_are_reported_with_their_number() {
+        let s = TempStorage::default();
+        let data = "\n{\"type\":\"key\"}\n";
+        match import(&s, data, OnConflict::Error) {
+            Err(Error::ParseError { message, .. }) => assert!(message.starts_with("line 2:")),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        // 何も書かれていない
+        assert!(s.show_spaces().unwrap().is_empty());
+    }
+}
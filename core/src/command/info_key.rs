@@ -4,10 +4,7 @@ use crate::{
     command::tools::valid_len::valid_len,
     error::Error,
     io::{StorageTrait, full::Storage},
-    json::{
-        input::{InfoKey, InfoSpace, InfoUser},
-        output::Output,
-    },
+    json::{input::InfoKey, output::Output},
 };
 
 pub fn info_key(v: InfoKey, s: Arc<Storage>) -> Result<Output, Error> {
@@ -15,15 +12,15 @@ pub fn info_key(v: InfoKey, s: Arc<Storage>) -> Result<Output, Error> {
         if valid_len(&v.key_name) {
             s.info_key(&v.space_name, &v.key_name)
         } else {
-            return Err(Error::KeyNotFound {
+            Err(Error::KeyNotFound {
                 key_name: v.key_name,
                 space_name: v.space_name,
                 location: "command::info_key",
-            });
+            })
         }
     } else {
-        return Err(Error::SpaceNotFound {
+        Err(Error::SpaceNotFound {
             space_name: v.space_name,
-        });
+        })
     }
 }
@@ -11,8 +11,8 @@ pub fn info_user(v: InfoUser, s: Arc<Storage>) -> Result<Output, Error> {
     if valid_len(&v.user_name) {
         s.info_user(&v.user_name)
     } else {
-        return Err(Error::UserNotFound {
+        Err(Error::UserNotFound {
             user_name: v.user_name,
-        });
+        })
     }
 }
@@ -0,0 +1,15 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::KillSession, output::Output},
+};
+
+// session_id は ShowSessions が返す ID
+pub fn kill_session(v: KillSession, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::kill_session")?;
+    s.kill_session(&v.session_id)
+}
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{
+        input::KillUserSessions,
+        output::{self, Output},
+    },
+};
+
+pub fn kill_user_sessions(
+    v: KillUserSessions,
+    s: Arc<Storage>,
+    caller: &Caller,
+) -> Result<Output, Error> {
+    require_admin(caller, "command::kill_user_sessions")?;
+    let sessions_killed = s.kill_user_sessions(&v.user_name)?;
+    Ok(Output::KillUserSessions(output::KillUserSessions {
+        user_name: v.user_name,
+        sessions_killed,
+    }))
+}
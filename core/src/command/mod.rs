@@ -1,91 +1,231 @@
 use std::sync::Arc;
-#[cfg(feature = "wasm")]
-use std::sync::Arc;
-#[cfg(feature = "full")]
-use std::sync::{Arc, Mutex};
 
-use rand::prelude;
+use log::error;
 
+use crate::command::alter_key_type::alter_key_type;
+use crate::command::change_password::change_password;
+use crate::command::clone_space::clone_space;
+use crate::command::copy_key::copy_key;
 use crate::command::create_key::create_key;
+use crate::command::create_role::create_role;
 use crate::command::create_space::create_space;
+use crate::command::create_token::create_token;
 use crate::command::create_user::create_user;
 use crate::command::delete_value::delete_value;
 use crate::command::drop_key::drop_key;
+use crate::command::drop_role::drop_role;
 use crate::command::drop_space::drop_space;
 use crate::command::drop_user::drop_user;
+use crate::command::grant_role::grant_role;
+use crate::command::import_ascii_grid::import_ascii_grid;
+use crate::command::import_csv::import_csv;
+use crate::command::import_geojson::import_geojson;
+use crate::command::import_space::import_space;
 use crate::command::info_key::info_key;
 use crate::command::info_space::info_space;
 use crate::command::info_user::info_user;
 use crate::command::insert_value::insert_value;
+use crate::command::kill_session::kill_session;
+use crate::command::kill_user_sessions::kill_user_sessions;
 use crate::command::patch_value::patch_value;
+use crate::command::rename_key::rename_key;
+use crate::command::rename_space::rename_space;
+use crate::command::reset_password::reset_password;
+use crate::command::revoke_role::revoke_role;
+use crate::command::revoke_token::revoke_token;
+use crate::command::select_pyramid::select_pyramid;
 use crate::command::select_value::select_value;
+use crate::command::share_space::share_space;
+use crate::command::show_audit::show_audit;
 use crate::command::show_keys::show_keys;
+use crate::command::show_roles::show_roles;
+use crate::command::show_sessions::show_sessions;
 use crate::command::show_spaces::show_spaces;
+use crate::command::show_tokens::show_tokens;
 use crate::command::show_user::show_users;
-//use crate::command::update_value::update_value;
+use crate::command::unshare_space::unshare_space;
+use crate::command::update_value::update_value;
 
+use crate::auth::Caller;
 use crate::command::show_values::show_values;
-use crate::io::full::Storage;
+use crate::command::tools::audit::{audit_entry, audit_result, is_mutating};
+use crate::command::tools::privilege::{check_privileges, required_privileges, resolve_roles};
+use crate::command::unlock_user::unlock_user;
+use crate::command::value_history::value_history;
+use crate::io::{AuditEntry, StorageTrait, full::Storage};
 use crate::{
     command::version::version,
     error::Error,
     json::{input::Command, output::Output},
 };
+pub mod alter_key_type;
+pub mod change_password;
+pub mod clone_space;
+pub mod copy_key;
 pub mod create_key;
+pub mod create_role;
 pub mod create_space;
+pub mod create_token;
 pub mod create_user;
 pub mod delete_value;
 pub mod drop_key;
+pub mod drop_role;
 pub mod drop_space;
 pub mod drop_user;
+pub mod export_space;
+pub mod export_values;
+pub mod grant_role;
+pub mod import_ascii_grid;
+pub mod import_csv;
+pub mod import_geojson;
+pub mod import_space;
 pub mod info_key;
 pub mod info_space;
 pub mod info_user;
 pub mod insert_value;
+pub mod kill_session;
+pub mod kill_user_sessions;
 pub mod patch_value;
+pub mod rename_key;
+pub mod rename_space;
+pub mod reset_password;
+pub mod revoke_role;
+pub mod revoke_token;
+pub mod select_pyramid;
+pub mod select_tile;
 pub mod select_value;
+pub mod share_space;
+pub mod show_audit;
 pub mod show_keys;
+pub mod show_roles;
+pub mod show_sessions;
 pub mod show_spaces;
+pub mod show_tokens;
 pub mod show_user;
 pub mod show_values;
 pub mod tools;
 pub mod triangle;
-//pub mod update_value;
+pub mod unlock_user;
+pub mod unshare_space;
+pub mod update_value;
+pub mod value_history;
 pub mod version;
 
 //関数のディスパッチ関数
 //関数の命令内容とストレージの参照権を関数に入力し、操作を行わせる
-pub fn process(cmd: Command, s: Arc<Storage>) -> Result<Output, Error> {
+//caller はセッション・API トークン・JWT から解決された実行者
+//書き込み系のコマンドは拒否されたものも含めて結果と一緒に audit DB に残す
+//実行前に audit DB へ書けなければコマンドは実行しない
+pub fn process(cmd: Command, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    let audit = match is_mutating(&cmd) {
+        true => {
+            let entry = audit_entry(&cmd, caller);
+            Some((s.append_audit(&entry)?, entry))
+        }
+        false => None,
+    };
+    let result = dispatch(cmd, s.clone(), caller);
+    if let Some((key, entry)) = audit {
+        let entry = AuditEntry {
+            result: audit_result(&result),
+            ..entry
+        };
+        // 実行済みのコマンドは失敗にできないので、結果を書けなければ result 無しのまま残る
+        if let Err(e) = s.finish_audit(&key, &entry) {
+            error!("Failed to write audit result {}: {}", entry.request_id, e);
+        }
+    }
+    result
+}
+
+fn dispatch(cmd: Command, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    let user = caller.user.as_str();
+    // パスワード変更が必要なユーザーは ChangePassword 以外を実行できない
+    // JWT のユーザーは user DB に無いことがある
+    let must_change_password = match s.must_change_password(user) {
+        Ok(v) => v,
+        Err(Error::UserNotFound { .. }) => false,
+        Err(e) => return Err(e),
+    };
+    if !matches!(cmd, Command::ChangePassword(_)) && must_change_password {
+        return Err(Error::PasswordChangeRequired {
+            user_name: user.to_string(),
+        });
+    }
+
+    // 付与されたロールの権限の和で実行できるか確認する
+    let caller = &resolve_roles(&s, caller)?;
+    check_privileges(&s, caller, &required_privileges(&cmd), "command::process")?;
+
     match cmd {
         //データベース操作系
-        Command::CreateSpace(v) => create_space(v, s),
+        Command::CreateSpace(v) => create_space(v, s, user),
         Command::DropSpace(v) => drop_space(v, s),
-        Command::ShowSpaces => show_spaces(s),
+        Command::RenameSpace(v) => rename_space(v, s),
+        Command::CloneSpace(v) => clone_space(v, s, user),
+        // 大きな Space を1つの応答に載せないよう、POST /export/space でストリームする
+        Command::ExportSpace(_) => Err(Error::ExportError {
+            message: "ExportSpace is streamed from POST /export/space".to_string(),
+            location: "command::dispatch",
+        }),
+        Command::ImportSpace(v) => import_space(v, s, user),
+        Command::ShowSpaces => show_spaces(s, caller),
         Command::InfoSpace(v) => info_space(v, s),
+        Command::ShareSpace(v) => share_space(v, s, caller),
+        Command::UnshareSpace(v) => unshare_space(v, s, caller),
         Command::Version => version(),
 
         //Key操作系
         Command::CreateKey(v) => create_key(v, s),
         Command::DropKey(v) => drop_key(v, s),
+        Command::RenameKey(v) => rename_key(v, s),
+        Command::AlterKeyType(v) => alter_key_type(v, s, user),
+        Command::CopyKey(v) => copy_key(v, s, user),
         Command::ShowKeys(v) => show_keys(v, s),
         Command::InfoKey(v) => info_key(v, s),
 
         //Value操作系
-        Command::InsertValue(v) => insert_value(v, s),
-        Command::PatchValue(v) => patch_value(v, s),
-        //Command::UpdateValue(v) => update_value(v, s),
-        Command::DeleteValue(v) => delete_value(v, s),
+        Command::InsertValue(v) => insert_value(v, s, user),
+        Command::PatchValue(v) => patch_value(v, s, user),
+        Command::UpdateValue(v) => update_value(v, s, user),
+        Command::DeleteValue(v) => delete_value(v, s, user),
         Command::SelectValue(v) => select_value(v, s),
         Command::ShowValues(v) => show_values(v, s),
+        Command::SelectPyramid(v) => select_pyramid(v, s),
+        Command::ImportCsv(v) => import_csv(v, s, user),
+        Command::ImportGeoJson(v) => import_geojson(v, s, user),
+        Command::ImportAsciiGrid(v) => import_ascii_grid(v, s, user),
+        Command::ValueHistory(v) => value_history(v, s),
 
         //ツール系
         //Command::Transaction(v) => todo!(),
 
         //ユーザー操作系
-        Command::CreateUser(v) => create_user(v, s),
-        Command::DropUser(v) => drop_user(v, s),
+        Command::CreateUser(v) => create_user(v, s, caller),
+        Command::DropUser(v) => drop_user(v, s, caller),
+        Command::ChangePassword(v) => change_password(v, s, caller),
+        Command::ResetPassword(v) => reset_password(v, s, caller),
+        Command::UnlockUser(v) => unlock_user(v, s, caller),
         Command::InfoUser(v) => info_user(v, s),
         Command::ShowUsers => show_users(s),
+
+        //セッション・トークン操作系
+        Command::ShowSessions => show_sessions(s, caller),
+        Command::KillSession(v) => kill_session(v, s, caller),
+        Command::KillUserSessions(v) => kill_user_sessions(v, s, caller),
+        Command::CreateToken(v) => create_token(v, s, caller),
+        Command::ShowTokens => show_tokens(s, caller),
+        Command::RevokeToken(v) => revoke_token(v, s, caller),
+
+        //ロール操作系
+        Command::CreateRole(v) => create_role(v, s, caller),
+        Command::DropRole(v) => drop_role(v, s, caller),
+        Command::GrantRole(v) => grant_role(v, s, caller),
+        Command::RevokeRole(v) => revoke_role(v, s, caller),
+        Command::ShowRoles => show_roles(s),
+
+        //監査系
+        Command::ShowAudit(v) => show_audit(v, s, caller),
         //権限付与系
         // Command::GrantDatabase(v) => todo!(),
         // Command::GrantSpacePrivilege(v) => todo!(),
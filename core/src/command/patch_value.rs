@@ -1,17 +1,19 @@
 use std::sync::Arc;
 
 use crate::{
+    command::tools::expires_at::expires_at,
     error::Error,
     io::{StorageTrait, full::Storage, tools::range::range},
     json::{input::PatchValue, output::Output},
 };
 
-pub fn patch_value(v: PatchValue, s: Arc<Storage>) -> Result<Output, Error> {
+pub fn patch_value(v: PatchValue, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
     let range = match range(v.range) {
         Ok(v) => v,
         Err(e) => {
             return Err(Error::RangeError { message: e });
         }
     };
-    s.patch_value(&v.space_name, &v.key_name, range, v.value)
+    let expires = expires_at(v.ttl_secs, v.expires_at);
+    s.patch_value(&v.space_name, &v.key_name, range, v.value, expires, user)
 }
@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use crate::{
+    command::tools::valid_name::valid_name,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::RenameKey, output::Output},
+};
+
+pub fn rename_key(v: RenameKey, s: Arc<Storage>) -> Result<Output, Error> {
+    if !valid_name(&v.new_key_name) {
+        Err(Error::KeyNameValidationError {
+            name: v.new_key_name,
+            reason: "only a-z, A-Z, 0-9, - _ . @ + = allowed, max 256 characters",
+            location: "command::rename_key::rename_key",
+        })
+    } else {
+        s.rename_key(&v.space_name, &v.key_name, &v.new_key_name)
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use crate::{
+    command::tools::valid_name::valid_name,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::RenameSpace, output::Output},
+};
+
+pub fn rename_space(v: RenameSpace, s: Arc<Storage>) -> Result<Output, Error> {
+    if !valid_name(&v.new_space_name) {
+        Err(Error::SpaceNameValidationError {
+            name: v.new_space_name,
+            reason: "only a-z, A-Z, 0-9, - _ . @ + = allowed, max 256 characters",
+            location: "command::rename_space::rename_space",
+        })
+    } else {
+        s.rename_space(&v.space_name, &v.new_space_name)
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::{require_admin::require_admin, valid_password::valid_password},
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::ResetPassword, output::Output},
+};
+
+// 対象ユーザーのセッション・API トークンはすべて失効する
+pub fn reset_password(v: ResetPassword, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::reset_password")?;
+    valid_password(&v.new_password).map_err(|reason| Error::PasswordValidationError {
+        reason,
+        location: "command::reset_password",
+    })?;
+    s.reset_password(&v.user_name, &v.new_password, v.must_change_password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tools::temp_storage::TempStorage;
+
+    fn reset(user: &str, must_change_password: bool) -> ResetPassword {
+        ResetPassword {
+            user_name: user.to_string(),
+            new_password: "reset-password".to_string(),
+            must_change_password,
+        }
+    }
+
+    #[test]
+    fn admin_resets_and_revokes_sessions() {
+        let s = TempStorage::default();
+        s.create_user("bob", "old-password", &[]).unwrap();
+        let session = s.create_session("bob").unwrap();
+
+        assert!(matches!(
+            reset_password(reset("bob", true), s.shared(), &Caller::new("bob")),
+            Err(Error::PermissionDenied { .. })
+        ));
+
+        let admin = Caller {
+            roles: Some(vec!["admin".to_string()]),
+            ..Caller::new("alice")
+        };
+        reset_password(reset("bob", true), s.shared(), &admin).unwrap();
+        assert!(s.verify_user("bob", "reset-password").unwrap());
+        assert!(s.must_change_password("bob").unwrap());
+        assert_eq!(s.session_user(&session).unwrap(), None);
+
+        reset_password(reset("bob", false), s.shared(), &admin).unwrap();
+        assert!(!s.must_change_password("bob").unwrap());
+        assert!(matches!(
+            reset_password(reset("nobody", false), s.shared(), &admin),
+            Err(Error::UserNotFound { .. })
+        ));
+    }
+}
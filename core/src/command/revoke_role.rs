@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::RevokeRole, output::Output},
+};
+
+pub fn revoke_role(v: RevokeRole, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::revoke_role")?;
+    s.revoke_role(&v.user_name, &v.role_name)
+}
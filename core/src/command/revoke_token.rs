@@ -0,0 +1,18 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::RevokeToken, output::Output},
+};
+
+// 他のユーザーのトークンを失効させられるのは admin だけ
+pub fn revoke_token(v: RevokeToken, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    let token = s.get_token(&v.token_id)?;
+    if token.user_name != caller.user {
+        require_admin(caller, "command::revoke_token")?;
+    }
+    s.revoke_token(&v.token_id)
+}
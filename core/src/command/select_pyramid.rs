@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    io::{
+        StorageTrait,
+        full::Storage,
+        tools::range::{bitmask_to_stid, range},
+    },
+    json::{
+        input::{Aggregation, SelectPyramid},
+        output::{Output, PyramidValue},
+    },
+};
+
+pub fn select_pyramid(v: SelectPyramid, s: Arc<Storage>) -> Result<Output, Error> {
+    let range = match range(v.range) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Error::RangeError { message: e });
+        }
+    };
+    let aggregations = match s.info_key(&v.space_name, &v.key_name)? {
+        Output::InfoKey(info) => info.pyramid.map(|p| p.aggregations).unwrap_or_default(),
+        _ => vec![],
+    };
+    let a = s.select_pyramid(&v.space_name, &v.key_name, v.zoom, range)?;
+
+    let mut result = vec![];
+
+    for (bits, cell) in a {
+        let stid = bitmask_to_stid(&bits)?;
+
+        result.push(PyramidValue {
+            id: stid,
+            center: stid.center(),
+            id_string: stid.to_string(),
+            count: aggregations
+                .contains(&Aggregation::Count)
+                .then_some(cell.count),
+            sum: aggregations.contains(&Aggregation::Sum).then_some(cell.sum),
+            avg: aggregations
+                .contains(&Aggregation::Avg)
+                .then_some(cell.sum / cell.count as f64),
+        });
+    }
+
+    Ok(Output::SelectPyramid(result))
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    io::{
+        StorageTrait,
+        full::Storage,
+        tools::range::{bitmask_to_id, bitmask_to_id_string},
+    },
+    json::{
+        input::SelectTile,
+        output::mvt::{EXTENT, MvtFeature, MvtValue, encode_tile},
+    },
+};
+
+// XYZ タイルに含まれるセルを MVT (レイヤー名は Key 名) にエンコードする
+pub fn select_tile(v: SelectTile, s: Arc<Storage>) -> Result<Vec<u8>, Error> {
+    if v.z > 31 || v.x >= 1 << v.z || v.y >= 1 << v.z {
+        return Err(Error::RangeError {
+            message: format!("Invalid tile {}/{}/{}", v.z, v.x, v.y),
+        });
+    }
+
+    let cells = s.select_tile(&v.space_name, &v.key_name, v.z, v.x, v.y)?;
+    let floor_min = v.floor_min.unwrap_or(i32::MIN);
+    let floor_max = v.floor_max.unwrap_or(i32::MAX);
+
+    let mut features = Vec::new();
+    for (bits, value) in cells {
+        let id = bitmask_to_id(&bits);
+
+        // セルの f をタイルのズームレベルでの範囲に直して比較する
+        let (f0, f1, rect) = if id.z >= v.z {
+            let d = (id.z - v.z) as u32;
+            let n = 1u64 << d;
+            let lx = (id.x - (v.x << d)) as u64;
+            let ly = (id.y - (v.y << d)) as u64;
+            let extent = EXTENT as u64;
+            // ピクセルより小さいセルも最低1単位の幅で描く
+            let x0 = (lx * extent / n) as u32;
+            let y0 = (ly * extent / n) as u32;
+            let x1 = (((lx + 1) * extent / n) as u32).max(x0 + 1);
+            let y1 = (((ly + 1) * extent / n) as u32).max(y0 + 1);
+            let f = id.f >> d;
+            (f as i64, f as i64, [x0, y0, x1, y1])
+        } else {
+            let d = (v.z - id.z) as u32;
+            let f0 = (id.f as i64) << d;
+            let f1 = ((id.f as i64 + 1) << d) - 1;
+            (f0, f1, [0, 0, EXTENT, EXTENT])
+        };
+        if f1 < floor_min as i64 || f0 > floor_max as i64 {
+            continue;
+        }
+
+        let value = match value.to_plain_json() {
+            serde_json::Value::String(s) => MvtValue::String(s),
+            serde_json::Value::Bool(b) => MvtValue::Bool(b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => MvtValue::Int(i),
+                None => MvtValue::Double(n.as_f64().unwrap_or_default()),
+            },
+            other => MvtValue::String(other.to_string()),
+        };
+
+        features.push(MvtFeature {
+            id: features.len() as u64 + 1,
+            rect,
+            properties: vec![
+                (
+                    "id_string".to_string(),
+                    MvtValue::String(bitmask_to_id_string(&bits)?),
+                ),
+                ("floor".to_string(), MvtValue::Int(id.f as i64)),
+                (v.key_name.clone(), value),
+            ],
+        });
+    }
+
+    Ok(encode_tile(&v.key_name, &features))
+}
@@ -1,18 +1,10 @@
 use std::sync::Arc;
 
-use kasane_logic::id::{DimensionRange, SpaceTimeId};
-
 use crate::{
+    command::tools::value_output::value_output,
     error::Error,
-    io::{
-        StorageTrait,
-        full::Storage,
-        tools::range::{bitmask_to_id, range},
-    },
-    json::{
-        input::SelectValue,
-        output::{Output, Value},
-    },
+    io::{StorageTrait, full::Storage, tools::range::range},
+    json::{input::SelectValue, output::Output},
 };
 
 pub fn select_value(v: SelectValue, s: Arc<Storage>) -> Result<Output, Error> {
@@ -22,31 +14,7 @@ pub fn select_value(v: SelectValue, s: Arc<Storage>) -> Result<Output, Error> {
             return Err(Error::RangeError { message: e });
         }
     };
-    let a = s.select_value(&v.space_name, v.key_names, range)?;
-
-    let mut result = vec![];
-
-    for ele in a {
-        let id = bitmask_to_id(&ele.0);
-
-        let stid = SpaceTimeId::new(
-            id.z,
-            DimensionRange::Single(id.f),
-            DimensionRange::Single(id.x),
-            DimensionRange::Single(id.y),
-            id.i,
-            DimensionRange::Any,
-        )
-        .unwrap();
-
-        result.push(Value {
-            id: stid,
-            center: stid.center(),
-            vertex: stid.vertex(),
-            id_string: stid.to_string(),
-            value: ele.1,
-        });
-    }
+    let a = s.select_value(&v.space_name, v.key_names, range, v.as_of)?;
 
-    return Ok(Output::SelectValue(result));
+    value_output(a, v.format, v.color_ramp.as_ref(), Output::SelectValue)
 }
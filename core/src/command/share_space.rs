@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_owner::require_owner,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{
+        input::{ShareSpace, SpaceShare},
+        output::Output,
+    },
+};
+
+pub fn share_space(v: ShareSpace, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_owner(&s, caller, &v.space_name, "command::share_space")?;
+    if v.user_name.is_some() == v.role_name.is_some() {
+        return Err(Error::ParseError {
+            message: "Specify either user_name or role_name".to_string(),
+            location: "command::share_space",
+        });
+    }
+    s.share_space(
+        &v.space_name,
+        SpaceShare {
+            user_name: v.user_name,
+            role_name: v.role_name,
+            level: v.level,
+        },
+    )
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{
+        input::ShowAudit,
+        output::{self, Output},
+    },
+};
+
+const DEFAULT_LIMIT: usize = 1000;
+
+pub fn show_audit(v: ShowAudit, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::show_audit")?;
+    let entries = s.show_audit(
+        v.user_name.as_deref(),
+        v.space_name.as_deref(),
+        v.from,
+        v.to,
+        v.limit.unwrap_or(DEFAULT_LIMIT),
+    )?;
+    Ok(Output::ShowAudit(output::ShowAudit { entries }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{AuditEntry, tools::temp_storage::TempStorage};
+
+    fn entry(timestamp: u64, user: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            user_name: user.to_string(),
+            request_id: timestamp.to_string(),
+            space_names: Vec::new(),
+            command: serde_json::Value::Null,
+            result: Some(Ok(())),
+        }
+    }
+
+    fn timestamps(s: &TempStorage, json: &str) -> Vec<u64> {
+        let admin = Caller {
+            roles: Some(vec!["admin".to_string()]),
+            ..Caller::new("admin")
+        };
+        let v: ShowAudit = serde_json::from_str(json).unwrap();
+        match show_audit(v, s.shared(), &admin).unwrap() {
+            Output::ShowAudit(audit) => audit.entries.iter().map(|e| e.timestamp).collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn lists_newest_first_with_and_without_from() {
+        let s = TempStorage::default();
+        for (ts, user) in [(10, "a"), (20, "b"), (30, "a"), (40, "b"), (50, "a")] {
+            s.append_audit(&entry(ts, user)).unwrap();
+        }
+
+        assert_eq!(timestamps(&s, "{}"), [50, 40, 30, 20, 10]);
+        // limit は範囲内の新しいものを残す
+        assert_eq!(timestamps(&s, r#"{"limit":2}"#), [50, 40]);
+        assert_eq!(timestamps(&s, r#"{"from":20,"limit":2}"#), [50, 40]);
+        assert_eq!(timestamps(&s, r#"{"from":20,"to":40}"#), [30, 20]);
+        assert_eq!(timestamps(&s, r#"{"to":30,"limit":5}"#), [20, 10]);
+        assert_eq!(
+            timestamps(&s, r#"{"from":15,"user_name":"a","limit":1}"#),
+            [50]
+        );
+        assert!(timestamps(&s, r#"{"from":60}"#).is_empty());
+    }
+
+    #[test]
+    fn result_is_filled_in_after_the_command() {
+        let s = TempStorage::default();
+        let key = s
+            .append_audit(&AuditEntry {
+                result: None,
+                ..entry(10, "a")
+            })
+            .unwrap();
+        s.finish_audit(&key, &entry(10, "a")).unwrap();
+
+        let entries = s.show_audit(None, None, None, None, 10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].result, Some(Ok(())));
+    }
+}
@@ -0,0 +1,11 @@
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::output::Output,
+};
+
+pub fn show_roles(s: Arc<Storage>) -> Result<Output, Error> {
+    s.show_roles()
+}
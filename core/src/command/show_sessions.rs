@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::output::{Output, SessionInfo, ShowSessions},
+};
+
+pub fn show_sessions(s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_admin(caller, "command::show_sessions")?;
+    let sessions = s
+        .show_sessions()?
+        .into_iter()
+        .map(|session| SessionInfo {
+            session_id: session.id,
+            user_name: session.user_name,
+            created_at: session.created_at,
+            last_access: session.last_access,
+        })
+        .collect();
+    Ok(Output::ShowSessions(ShowSessions { sessions }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::{kill_session::kill_session, kill_user_sessions::kill_user_sessions},
+        io::tools::temp_storage::TempStorage,
+        json::input::{KillSession, KillUserSessions},
+    };
+
+    fn session_ids(s: &TempStorage, caller: &Caller) -> Vec<(String, String)> {
+        match show_sessions(s.shared(), caller).unwrap() {
+            Output::ShowSessions(v) => v
+                .sessions
+                .into_iter()
+                .map(|session| (session.user_name, session.session_id))
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn admin_lists_and_kills_sessions() {
+        let s = TempStorage::default();
+        let admin = Caller {
+            roles: Some(vec!["admin".to_string()]),
+            ..Caller::new("alice")
+        };
+        let bob = Caller::new("bob");
+        let tokens = [
+            s.create_session("bob").unwrap(),
+            s.create_session("bob").unwrap(),
+            s.create_session("carol").unwrap(),
+        ];
+
+        assert!(matches!(
+            show_sessions(s.shared(), &bob),
+            Err(Error::PermissionDenied { .. })
+        ));
+        let sessions = session_ids(&s, &admin);
+        assert_eq!(sessions.len(), 3);
+        // 一覧にはトークンそのものを出さない
+        assert!(sessions.iter().all(|(_, id)| !tokens.contains(id)));
+
+        let carol = sessions.iter().find(|(user, _)| user == "carol").unwrap();
+        let kill = |session_id: &str| KillSession {
+            session_id: session_id.to_string(),
+        };
+        assert!(kill_session(kill(&carol.1), s.shared(), &bob).is_err());
+        kill_session(kill(&carol.1), s.shared(), &admin).unwrap();
+        assert_eq!(s.session_user(&tokens[2]).unwrap(), None);
+
+        let v = KillUserSessions {
+            user_name: "bob".to_string(),
+        };
+        match kill_user_sessions(v, s.shared(), &admin).unwrap() {
+            Output::KillUserSessions(v) => assert_eq!(v.sessions_killed, 2),
+            _ => unreachable!(),
+        }
+        assert!(session_ids(&s, &admin).is_empty());
+    }
+}
@@ -1,11 +1,32 @@
 use std::sync::Arc;
 
 use crate::{
+    auth::Caller,
+    command::tools::privilege::space_visible,
     error::Error,
     io::{StorageTrait, full::Storage},
-    json::output::Output,
+    json::{
+        input::CommandCategory,
+        output::{Output, ShowSpaces},
+    },
 };
 
-pub fn show_spaces(s: Arc<Storage>) -> Result<Output, Error> {
-    s.show_spaces()
+// ロール・所有者・共有先として見える Space だけを返す
+pub fn show_spaces(s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    let privileges = match caller.is_admin() {
+        true => None,
+        false => Some(s.role_privileges(caller.roles.as_deref().unwrap_or_default())?),
+    };
+    let spacenames = s
+        .show_spaces()?
+        .into_iter()
+        .filter(|(name, acl)| {
+            caller.allows_space(CommandCategory::Database, name)
+                && privileges
+                    .as_ref()
+                    .is_none_or(|p| space_visible(p, caller, name, acl))
+        })
+        .map(|(name, _)| name)
+        .collect();
+    Ok(Output::ShowSpaces(ShowSpaces { spacenames }))
 }
@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::output::{Output, ShowTokens, TokenInfo},
+};
+
+// admin は全ユーザー、それ以外は自分のトークンだけを見られる
+pub fn show_tokens(s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    let owner = (!caller.is_admin()).then_some(caller.user.as_str());
+    let tokens = s
+        .show_tokens(owner)?
+        .into_iter()
+        .map(|token| TokenInfo {
+            token_id: token.id,
+            token_name: token.token_name,
+            user_name: token.user_name,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            scope: token.scope,
+        })
+        .collect();
+    Ok(Output::ShowTokens(ShowTokens { tokens }))
+}
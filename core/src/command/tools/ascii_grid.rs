@@ -0,0 +1,157 @@
+// ESRI ASCII グリッド (.asc)。座標は経緯度（WGS84）とみなす
+// ヘッダに座標系は無いため、範囲が経緯度に収まらないグリッド（投影座標系）は拒否する
+pub struct AsciiGrid {
+    pub ncols: usize,
+    pub nrows: usize,
+    // 左下セルの中心
+    pub x_center: f64,
+    pub y_center: f64,
+    pub cellsize: f64,
+    pub nodata: Option<f64>,
+    // 北の行から順に並ぶ
+    pub values: Vec<f64>,
+}
+
+impl AsciiGrid {
+    // row は北から数えた 0 始まりの行番号
+    pub fn center(&self, row: usize, col: usize) -> (f64, f64) {
+        let lng = self.x_center + col as f64 * self.cellsize;
+        let lat = self.y_center + (self.nrows - 1 - row) as f64 * self.cellsize;
+        (lat, lng)
+    }
+
+    pub fn is_nodata(&self, value: f64) -> bool {
+        value.is_nan() || self.nodata == Some(value)
+    }
+}
+
+pub fn parse_ascii_grid(data: &str) -> Result<AsciiGrid, String> {
+    let mut tokens = data.split_ascii_whitespace().peekable();
+
+    let mut ncols = None;
+    let mut nrows = None;
+    let mut x = None;
+    let mut y = None;
+    let mut corner = (false, false);
+    let mut cellsize = None;
+    let mut nodata = None;
+
+    // ヘッダは値が数値で始まるまで続く
+    while let Some(name) = tokens.next_if(|t| t.parse::<f64>().is_err()) {
+        let value = tokens
+            .next()
+            .ok_or_else(|| format!("Missing value for '{}'", name))?;
+        let number = value
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid {} '{}'", name, value))?;
+        match name.to_ascii_lowercase().as_str() {
+            "ncols" => ncols = Some(count(name, number)?),
+            "nrows" => nrows = Some(count(name, number)?),
+            "xllcorner" => (x, corner.0) = (Some(number), true),
+            "xllcenter" => (x, corner.0) = (Some(number), false),
+            "yllcorner" => (y, corner.1) = (Some(number), true),
+            "yllcenter" => (y, corner.1) = (Some(number), false),
+            "cellsize" => cellsize = Some(number),
+            "nodata_value" => nodata = Some(number),
+            _ => return Err(format!("Unknown header '{}'", name)),
+        }
+    }
+
+    let missing = |name: &str| format!("Missing header '{}'", name);
+    let ncols = ncols.ok_or_else(|| missing("ncols"))?;
+    let nrows = nrows.ok_or_else(|| missing("nrows"))?;
+    let cellsize = cellsize.ok_or_else(|| missing("cellsize"))?;
+    if cellsize <= 0.0 {
+        return Err(format!("cellsize must be positive. Got {}", cellsize));
+    }
+    let offset = |is_corner: bool| if is_corner { cellsize / 2.0 } else { 0.0 };
+    let x_center = x.ok_or_else(|| missing("xllcorner"))? + offset(corner.0);
+    let y_center = y.ok_or_else(|| missing("yllcorner"))? + offset(corner.1);
+    let cells = ncols
+        .checked_mul(nrows)
+        .ok_or_else(|| format!("Grid too large ({} x {})", ncols, nrows))?;
+
+    let west = x_center - cellsize / 2.0;
+    let south = y_center - cellsize / 2.0;
+    let east = west + ncols as f64 * cellsize;
+    let north = south + nrows as f64 * cellsize;
+    // 端がちょうど ±180 / ±90 のグリッドを丸め誤差で弾かないよう少し余裕を持たせる
+    // NaN の座標もここで弾く
+    let fits = |min: f64, max: f64, limit: f64| min >= -limit - 1e-6 && max <= limit + 1e-6;
+    if !(fits(west, east, 180.0) && fits(south, north, 90.0)) {
+        return Err(format!(
+            "Grid extent ({}, {}) - ({}, {}) is not in longitude/latitude. Reproject it to WGS84 first",
+            west, south, east, north
+        ));
+    }
+
+    let values = tokens
+        .map(|t| {
+            t.parse::<f64>()
+                .map_err(|_| format!("Invalid value '{}'", t))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() != cells {
+        return Err(format!(
+            "Expected {} values ({} x {}). Got {}",
+            cells,
+            ncols,
+            nrows,
+            values.len()
+        ));
+    }
+
+    Ok(AsciiGrid {
+        ncols,
+        nrows,
+        x_center,
+        y_center,
+        cellsize,
+        nodata,
+        values,
+    })
+}
+
+// ncols / nrows は 1 以上の整数（f64 で正確に表せる 2^53 まで）
+fn count(name: &str, number: f64) -> Result<usize, String> {
+    if number < 1.0 || number.fract() != 0.0 || number > (1u64 << 53) as f64 {
+        return Err(format!(
+            "{} must be a positive integer. Got {}",
+            name, number
+        ));
+    }
+    Ok(number as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "ncols 2\nnrows 2\nxllcorner 139.0\nyllcorner 35.0\ncellsize 0.5\n";
+
+    #[test]
+    fn parses_geographic_grid() {
+        let grid = parse_ascii_grid(&format!("{}1 2\n3 4\n", HEADER)).unwrap();
+        assert_eq!(grid.values, vec![1.0, 2.0, 3.0, 4.0]);
+        // 北西のセルの中心
+        assert_eq!(grid.center(0, 0), (35.75, 139.25));
+    }
+
+    #[test]
+    fn rejects_projected_grid() {
+        let data = "ncols 2\nnrows 1\nxllcorner 500000\nyllcorner 3900000\ncellsize 30\n1 2\n";
+        assert!(parse_ascii_grid(data).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_counts() {
+        for ncols in ["2.5", "-2", "0", "nan", "inf", "1e30"] {
+            let data = HEADER.replace("ncols 2", &format!("ncols {}", ncols));
+            assert!(parse_ascii_grid(&data).is_err(), "ncols {}", ncols);
+        }
+        // ncols * nrows が usize に収まらない
+        let data =
+            "ncols 1099511627776\nnrows 1099511627776\nxllcorner 0\nyllcorner 0\ncellsize 1e-13\n";
+        assert!(parse_ascii_grid(data).is_err_and(|e| e.starts_with("Grid too large")));
+    }
+}
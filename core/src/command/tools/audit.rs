@@ -0,0 +1,116 @@
+use serde_json::Value;
+
+use crate::{
+    auth::Caller,
+    command::tools::scope::command_spaces,
+    error::Error,
+    io::{AuditEntry, tools::time::now_millis},
+    json::{input::Command, output::Output},
+};
+
+// 値を伏せるフィールド（ChangePassword・ResetPassword・CreateUser）
+const SECRET_FIELDS: [&str; 3] = ["password", "old_password", "new_password"];
+// 一括投入の本文。大きいのでバイト数だけ残す
+const BULK_FIELDS: [&str; 1] = ["data"];
+
+// audit DB に残す書き込み系のコマンドか
+pub fn is_mutating(cmd: &Command) -> bool {
+    match cmd {
+        Command::CreateSpace(_)
+        | Command::DropSpace(_)
+        | Command::RenameSpace(_)
+        | Command::CloneSpace(_)
+        | Command::ImportSpace(_)
+        | Command::ShareSpace(_)
+        | Command::UnshareSpace(_)
+        | Command::CreateKey(_)
+        | Command::DropKey(_)
+        | Command::RenameKey(_)
+        | Command::AlterKeyType(_)
+        | Command::CopyKey(_)
+        | Command::InsertValue(_)
+        | Command::PatchValue(_)
+        | Command::UpdateValue(_)
+        | Command::DeleteValue(_)
+        | Command::ImportCsv(_)
+        | Command::ImportGeoJson(_)
+        | Command::ImportAsciiGrid(_)
+        | Command::CreateUser(_)
+        | Command::DropUser(_)
+        | Command::ChangePassword(_)
+        | Command::ResetPassword(_)
+        | Command::UnlockUser(_)
+        | Command::KillSession(_)
+        | Command::KillUserSessions(_)
+        | Command::CreateToken(_)
+        | Command::RevokeToken(_)
+        | Command::CreateRole(_)
+        | Command::DropRole(_)
+        | Command::GrantRole(_)
+        | Command::RevokeRole(_) => true,
+
+        Command::ExportSpace(_)
+        | Command::InfoSpace(_)
+        | Command::ShowSpaces
+        | Command::Version
+        | Command::ShowKeys(_)
+        | Command::InfoKey(_)
+        | Command::SelectValue(_)
+        | Command::ShowValues(_)
+        | Command::SelectPyramid(_)
+        | Command::ValueHistory(_)
+        | Command::InfoUser(_)
+        | Command::ShowUsers
+        | Command::ShowSessions
+        | Command::ShowTokens
+        | Command::ShowRoles
+        | Command::ShowAudit(_) => false,
+    }
+}
+
+// パスワードを伏せ、一括投入の本文をバイト数に置き換えた Command の JSON
+// 値の JSON の中身は書き換えないよう、コマンドの直下のフィールドだけを見る
+pub fn redacted_command(cmd: &Command) -> Value {
+    let mut value = serde_json::to_value(cmd).unwrap_or(Value::Null);
+    if let Value::Object(variant) = &mut value {
+        for fields in variant.values_mut() {
+            let Value::Object(fields) = fields else {
+                continue;
+            };
+            for (name, field) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&name.as_str()) {
+                    *field = Value::String("***".to_string());
+                } else if BULK_FIELDS.contains(&name.as_str()) {
+                    let len = match &*field {
+                        Value::String(s) => s.len(),
+                        other => other.to_string().len(),
+                    };
+                    *field = Value::String(format!("<{} bytes>", len));
+                }
+            }
+        }
+    }
+    value
+}
+
+// 実行前に結果無しで audit DB に書き、実行後に結果を入れて書き直す
+pub fn audit_entry(cmd: &Command, caller: &Caller) -> AuditEntry {
+    AuditEntry {
+        timestamp: now_millis(),
+        user_name: caller.user.clone(),
+        request_id: caller.request_id.clone(),
+        space_names: command_spaces(cmd)
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        command: redacted_command(cmd),
+        result: None,
+    }
+}
+
+pub fn audit_result(result: &Result<Output, Error>) -> Option<Result<(), Value>> {
+    Some(match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(serde_json::to_value(e).unwrap_or(Value::Null)),
+    })
+}
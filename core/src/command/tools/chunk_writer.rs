@@ -0,0 +1,51 @@
+use std::io::{self, Write};
+
+use actix_web::web::Bytes;
+use tokio::sync::mpsc;
+
+use crate::error::Error;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// ブロッキングスレッドで書いた出力を一定サイズごとに HTTP レスポンスへ流す
+pub struct ChunkWriter {
+    buf: Vec<u8>,
+    tx: mpsc::Sender<Result<Bytes, Error>>,
+}
+
+impl ChunkWriter {
+    pub fn new(tx: mpsc::Sender<Result<Bytes, Error>>) -> Self {
+        ChunkWriter {
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            tx,
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        // 受信側が閉じていればクライアントが切断している
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
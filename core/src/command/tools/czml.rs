@@ -0,0 +1,294 @@
+use kasane_logic::{
+    function::tools::point_to_ecef::point_to_ecef,
+    id::{DimensionRange, SpaceTimeId, coordinates::Point},
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    io::{ValueEntry, tools::time::iso8601},
+    json::{
+        input::{ColorRamp, ColorStop},
+        output::czml::{Color, Cuboid, Dimensions, Material, Packet, Position, SolidColor},
+    },
+};
+
+// 数値でない値や Key を持たないセルの色
+const NO_VALUE_COLOR: [u8; 4] = [128, 128, 128, 160];
+const MIN_COLOR: [u8; 4] = [0, 0, 255, 160];
+const MAX_COLOR: [u8; 4] = [255, 0, 0, 160];
+
+// セルごとの値を CZML の box エンティティに変換する
+pub fn czml_document(
+    cells: Vec<(SpaceTimeId, Vec<(String, ValueEntry)>)>,
+    ramp: Option<&ColorRamp>,
+) -> Vec<Packet> {
+    let ramp = ramp.cloned().unwrap_or_default();
+    let color_value = |values: &[(String, ValueEntry)]| -> Option<f64> {
+        match &ramp.key_name {
+            Some(key) => values.iter().find(|(k, _)| k == key),
+            None => values.first(),
+        }
+        .and_then(|(_, v)| v.as_f64())
+    };
+
+    // stops 省略時は値の範囲に合わせる
+    let stops = if ramp.stops.is_empty() {
+        let numbers: Vec<f64> = cells.iter().filter_map(|(_, v)| color_value(v)).collect();
+        let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        vec![
+            ColorStop {
+                value: min,
+                color: MIN_COLOR,
+            },
+            ColorStop {
+                value: max,
+                color: MAX_COLOR,
+            },
+        ]
+    } else {
+        let mut stops = ramp.stops.clone();
+        stops.sort_by(|a, b| a.value.total_cmp(&b.value));
+        stops
+    };
+
+    let mut packets = vec![Packet {
+        id: "document".to_string(),
+        name: Some("kasane".to_string()),
+        version: Some("1.0".to_string()),
+        availability: None,
+        position: None,
+        cuboid: None,
+        properties: None,
+    }];
+
+    for (stid, values) in cells {
+        let color = color_value(&values)
+            .map(|v| interpolate(&stops, v))
+            .unwrap_or(NO_VALUE_COLOR);
+        let center = stid.center();
+
+        // MultiKey で同じキー名が複数あれば配列にまとめる
+        let mut properties = Map::new();
+        for (key, value) in values {
+            let value = value.to_plain_json();
+            match properties.get_mut(&key) {
+                Some(Value::Array(list)) => list.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    properties.insert(key, value);
+                }
+            }
+        }
+
+        packets.push(Packet {
+            id: stid.to_string(),
+            name: Some(stid.to_string()),
+            version: None,
+            availability: availability(&stid),
+            position: Some(Position {
+                cartographic_degrees: [center.longitude, center.latitude, center.altitude],
+            }),
+            cuboid: Some(Cuboid {
+                dimensions: Dimensions {
+                    cartesian: dimensions(&stid.vertex()),
+                },
+                material: Material {
+                    solid_color: SolidColor {
+                        color: Color { rgba: color },
+                    },
+                },
+                outline: true,
+                outline_color: Color {
+                    rgba: [0, 0, 0, 255],
+                },
+            }),
+            properties: Some(properties),
+        });
+    }
+
+    packets
+}
+
+// 8頂点の範囲から、中心での東西・南北・高さの長さ [m] を求める
+fn dimensions(vertex: &[Point; 8]) -> [f64; 3] {
+    let bound = |f: fn(&Point) -> f64| {
+        vertex
+            .iter()
+            .map(f)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+                (lo.min(v), hi.max(v))
+            })
+    };
+    let (lat0, lat1) = bound(|p| p.latitude);
+    let (lng0, lng1) = bound(|p| p.longitude);
+    let (alt0, alt1) = bound(|p| p.altitude);
+    let (lat, lng, alt) = (
+        (lat0 + lat1) / 2.0,
+        (lng0 + lng1) / 2.0,
+        (alt0 + alt1) / 2.0,
+    );
+
+    let distance = |a: Point, b: Point| {
+        let (a, b) = (point_to_ecef(a), point_to_ecef(b));
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+    };
+    let point = |latitude, longitude| Point {
+        latitude,
+        longitude,
+        altitude: alt,
+    };
+
+    [
+        distance(point(lat, lng0), point(lat, lng1)),
+        distance(point(lat0, lng), point(lat1, lng)),
+        alt1 - alt0,
+    ]
+}
+
+// 時間を持つ ID は t の区間 (i 秒単位) だけ表示する
+fn availability(stid: &SpaceTimeId) -> Option<String> {
+    let i = stid.i() as u64;
+    let (start, end) = match stid.t() {
+        _ if i == 0 => return None,
+        DimensionRange::Single(t) => (t, t),
+        DimensionRange::LimitRange(s, e) => (s, e),
+        _ => return None,
+    };
+    Some(format!(
+        "{}/{}",
+        iso8601(start as u64 * i),
+        iso8601((end as u64 + 1) * i)
+    ))
+}
+
+// stops の範囲外は端の色にする
+fn interpolate(stops: &[ColorStop], value: f64) -> [u8; 4] {
+    let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+        return NO_VALUE_COLOR;
+    };
+    if value <= first.value {
+        return first.color;
+    }
+    if value >= last.value {
+        return last.color;
+    }
+
+    for pair in stops.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if value <= b.value {
+            let r = (value - a.value) / (b.value - a.value);
+            let mut color = [0; 4];
+            for (c, (x, y)) in color.iter_mut().zip(a.color.iter().zip(b.color)) {
+                *c = (*x as f64 + (y as f64 - *x as f64) * r).round() as u8;
+            }
+            return color;
+        }
+    }
+    last.color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DimensionRange::Single;
+
+    fn cell(x: u32, i: u32, t: DimensionRange<u32>) -> SpaceTimeId {
+        SpaceTimeId::new(10, Single(0), Single(x), Single(400), i, t).unwrap()
+    }
+
+    fn values(pairs: &[(&str, ValueEntry)]) -> Vec<(String, ValueEntry)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    fn colors(packets: &[Packet]) -> Vec<[u8; 4]> {
+        packets[1..]
+            .iter()
+            .map(|p| p.cuboid.as_ref().unwrap().material.solid_color.color.rgba)
+            .collect()
+    }
+
+    fn stop(value: f64, color: [u8; 4]) -> ColorStop {
+        ColorStop { value, color }
+    }
+
+    #[test]
+    fn default_ramp_spans_the_values() {
+        let cells = vec![
+            (
+                cell(0, 0, DimensionRange::Any),
+                values(&[("a", ValueEntry::INT(0))]),
+            ),
+            (
+                cell(1, 0, DimensionRange::Any),
+                values(&[("a", ValueEntry::INT(10))]),
+            ),
+            (
+                cell(2, 0, DimensionRange::Any),
+                values(&[("a", ValueEntry::TEXT("x".to_string()))]),
+            ),
+        ];
+        let packets = czml_document(cells, None);
+
+        assert_eq!(packets[0].id, "document");
+        assert_eq!(packets[0].version.as_deref(), Some("1.0"));
+        assert_eq!(colors(&packets), [MIN_COLOR, MAX_COLOR, NO_VALUE_COLOR]);
+    }
+
+    #[test]
+    fn stops_are_sorted_and_interpolated_on_the_named_key() {
+        let ramp = ColorRamp {
+            key_name: Some("b".to_string()),
+            stops: vec![stop(10.0, [200, 0, 0, 255]), stop(0.0, [0, 0, 100, 255])],
+        };
+        let cells = [-5, 5, 15]
+            .into_iter()
+            .enumerate()
+            .map(|(x, b)| {
+                let v = values(&[("a", ValueEntry::INT(1000)), ("b", ValueEntry::INT(b))]);
+                (cell(x as u32, 0, DimensionRange::Any), v)
+            })
+            .collect();
+        let packets = czml_document(cells, Some(&ramp));
+
+        assert_eq!(
+            colors(&packets),
+            [[0, 0, 100, 255], [100, 0, 50, 255], [200, 0, 0, 255]]
+        );
+    }
+
+    #[test]
+    fn box_is_centered_on_the_cell_and_sized_in_meters() {
+        let stid = cell(0, 0, DimensionRange::Any);
+        let center = stid.center();
+        let packets = czml_document(vec![(stid, Vec::new())], None);
+        let packet = &packets[1];
+
+        let position = packet.position.as_ref().unwrap().cartographic_degrees;
+        assert_eq!(
+            position,
+            [center.longitude, center.latitude, center.altitude]
+        );
+        let [east, north, height] = packet.cuboid.as_ref().unwrap().dimensions.cartesian;
+        // z=10 のセルは 40km 弱四方、高さは 2^25/2^10 m
+        assert!(east > 1_000.0 && east < 40_000.0);
+        assert!(north > 1_000.0 && north < 40_000.0);
+        assert_eq!(height, 32_768.0);
+        assert!(packet.availability.is_none());
+    }
+
+    #[test]
+    fn temporal_cells_have_availability() {
+        let packets = czml_document(
+            vec![(cell(0, 60, DimensionRange::LimitRange(1, 2)), Vec::new())],
+            None,
+        );
+        assert_eq!(
+            packets[1].availability.as_deref(),
+            Some("1970-01-01T00:01:00Z/1970-01-01T00:03:00Z")
+        );
+    }
+}
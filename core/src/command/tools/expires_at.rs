@@ -0,0 +1,22 @@
+use crate::io::tools::time::now_millis;
+
+/// expires_at (UNIXミリ秒) を優先し、無ければ ttl_secs から期限を計算する
+pub fn expires_at(ttl_secs: Option<u64>, expires_at: Option<u64>) -> Option<u64> {
+    expires_at.or_else(|| ttl_secs.map(|ttl| now_millis().saturating_add(ttl.saturating_mul(1000))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_at_wins_over_ttl() {
+        assert_eq!(expires_at(Some(10), Some(5)), Some(5));
+        assert_eq!(expires_at(None, None), None);
+
+        let before = now_millis();
+        let t = expires_at(Some(10), None).unwrap();
+        assert!(t >= before + 10_000 && t <= now_millis() + 10_000);
+        assert_eq!(expires_at(Some(u64::MAX), None), Some(u64::MAX));
+    }
+}
@@ -0,0 +1,130 @@
+use kasane_logic::id::{DimensionRange, SpaceTimeId};
+use serde_json::{Map, Value};
+
+use crate::{
+    io::ValueEntry,
+    json::output::geojson::{Feature, FeatureCollection, Geometry},
+};
+
+// セルごとの値を GeoJSON に変換する
+// point が false ならセルの底面ポリゴン、true なら中心点を使う
+pub fn feature_collection(
+    cells: Vec<(SpaceTimeId, Vec<(String, ValueEntry)>)>,
+    point: bool,
+) -> FeatureCollection {
+    let features = cells
+        .into_iter()
+        .map(|(stid, values)| {
+            let c = stid.coordinates();
+            let (lat0, lat1) = min_max(c.latitude);
+            let (lng0, lng1) = min_max(c.longitude);
+            let (alt0, alt1) = min_max(c.altitude);
+
+            let geometry = if point {
+                let center = stid.center();
+                Geometry::Point([center.longitude, center.latitude])
+            } else {
+                // 外周は反時計回り
+                Geometry::Polygon(vec![vec![
+                    [lng0, lat0],
+                    [lng1, lat0],
+                    [lng1, lat1],
+                    [lng0, lat1],
+                    [lng0, lat0],
+                ]])
+            };
+
+            // MultiKey で同じキー名が複数あれば配列にまとめる
+            let mut properties = Map::new();
+            for (key, value) in values {
+                let value = value.to_plain_json();
+                match properties.get_mut(&key) {
+                    Some(Value::Array(list)) => list.push(value),
+                    Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                    None => {
+                        properties.insert(key, value);
+                    }
+                }
+            }
+
+            // セル自体の情報は同名のキーより優先する
+            properties.insert("id_string".to_string(), stid.to_string().into());
+            properties.insert("zoom".to_string(), stid.z().into());
+            if let DimensionRange::Single(f) = stid.f() {
+                properties.insert("floor".to_string(), f.into());
+            }
+            properties.insert("altitude_min".to_string(), alt0.into());
+            properties.insert("altitude_max".to_string(), alt1.into());
+
+            Feature {
+                geometry,
+                properties,
+            }
+        })
+        .collect();
+
+    FeatureCollection { features }
+}
+
+fn min_max((a, b): (f64, f64)) -> (f64, f64) {
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tools::range::{bitmask_to_stid, id_string_to_bitmask};
+
+    fn stid(s: &str) -> SpaceTimeId {
+        bitmask_to_stid(&id_string_to_bitmask(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn polygon_covers_the_cell_and_properties_merge_keys() {
+        let values = vec![
+            ("a".to_string(), ValueEntry::INT(1)),
+            ("b".to_string(), ValueEntry::TEXT("x".to_string())),
+            ("a".to_string(), ValueEntry::INT(2)),
+            ("zoom".to_string(), ValueEntry::INT(9)),
+        ];
+        let json = serde_json::to_value(feature_collection(
+            vec![(stid("1/0/1/0_0/-"), values)],
+            false,
+        ))
+        .unwrap();
+
+        assert_eq!(json["type"], "FeatureCollection");
+        let feature = &json["features"][0];
+        assert_eq!(feature["type"], "Feature");
+        assert_eq!(feature["geometry"]["type"], "Polygon");
+
+        // 東半球の北側。外周は閉じていて反時計回り
+        let ring: Vec<[f64; 2]> =
+            serde_json::from_value(feature["geometry"]["coordinates"][0].clone()).unwrap();
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring[0], ring[4]);
+        assert_eq!((ring[0][0], ring[1][0]), (0.0, 180.0));
+        assert!(ring[0][1].abs() < 1e-9 && (ring[2][1] - 85.0511).abs() < 1e-3);
+
+        let properties = &feature["properties"];
+        assert_eq!(properties["a"], serde_json::json!([1, 2]));
+        assert_eq!(properties["b"], "x");
+        assert_eq!(properties["zoom"], 1);
+        assert_eq!(properties["floor"], 0);
+        assert_eq!(properties["id_string"], "1/0/1/0_0/-");
+    }
+
+    #[test]
+    fn point_is_the_cell_center() {
+        let json = serde_json::to_value(feature_collection(
+            vec![(stid("1/0/1/0_0/-"), Vec::new())],
+            true,
+        ))
+        .unwrap();
+        let geometry = &json["features"][0]["geometry"];
+        assert_eq!(geometry["type"], "Point");
+        let [lng, lat]: [f64; 2] = serde_json::from_value(geometry["coordinates"].clone()).unwrap();
+        assert_eq!(lng, 90.0);
+        assert!(lat > 0.0 && lat < 85.06);
+    }
+}
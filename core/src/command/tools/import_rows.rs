@@ -0,0 +1,21 @@
+use crate::{
+    io::ImportReport,
+    json::output::{ImportRows, Output, RowError},
+};
+
+// 取り込み前の解析エラーと書き込み時の行エラーを行番号順にまとめる
+pub fn import_output(total: usize, report: ImportReport, parse_errors: Vec<RowError>) -> Output {
+    let mut errors = parse_errors;
+    errors.extend(report.errors.into_iter().map(|(row, e)| RowError {
+        row,
+        message: e.to_string(),
+    }));
+    errors.sort_by_key(|e| e.row);
+
+    Output::ImportRows(ImportRows {
+        rows_total: total,
+        rows_imported: report.imported,
+        rows_skipped: report.skipped,
+        errors,
+    })
+}
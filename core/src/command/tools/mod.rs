@@ -1,2 +1,19 @@
+pub mod ascii_grid;
+pub mod audit;
+pub mod chunk_writer;
+pub mod czml;
+pub mod expires_at;
+pub mod geojson;
+pub mod import_rows;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod privilege;
+pub mod rasterize;
+pub mod require_admin;
+pub mod require_owner;
+pub mod scope;
+pub mod spot;
 pub mod valid_len;
 pub mod valid_name;
+pub mod valid_password;
+pub mod value_output;
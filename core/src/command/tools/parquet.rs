@@ -0,0 +1,268 @@
+use std::{io::Write, sync::Arc};
+
+use arrow_array::{
+    ArrayRef, RecordBatch,
+    builder::{
+        BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int32Builder, Int64Builder,
+        StringBuilder, TimestampMillisecondBuilder, UInt8Builder, UInt32Builder,
+    },
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+
+use crate::{
+    command::export_values::{export_error, scan_rows},
+    error::Error,
+    io::{StorageTrait, ValueEntry, full::Storage},
+    json::input::KeyType,
+};
+
+// 1つの RecordBatch (= row group) に入れる行数
+const BATCH_ROWS: usize = 8192;
+
+// Key の型に対応した Arrow の列
+enum ValueColumn {
+    Int(Int32Builder),
+    Float(Float32Builder),
+    Int64(Int64Builder),
+    Double(Float64Builder),
+    Boolean(BooleanBuilder),
+    Timestamp(TimestampMillisecondBuilder),
+    Text(StringBuilder),
+    Binary(BinaryBuilder),
+}
+
+impl ValueColumn {
+    fn new(keytype: KeyType) -> Self {
+        match keytype {
+            KeyType::INT => ValueColumn::Int(Int32Builder::new()),
+            KeyType::FLOAT => ValueColumn::Float(Float32Builder::new()),
+            KeyType::INT64 => ValueColumn::Int64(Int64Builder::new()),
+            KeyType::DOUBLE => ValueColumn::Double(Float64Builder::new()),
+            KeyType::BOOLEAN => ValueColumn::Boolean(BooleanBuilder::new()),
+            KeyType::TIMESTAMP => ValueColumn::Timestamp(TimestampMillisecondBuilder::new()),
+            KeyType::TEXT | KeyType::JSON => ValueColumn::Text(StringBuilder::new()),
+            KeyType::BLOB => ValueColumn::Binary(BinaryBuilder::new()),
+        }
+    }
+
+    fn data_type(keytype: KeyType) -> DataType {
+        match keytype {
+            KeyType::INT => DataType::Int32,
+            KeyType::FLOAT => DataType::Float32,
+            KeyType::INT64 => DataType::Int64,
+            KeyType::DOUBLE => DataType::Float64,
+            KeyType::BOOLEAN => DataType::Boolean,
+            KeyType::TIMESTAMP => DataType::Timestamp(TimeUnit::Millisecond, None),
+            KeyType::TEXT | KeyType::JSON => DataType::Utf8,
+            KeyType::BLOB => DataType::Binary,
+        }
+    }
+
+    fn append(&mut self, value: Option<&ValueEntry>) {
+        match (self, value) {
+            (ValueColumn::Int(b), Some(ValueEntry::INT(v))) => b.append_value(*v),
+            (ValueColumn::Float(b), Some(ValueEntry::FLOAT(v))) => b.append_value(*v),
+            (ValueColumn::Int64(b), Some(ValueEntry::INT64(v))) => b.append_value(*v),
+            (ValueColumn::Double(b), Some(ValueEntry::DOUBLE(v))) => b.append_value(*v),
+            (ValueColumn::Boolean(b), Some(ValueEntry::BOOLEAN(v))) => b.append_value(*v),
+            (ValueColumn::Timestamp(b), Some(ValueEntry::TIMESTAMP(v))) => b.append_value(*v),
+            (ValueColumn::Text(b), Some(ValueEntry::TEXT(v))) => b.append_value(v),
+            (ValueColumn::Text(b), Some(ValueEntry::JSON(v))) => b.append_value(v.to_string()),
+            (ValueColumn::Binary(b), Some(ValueEntry::BLOB(v))) => b.append_value(v),
+            (ValueColumn::Int(b), _) => b.append_null(),
+            (ValueColumn::Float(b), _) => b.append_null(),
+            (ValueColumn::Int64(b), _) => b.append_null(),
+            (ValueColumn::Double(b), _) => b.append_null(),
+            (ValueColumn::Boolean(b), _) => b.append_null(),
+            (ValueColumn::Timestamp(b), _) => b.append_null(),
+            (ValueColumn::Text(b), _) => b.append_null(),
+            (ValueColumn::Binary(b), _) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ValueColumn::Int(b) => Arc::new(b.finish()),
+            ValueColumn::Float(b) => Arc::new(b.finish()),
+            ValueColumn::Int64(b) => Arc::new(b.finish()),
+            ValueColumn::Double(b) => Arc::new(b.finish()),
+            ValueColumn::Boolean(b) => Arc::new(b.finish()),
+            ValueColumn::Timestamp(b) => Arc::new(b.finish()),
+            ValueColumn::Text(b) => Arc::new(b.finish()),
+            ValueColumn::Binary(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+// セル情報の固定列
+#[derive(Default)]
+struct CellColumns {
+    id_string: StringBuilder,
+    z: UInt8Builder,
+    f: Int32Builder,
+    x: UInt32Builder,
+    y: UInt32Builder,
+    lat: Float64Builder,
+    lng: Float64Builder,
+    alt: Float64Builder,
+}
+
+pub fn export_parquet(
+    spacename: &str,
+    keynames: &[String],
+    ids: Option<Vec<Vec<u8>>>,
+    s: Arc<Storage>,
+    out: impl Write + Send,
+) -> Result<(), Error> {
+    let keytypes = keynames
+        .iter()
+        .map(|k| s.key_type(spacename, k))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut fields = vec![
+        Field::new("id_string", DataType::Utf8, false),
+        Field::new("z", DataType::UInt8, false),
+        Field::new("f", DataType::Int32, false),
+        Field::new("x", DataType::UInt32, false),
+        Field::new("y", DataType::UInt32, false),
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lng", DataType::Float64, false),
+        Field::new("alt", DataType::Float64, false),
+    ];
+    fields.extend(
+        keynames
+            .iter()
+            .zip(&keytypes)
+            .map(|(k, t)| Field::new(k, ValueColumn::data_type(*t), true)),
+    );
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut writer = ArrowWriter::try_new(out, schema.clone(), None).map_err(export_error)?;
+    let mut cells = CellColumns::default();
+    let mut values: Vec<ValueColumn> = keytypes.iter().map(|t| ValueColumn::new(*t)).collect();
+    let mut rows = 0;
+
+    let flush = |cells: &mut CellColumns,
+                 values: &mut Vec<ValueColumn>,
+                 writer: &mut ArrowWriter<_>|
+     -> Result<(), Error> {
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(cells.id_string.finish()),
+            Arc::new(cells.z.finish()),
+            Arc::new(cells.f.finish()),
+            Arc::new(cells.x.finish()),
+            Arc::new(cells.y.finish()),
+            Arc::new(cells.lat.finish()),
+            Arc::new(cells.lng.finish()),
+            Arc::new(cells.alt.finish()),
+        ];
+        columns.extend(values.iter_mut().map(ValueColumn::finish));
+        let batch = RecordBatch::try_new(schema.clone(), columns).map_err(export_error)?;
+        writer.write(&batch).map_err(export_error)?;
+        // row group を書き出して出力先へ流す
+        writer.flush().map_err(export_error)
+    };
+
+    scan_rows(&s, spacename, keynames, ids, |row| {
+        let center = row.stid.center();
+        cells.id_string.append_value(row.stid.to_string());
+        cells.z.append_value(row.stid.z());
+        cells.f.append_value(row.f);
+        cells.x.append_value(row.x);
+        cells.y.append_value(row.y);
+        cells.lat.append_value(center.latitude);
+        cells.lng.append_value(center.longitude);
+        cells.alt.append_value(center.altitude);
+        for (column, value) in values.iter_mut().zip(row.values) {
+            column.append(value.as_ref());
+        }
+
+        rows += 1;
+        if rows % BATCH_ROWS == 0 {
+            flush(&mut cells, &mut values, &mut writer)?;
+        }
+        Ok(())
+    })?;
+
+    if rows % BATCH_ROWS != 0 {
+        flush(&mut cells, &mut values, &mut writer)?;
+    }
+    writer.close().map_err(export_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::tools::{range::id_string_to_bitmask, temp_storage::TempStorage},
+        json::input::KeyMode,
+    };
+    use actix_web::web::Bytes;
+    use arrow_array::{Array, Int32Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn parquet_columns_follow_the_key_types() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        for (key, keytype) in [("a", KeyType::INT), ("b", KeyType::TEXT)] {
+            s.create_key("s", key, keytype, KeyMode::UniqueKey, Default::default())
+                .unwrap();
+        }
+        let id = |c: &str| id_string_to_bitmask(c).unwrap();
+        s.insert_value(
+            "s",
+            "a",
+            vec![id("3/5/2/7_0/-")],
+            ValueEntry::INT(5),
+            None,
+            "alice",
+        )
+        .unwrap();
+        s.insert_value(
+            "s",
+            "b",
+            vec![id("3/5/2/6_0/-")],
+            ValueEntry::TEXT("x".to_string()),
+            None,
+            "alice",
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        let keys = ["a".to_string(), "b".to_string()];
+        export_parquet("s", &keys, None, s.shared(), &mut out).unwrap();
+
+        let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(out))
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(8).data_type(), &DataType::Int32);
+        assert_eq!(batch.schema().field(9).data_type(), &DataType::Utf8);
+
+        let column = |i: usize| batch.column(i).as_any();
+        let ids = column(0).downcast_ref::<StringArray>().unwrap();
+        let a = column(8).downcast_ref::<Int32Array>().unwrap();
+        let b = column(9).downcast_ref::<StringArray>().unwrap();
+        for row in 0..2 {
+            match ids.value(row) {
+                "3/5/2/7_0/-" => {
+                    assert_eq!(a.value(row), 5);
+                    assert!(b.is_null(row));
+                }
+                "3/5/2/6_0/-" => {
+                    assert!(a.is_null(row));
+                    assert_eq!(b.value(row), "x");
+                }
+                other => panic!("unexpected cell {}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use crate::{
+    auth::Caller,
+    error::Error,
+    io::{SpaceAcl, StorageTrait, full::Storage, tools::role::space_privileges},
+    json::input::{AllOrChoose, Command, CommandDatabase, CommandKey, CommandSpace, Privileges},
+};
+
+// コマンドの実行に必要な権限
+pub enum Required<'a> {
+    Database(CommandDatabase),
+    // Database の権限か、Space の所有者であること
+    SpaceOwner(&'a str, CommandDatabase),
+    Space(&'a str, CommandSpace),
+    Key(&'a str, &'a str, CommandKey),
+    // Space 内の全コマンド（所有者か Write の共有と同じ）。まだ無い Space なら不要
+    SpaceWrite(&'a str),
+}
+
+impl Required<'_> {
+    fn space_name(&self) -> Option<&str> {
+        match self {
+            Required::Database(_) => None,
+            Required::SpaceOwner(space, _)
+            | Required::Space(space, _)
+            | Required::Key(space, _, _)
+            | Required::SpaceWrite(space) => Some(space),
+        }
+    }
+}
+
+// ユーザー・セッション・ロール系と Space の共有はコマンドの中で実行者を確認するので空
+// ShowSpaces は見える Space だけを返すので誰でも実行できる
+pub fn required_privileges(cmd: &Command) -> Vec<Required<'_>> {
+    use Required::{Database, Key, Space, SpaceOwner, SpaceWrite};
+    match cmd {
+        Command::CreateSpace(_) => vec![Database(CommandDatabase::CreateSpace)],
+        Command::DropSpace(v) => vec![SpaceOwner(&v.space_name, CommandDatabase::DropSpace)],
+        Command::RenameSpace(v) => {
+            vec![SpaceOwner(&v.space_name, CommandDatabase::RenameSpace)]
+        }
+        Command::CloneSpace(v) => vec![
+            Database(CommandDatabase::CloneSpace),
+            Space(&v.space_name, CommandSpace::ExportSpace),
+        ],
+        // 既存の Space には Key の作成と値の上書きをする
+        Command::ImportSpace(v) => vec![
+            Database(CommandDatabase::ImportSpace),
+            SpaceWrite(&v.space_name),
+        ],
+        Command::ShowSpaces | Command::ShareSpace(_) | Command::UnshareSpace(_) => Vec::new(),
+        Command::Version => vec![Database(CommandDatabase::Version)],
+        Command::ExportSpace(v) => vec![Space(&v.space_name, CommandSpace::ExportSpace)],
+        Command::InfoSpace(v) => vec![Space(&v.space_name, CommandSpace::InfoSpace)],
+
+        Command::CreateKey(v) => vec![Space(&v.space_name, CommandSpace::CreateKey)],
+        Command::DropKey(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::DropKey)],
+        Command::RenameKey(v) => vec![Space(&v.space_name, CommandSpace::RenameKey)],
+        Command::AlterKeyType(v) => vec![Space(&v.space_name, CommandSpace::AlterKeyType)],
+        // コピー先の既存値は置き換えるので、UpdateValue・DeleteValue も必要
+        Command::CopyKey(v) => {
+            let to_space = v.to_space_name.as_deref().unwrap_or(&v.space_name);
+            vec![
+                Key(&v.space_name, &v.key_name, CommandKey::SelectValue),
+                Key(to_space, &v.to_key_name, CommandKey::InsertValue),
+                Key(to_space, &v.to_key_name, CommandKey::UpdateValue),
+                Key(to_space, &v.to_key_name, CommandKey::DeleteValue),
+            ]
+        }
+        Command::ShowKeys(v) => vec![Space(&v.space_name, CommandSpace::ShowKeys)],
+        Command::InfoKey(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::InfoKey)],
+
+        Command::InsertValue(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::InsertValue)],
+        Command::PatchValue(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::PatchValue)],
+        Command::UpdateValue(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::UpdateValue)],
+        Command::DeleteValue(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::DeleteValue)],
+        Command::SelectValue(v) => select_keys(&v.space_name, &v.key_names),
+        Command::ShowValues(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::ShowValues)],
+        Command::SelectPyramid(v) => {
+            vec![Key(&v.space_name, &v.key_name, CommandKey::SelectPyramid)]
+        }
+        Command::ImportCsv(v) => vec![Key(&v.space_name, &v.key_name, CommandKey::InsertValue)],
+        Command::ImportGeoJson(v) => {
+            vec![Key(&v.space_name, &v.key_name, CommandKey::InsertValue)]
+        }
+        Command::ImportAsciiGrid(v) => {
+            vec![Key(&v.space_name, &v.key_name, CommandKey::InsertValue)]
+        }
+        Command::ValueHistory(v) => {
+            vec![Key(&v.space_name, &v.key_name, CommandKey::ValueHistory)]
+        }
+
+        Command::CreateUser(_)
+        | Command::DropUser(_)
+        | Command::ChangePassword(_)
+        | Command::ResetPassword(_)
+        | Command::UnlockUser(_)
+        | Command::InfoUser(_)
+        | Command::ShowUsers
+        | Command::ShowSessions
+        | Command::KillSession(_)
+        | Command::KillUserSessions(_)
+        | Command::CreateToken(_)
+        | Command::ShowTokens
+        | Command::RevokeToken(_)
+        | Command::CreateRole(_)
+        | Command::DropRole(_)
+        | Command::GrantRole(_)
+        | Command::RevokeRole(_)
+        | Command::ShowRoles
+        | Command::ShowAudit(_) => Vec::new(),
+    }
+}
+
+// SelectValue とエクスポートは指定した全 Key の SelectValue が必要
+pub fn select_keys<'a>(space_name: &'a str, key_names: &'a [String]) -> Vec<Required<'a>> {
+    key_names
+        .iter()
+        .map(|k| Required::Key(space_name, k, CommandKey::SelectValue))
+        .collect()
+}
+
+// ロールの権限の和で required を満たすか
+pub fn privileges_allow(privileges: &[Privileges], required: &Required) -> bool {
+    let space_allows = |p: &Privileges, space: &str, command: &CommandSpace| {
+        p.spaces
+            .iter()
+            .any(|s| s.target_space.includes(space) && s.command.includes(command))
+    };
+    // SpaceWrite は複数のロールを合わせて Space と全 Key の全コマンドがあれば良い
+    if let Required::SpaceWrite(space) = required {
+        let spaces = privileges
+            .iter()
+            .flat_map(|p| &p.spaces)
+            .any(|s| s.target_space.includes(*space) && matches!(s.command, AllOrChoose::All));
+        let keys = privileges.iter().flat_map(|p| &p.keys).any(|k| {
+            k.target_space.includes(*space)
+                && matches!(k.target_key, AllOrChoose::All)
+                && matches!(k.command, AllOrChoose::All)
+        });
+        return spaces && keys;
+    }
+    privileges.iter().any(|p| match required {
+        Required::Database(command) | Required::SpaceOwner(_, command) => {
+            p.database.as_ref().is_some_and(|d| d.includes(command))
+        }
+        Required::Space(space, command) => space_allows(p, space, command),
+        Required::Key(space, key, command) => {
+            // Key の削除は Space の DropKey でも良い
+            (*command == CommandKey::DropKey && space_allows(p, space, &CommandSpace::DropKey))
+                || p.keys.iter().any(|k| {
+                    k.target_space.includes(*space)
+                        && k.target_key.includes(*key)
+                        && k.command.includes(command)
+                })
+        }
+        Required::SpaceWrite(_) => false,
+    })
+}
+
+// roles が無ければ user DB のロールで補う。user DB に無い JWT のユーザーはロール無し
+pub fn resolve_roles(s: &Storage, caller: &Caller) -> Result<Caller, Error> {
+    if caller.roles.is_some() {
+        return Ok(caller.clone());
+    }
+    let roles = match s.user_roles(&caller.user) {
+        Ok(roles) => roles,
+        Err(Error::UserNotFound { .. }) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    Ok(Caller {
+        roles: Some(roles),
+        ..caller.clone()
+    })
+}
+
+// ロールか共有で Space 内のコマンドを1つでも実行できれば ShowSpaces に出す
+pub fn space_visible(
+    privileges: &[Privileges],
+    caller: &Caller,
+    space_name: &str,
+    acl: &SpaceAcl,
+) -> bool {
+    let roles = caller.roles.as_deref().unwrap_or_default();
+    acl.level(&caller.user, roles).is_some()
+        || privileges.iter().any(|p| {
+            p.spaces.iter().any(|s| s.target_space.includes(space_name))
+                || p.keys.iter().any(|k| k.target_space.includes(space_name))
+        })
+}
+
+// ロールを解決した実行者が required を全て満たすか。admin は何でも実行できる
+// ロールの権限に、required の Space の所有者・共有先としての権限を加えて判断する
+pub fn check_privileges(
+    s: &Storage,
+    caller: &Caller,
+    required: &[Required],
+    location: &'static str,
+) -> Result<(), Error> {
+    if required.is_empty() || caller.is_admin() {
+        return Ok(());
+    }
+    let roles = caller.roles.as_deref().unwrap_or_default();
+    let mut privileges = s.role_privileges(roles)?;
+    let mut owned = Vec::new();
+    let mut missing = Vec::new();
+    for space in required.iter().filter_map(Required::space_name) {
+        // 作成前の Space（RenameSpace の新しい名前等）は共有も無い
+        let acl = match s.space_acl(space) {
+            Ok(acl) => acl,
+            Err(Error::SpaceNotFound { .. }) => {
+                missing.push(space);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if acl.owner.as_deref() == Some(caller.user.as_str()) {
+            owned.push(space);
+        }
+        if let Some(level) = acl.level(&caller.user, roles) {
+            privileges.push(space_privileges(
+                AllOrChoose::Choose(vec![space.to_string()]),
+                level,
+            ));
+        }
+    }
+
+    let allowed = required.iter().all(|r| match r {
+        Required::SpaceOwner(space, _) if owned.contains(space) => true,
+        Required::SpaceWrite(space) if missing.contains(space) => true,
+        _ => privileges_allow(&privileges, r),
+    });
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied {
+            user_name: caller.user.clone(),
+            location,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::tools::{role::builtin_role, temp_storage::TempStorage},
+        json::input::{KeyPrivilege, ShareLevel, SpaceShare},
+    };
+
+    fn role(name: &str) -> Vec<Privileges> {
+        vec![builtin_role(name).unwrap()]
+    }
+
+    fn caller(user: &str, roles: &[&str]) -> Caller {
+        Caller {
+            roles: Some(roles.iter().map(|r| r.to_string()).collect()),
+            ..Caller::new(user)
+        }
+    }
+
+    fn allowed(s: &Storage, caller: &Caller, required: Required) -> bool {
+        check_privileges(s, caller, &[required], "test").is_ok()
+    }
+
+    fn command_allowed(s: &Storage, caller: &Caller, json: &str) -> bool {
+        let cmd: Command = serde_json::from_str(json).unwrap();
+        check_privileges(s, caller, &required_privileges(&cmd), "test").is_ok()
+    }
+
+    fn share(user: Option<&str>, role: Option<&str>, level: ShareLevel) -> SpaceShare {
+        SpaceShare {
+            user_name: user.map(str::to_string),
+            role_name: role.map(str::to_string),
+            level,
+        }
+    }
+
+    // alice が所有する mine と、bob・dave・team ロールを用意する
+    // dave は削除済みで、dave が作った orphan は所有者の居ない Space になる
+    fn fixture(roles: &[(&str, Privileges)]) -> TempStorage {
+        let s = TempStorage::default();
+        s.create_space("mine", "alice").unwrap();
+        s.create_user("bob", "bob-password", &[]).unwrap();
+        s.create_user("dave", "dave-password", &[]).unwrap();
+        s.create_space("orphan", "dave").unwrap();
+        s.drop_user("dave").unwrap();
+        let empty = Privileges {
+            database: None,
+            spaces: Vec::new(),
+            keys: Vec::new(),
+        };
+        s.create_role("team", &empty).unwrap();
+        for (name, privileges) in roles {
+            s.create_role(name, privileges).unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn builtin_role_matrix() {
+        use Required::{Database, Key, Space, SpaceOwner, SpaceWrite};
+        let cases = [
+            (
+                Database(CommandDatabase::CreateSpace),
+                [true, true, false, true],
+            ),
+            (
+                Database(CommandDatabase::CloneSpace),
+                [true, true, false, true],
+            ),
+            (
+                Database(CommandDatabase::ImportSpace),
+                [true, false, false, true],
+            ),
+            (
+                Database(CommandDatabase::ShowSpaces),
+                [true, true, true, true],
+            ),
+            (Database(CommandDatabase::Version), [true, true, true, true]),
+            (
+                SpaceOwner("s", CommandDatabase::DropSpace),
+                [true, false, false, true],
+            ),
+            (
+                Space("s", CommandSpace::InfoSpace),
+                [true, false, true, true],
+            ),
+            (
+                Space("s", CommandSpace::CreateKey),
+                [true, false, false, true],
+            ),
+            (
+                Key("s", "k", CommandKey::SelectValue),
+                [true, false, true, true],
+            ),
+            (
+                Key("s", "k", CommandKey::InsertValue),
+                [true, false, false, true],
+            ),
+            (
+                Key("s", "k", CommandKey::DropKey),
+                [true, false, false, true],
+            ),
+            (SpaceWrite("s"), [true, false, false, true]),
+        ];
+        for (required, expected) in cases {
+            for (name, expected) in ["admin", "writer", "reader", "legacy"]
+                .into_iter()
+                .zip(expected)
+            {
+                assert_eq!(
+                    privileges_allow(&role(name), &required),
+                    expected,
+                    "{name} {:?}",
+                    required.space_name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn writer_writes_only_owned_or_shared_spaces() {
+        let s = fixture(&[]);
+        let alice = caller("alice", &["writer"]);
+        let bob = caller("bob", &["writer"]);
+        let insert = || Required::Key("mine", "k", CommandKey::InsertValue);
+        let info = || Required::Space("mine", CommandSpace::InfoSpace);
+        let drop = || Required::SpaceOwner("mine", CommandDatabase::DropSpace);
+
+        assert!(allowed(&s, &alice, insert()));
+        assert!(allowed(&s, &alice, drop()));
+        assert!(!allowed(&s, &bob, insert()));
+        assert!(!allowed(&s, &bob, info()));
+        assert!(!allowed(&s, &bob, drop()));
+
+        s.share_space("mine", share(Some("bob"), None, ShareLevel::Read))
+            .unwrap();
+        assert!(allowed(&s, &bob, info()));
+        assert!(!allowed(&s, &bob, insert()));
+
+        s.share_space("mine", share(None, Some("team"), ShareLevel::Write))
+            .unwrap();
+        let carol = caller("carol", &["writer", "team"]);
+        assert!(allowed(&s, &carol, insert()));
+        // 共有されても所有者にはならない
+        assert!(!allowed(&s, &carol, drop()));
+        assert!(allowed(&s, &caller("root", &["admin"]), drop()));
+    }
+
+    #[test]
+    fn shares_and_ownership_decide_visibility() {
+        let s = fixture(&[]);
+        s.share_space("mine", share(None, Some("team"), ShareLevel::Read))
+            .unwrap();
+
+        let visible = |caller: &Caller| -> Vec<String> {
+            let privileges = s.role_privileges(caller.roles.as_deref().unwrap()).unwrap();
+            s.show_spaces()
+                .unwrap()
+                .into_iter()
+                .filter(|(name, acl)| space_visible(&privileges, caller, name, acl))
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(visible(&caller("alice", &["writer"])), ["mine"]);
+        assert_eq!(visible(&caller("carol", &["writer", "team"])), ["mine"]);
+        assert!(visible(&caller("bob", &["writer"])).is_empty());
+        assert_eq!(s.space_acl("orphan").unwrap().owner, None);
+        assert!(visible(&caller("dave", &["writer"])).is_empty());
+        assert_eq!(visible(&caller("bob", &["reader"])), ["mine", "orphan"]);
+    }
+
+    #[test]
+    fn import_space_needs_write_access_to_an_existing_space() {
+        let importer = Privileges {
+            database: Some(AllOrChoose::Choose(vec![CommandDatabase::ImportSpace])),
+            spaces: Vec::new(),
+            keys: Vec::new(),
+        };
+        let s = fixture(&[("importer", importer)]);
+        let bob = caller("bob", &["importer"]);
+        let import =
+            |space: &str| format!(r#"{{"importSpace":{{"space_name":"{space}","data":""}}}}"#);
+
+        // 新しい Space は作成者が所有者になる
+        assert!(command_allowed(&s, &bob, &import("fresh")));
+        assert!(!command_allowed(&s, &bob, &import("mine")));
+        assert!(!command_allowed(&s, &bob, &import("orphan")));
+
+        s.share_space("mine", share(Some("bob"), None, ShareLevel::Read))
+            .unwrap();
+        assert!(!command_allowed(&s, &bob, &import("mine")));
+        s.share_space("mine", share(Some("bob"), None, ShareLevel::Write))
+            .unwrap();
+        assert!(command_allowed(&s, &bob, &import("mine")));
+        assert!(command_allowed(
+            &s,
+            &caller("alice", &["importer"]),
+            &import("mine")
+        ));
+    }
+
+    #[test]
+    fn copy_key_needs_update_and_delete_on_the_target() {
+        let keys = |commands: Vec<CommandKey>| Privileges {
+            database: None,
+            spaces: Vec::new(),
+            keys: vec![KeyPrivilege {
+                target_space: AllOrChoose::All,
+                target_key: AllOrChoose::All,
+                command: AllOrChoose::Choose(commands),
+            }],
+        };
+        let s = fixture(&[
+            (
+                "inserter",
+                keys(vec![CommandKey::SelectValue, CommandKey::InsertValue]),
+            ),
+            (
+                "editor",
+                keys(vec![
+                    CommandKey::SelectValue,
+                    CommandKey::InsertValue,
+                    CommandKey::UpdateValue,
+                    CommandKey::DeleteValue,
+                ]),
+            ),
+        ]);
+        let copy = r#"{"copyKey":{"space_name":"mine","key_name":"a","to_key_name":"b"}}"#;
+
+        assert!(!command_allowed(&s, &caller("bob", &["inserter"]), copy));
+        assert!(command_allowed(&s, &caller("bob", &["editor"]), copy));
+        // 所有者はコピー先の全コマンドを持つ
+        assert!(command_allowed(&s, &caller("alice", &["writer"]), copy));
+        assert!(!command_allowed(&s, &caller("bob", &["writer"]), copy));
+    }
+
+    #[test]
+    fn users_from_before_roles_keep_access_to_every_space() {
+        let s = fixture(&[]);
+        // ロール導入前の user DB の値には roles が無い
+        let record = serde_json::json!({"password_hash": "x", "must_change_password": false});
+        let mut txn = s.env.begin_rw_txn().unwrap();
+        txn.put(
+            s.user,
+            &"old",
+            &record.to_string(),
+            lmdb::WriteFlags::empty(),
+        )
+        .unwrap();
+        lmdb::Transaction::commit(txn).unwrap();
+
+        let old = resolve_roles(&s, &Caller::new("old")).unwrap();
+        assert_eq!(old.roles.as_deref().unwrap(), ["legacy"]);
+        assert!(!old.is_admin());
+        for space in ["mine", "orphan"] {
+            assert!(allowed(&s, &old, Required::SpaceWrite(space)));
+            assert!(allowed(
+                &s,
+                &old,
+                Required::SpaceOwner(space, CommandDatabase::DropSpace)
+            ));
+        }
+
+        // ロールを付与しても legacy は残る
+        s.grant_role("old", "reader").unwrap();
+        assert_eq!(s.user_roles("old").unwrap(), ["legacy", "reader"]);
+    }
+}
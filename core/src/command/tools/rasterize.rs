@@ -0,0 +1,230 @@
+use std::{collections::HashSet, f64::consts::PI};
+
+use kasane_logic::{
+    function::{line::line, point::point},
+    id::{DimensionRange, SpaceTimeId, coordinates::Point},
+};
+
+use crate::{
+    command::tools::spot::check_point,
+    io::tools::range::range,
+    json::input::{GeoJsonGeometry, IdInput, Range},
+};
+
+// 1つの Geometry が覆える xy セル数の上限
+const MAX_CELLS: u64 = 1_000_000;
+
+// Geometry を覆う zoom の xy セルを求め、高度の範囲の f と組み合わせたビット列にする
+pub fn rasterize(
+    zoom: u8,
+    geometry: &GeoJsonGeometry,
+    altitude_min: f64,
+    altitude_max: f64,
+) -> Result<Vec<Vec<u8>>, String> {
+    let (f_min, f_max) = f_range(zoom, altitude_min, altitude_max)?;
+    let f = if f_min == f_max {
+        DimensionRange::Single(f_min)
+    } else {
+        DimensionRange::LimitRange(f_min, f_max)
+    };
+
+    let ids = cells(zoom, geometry)?
+        .into_iter()
+        .map(|(x, y)| IdInput {
+            z: zoom,
+            f,
+            x: DimensionRange::Single(x),
+            y: DimensionRange::Single(y),
+            i: 0,
+            t: DimensionRange::Any,
+        })
+        .collect();
+    let mut bits = range(Range::IdSet(ids))?;
+    bits.sort();
+    Ok(bits)
+}
+
+// 高度 [m] の範囲を f の範囲（両端含む）にする。上端ちょうどのセルは含めない
+fn f_range(zoom: u8, altitude_min: f64, altitude_max: f64) -> Result<(i32, i32), String> {
+    if altitude_min > altitude_max {
+        return Err(format!(
+            "altitude_min {} is greater than altitude_max {}",
+            altitude_min, altitude_max
+        ));
+    }
+    for altitude in [altitude_min, altitude_max] {
+        check_point(
+            zoom,
+            &Point {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude,
+            },
+        )?;
+    }
+
+    let factor = 2_f64.powi(zoom as i32 - 25);
+    let f_min = (altitude_min * factor).floor() as i32;
+    let f_max = ((altitude_max * factor).ceil() as i32 - 1).max(f_min);
+    Ok((f_min, f_max))
+}
+
+fn cells(zoom: u8, geometry: &GeoJsonGeometry) -> Result<HashSet<(u32, u32)>, String> {
+    let mut cells = HashSet::new();
+    match geometry {
+        GeoJsonGeometry::Point(p) => insert_point(zoom, &position(zoom, p)?, &mut cells),
+        GeoJsonGeometry::MultiPoint(ps) => {
+            for p in ps {
+                insert_point(zoom, &position(zoom, p)?, &mut cells);
+            }
+        }
+        GeoJsonGeometry::LineString(ps) => insert_path(zoom, &positions(zoom, ps)?, &mut cells)?,
+        GeoJsonGeometry::MultiLineString(lines) => {
+            for ps in lines {
+                insert_path(zoom, &positions(zoom, ps)?, &mut cells)?;
+            }
+        }
+        GeoJsonGeometry::Polygon(rings) => insert_polygon(zoom, rings, &mut cells)?,
+        GeoJsonGeometry::MultiPolygon(polygons) => {
+            for rings in polygons {
+                insert_polygon(zoom, rings, &mut cells)?;
+            }
+        }
+    }
+    Ok(cells)
+}
+
+// GeoJSON の座標は [経度, 緯度, 高度?]。高さは altitude_min / max で決めるので使わない
+fn position(zoom: u8, p: &[f64]) -> Result<Point, String> {
+    let [longitude, latitude, ..] = p[..] else {
+        return Err(format!("Invalid position {:?}", p));
+    };
+    let point = Point {
+        latitude,
+        longitude,
+        altitude: 0.0,
+    };
+    check_point(zoom, &point)?;
+    Ok(point)
+}
+
+fn positions(zoom: u8, ps: &[Vec<f64>]) -> Result<Vec<Point>, String> {
+    ps.iter().map(|p| position(zoom, p)).collect()
+}
+
+fn insert_id(id: SpaceTimeId, cells: &mut HashSet<(u32, u32)>) {
+    for p in id.pure() {
+        cells.insert((p.x, p.y));
+    }
+}
+
+fn insert_point(zoom: u8, p: &Point, cells: &mut HashSet<(u32, u32)>) {
+    insert_id(point(zoom, *p), cells);
+}
+
+fn insert_path(zoom: u8, ps: &[Point], cells: &mut HashSet<(u32, u32)>) -> Result<(), String> {
+    if let [p] = ps {
+        insert_point(zoom, p, cells);
+    }
+    for pair in ps.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        // 同じ点同士だと line の分割数が 0 になる
+        if a.latitude == b.latitude && a.longitude == b.longitude {
+            insert_point(zoom, &a, cells);
+            continue;
+        }
+
+        let (ax, ay) = tile_xy(zoom, &a);
+        let (bx, by) = tile_xy(zoom, &b);
+        if (ax - bx).abs() + (ay - by).abs() > MAX_CELLS as f64 {
+            return Err("Line covers too many cells".to_string());
+        }
+        for id in line(zoom, a, b) {
+            insert_id(id, cells);
+        }
+    }
+    Ok(())
+}
+
+// 境界が通るセルと、中心がポリゴン内にあるセルで覆う
+fn insert_polygon(
+    zoom: u8,
+    rings: &[Vec<Vec<f64>>],
+    cells: &mut HashSet<(u32, u32)>,
+) -> Result<(), String> {
+    let rings = rings
+        .iter()
+        .map(|ring| positions(zoom, ring))
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(outer) = rings.first() else {
+        return Err("Polygon has no rings".to_string());
+    };
+    if rings.iter().any(|ring| ring.len() < 4) {
+        return Err("Polygon ring must have at least 4 positions".to_string());
+    }
+
+    let n = 2_f64.powi(zoom as i32);
+    let (mut x_min, mut y_min, mut x_max, mut y_max) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for p in outer {
+        let (x, y) = tile_xy(zoom, p);
+        x_min = x_min.min(x);
+        y_min = y_min.min(y);
+        x_max = x_max.max(x);
+        y_max = y_max.max(y);
+    }
+    let (x_min, y_min) = (x_min.floor() as u32, y_min.floor() as u32);
+    let (x_max, y_max) = (
+        (x_max.floor() as u32).min(n as u32 - 1),
+        (y_max.floor() as u32).min(n as u32 - 1),
+    );
+    if (x_max - x_min + 1) as u64 * (y_max - y_min + 1) as u64 > MAX_CELLS {
+        return Err("Polygon covers too many cells".to_string());
+    }
+
+    for ring in &rings {
+        insert_path(zoom, ring, cells)?;
+    }
+    for y in y_min..=y_max {
+        let latitude = (PI * (1.0 - 2.0 * (y as f64 + 0.5) / n))
+            .sinh()
+            .atan()
+            .to_degrees();
+        for x in x_min..=x_max {
+            let longitude = (x as f64 + 0.5) / n * 360.0 - 180.0;
+            if contains(&rings, longitude, latitude) {
+                cells.insert((x, y));
+            }
+        }
+    }
+    Ok(())
+}
+
+// 経緯度を zoom のタイル座標（小数）にする。point_to_id と同じ式
+fn tile_xy(zoom: u8, p: &Point) -> (f64, f64) {
+    let n = 2_f64.powi(zoom as i32);
+    let lat = p.latitude.to_radians();
+    let x = (p.longitude + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n;
+    (x, y)
+}
+
+// 偶奇規則の内外判定。2番目以降のリングは穴になる
+fn contains(rings: &[Vec<Point>], longitude: f64, latitude: f64) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        let mut j = ring.len() - 1;
+        for i in 0..ring.len() {
+            let (a, b) = (&ring[i], &ring[j]);
+            if (a.latitude > latitude) != (b.latitude > latitude)
+                && longitude
+                    < (b.longitude - a.longitude) * (latitude - a.latitude)
+                        / (b.latitude - a.latitude)
+                        + a.longitude
+            {
+                inside = !inside;
+            }
+            j = i;
+        }
+    }
+    inside
+}
@@ -0,0 +1,12 @@
+use crate::{auth::Caller, error::Error};
+
+// admin 専用コマンドの実行者を確認する
+pub fn require_admin(caller: &Caller, location: &'static str) -> Result<(), Error> {
+    if !caller.is_admin() {
+        return Err(Error::PermissionDenied {
+            user_name: caller.user.clone(),
+            location,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use crate::{
+    auth::Caller,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+};
+
+// Space の所有者か admin であることを確認する
+pub fn require_owner(
+    s: &Storage,
+    caller: &Caller,
+    space_name: &str,
+    location: &'static str,
+) -> Result<(), Error> {
+    let acl = s.space_acl(space_name)?;
+    if !caller.is_admin() && acl.owner.as_deref() != Some(caller.user.as_str()) {
+        return Err(Error::PermissionDenied {
+            user_name: caller.user.clone(),
+            location,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use crate::json::input::{Command, CommandCategory, TokenScope};
+
+pub fn command_category(cmd: &Command) -> CommandCategory {
+    match cmd {
+        Command::CreateSpace(_)
+        | Command::DropSpace(_)
+        | Command::RenameSpace(_)
+        | Command::CloneSpace(_)
+        | Command::ExportSpace(_)
+        | Command::ImportSpace(_)
+        | Command::InfoSpace(_)
+        | Command::ShareSpace(_)
+        | Command::UnshareSpace(_)
+        | Command::ShowSpaces
+        | Command::Version => CommandCategory::Database,
+
+        Command::CreateKey(_)
+        | Command::DropKey(_)
+        | Command::RenameKey(_)
+        | Command::AlterKeyType(_)
+        | Command::CopyKey(_)
+        | Command::ShowKeys(_)
+        | Command::InfoKey(_) => CommandCategory::Key,
+
+        Command::InsertValue(_)
+        | Command::PatchValue(_)
+        | Command::UpdateValue(_)
+        | Command::DeleteValue(_)
+        | Command::SelectValue(_)
+        | Command::ShowValues(_)
+        | Command::SelectPyramid(_)
+        | Command::ImportCsv(_)
+        | Command::ImportGeoJson(_)
+        | Command::ImportAsciiGrid(_)
+        | Command::ValueHistory(_) => CommandCategory::Value,
+
+        Command::CreateUser(_)
+        | Command::DropUser(_)
+        | Command::ChangePassword(_)
+        | Command::ResetPassword(_)
+        | Command::UnlockUser(_)
+        | Command::InfoUser(_)
+        | Command::ShowUsers
+        | Command::CreateRole(_)
+        | Command::DropRole(_)
+        | Command::GrantRole(_)
+        | Command::RevokeRole(_)
+        | Command::ShowRoles
+        | Command::ShowAudit(_) => CommandCategory::User,
+
+        Command::ShowSessions
+        | Command::KillSession(_)
+        | Command::KillUserSessions(_)
+        | Command::CreateToken(_)
+        | Command::ShowTokens
+        | Command::RevokeToken(_) => CommandCategory::Session,
+    }
+}
+
+// コマンドが読み書きする Space（新しい名前やコピー先も含む）
+pub fn command_spaces(cmd: &Command) -> Vec<&str> {
+    match cmd {
+        Command::CreateSpace(v) => vec![&v.space_name],
+        Command::DropSpace(v) => vec![&v.space_name],
+        Command::RenameSpace(v) => vec![&v.space_name, &v.new_space_name],
+        Command::CloneSpace(v) => vec![&v.space_name, &v.new_space_name],
+        Command::ExportSpace(v) => vec![&v.space_name],
+        Command::ImportSpace(v) => vec![&v.space_name],
+        Command::InfoSpace(v) => vec![&v.space_name],
+        Command::ShareSpace(v) => vec![&v.space_name],
+        Command::UnshareSpace(v) => vec![&v.space_name],
+        Command::CreateKey(v) => vec![&v.space_name],
+        Command::DropKey(v) => vec![&v.space_name],
+        Command::RenameKey(v) => vec![&v.space_name],
+        Command::AlterKeyType(v) => vec![&v.space_name],
+        Command::CopyKey(v) => {
+            let mut spaces = vec![v.space_name.as_str()];
+            spaces.extend(v.to_space_name.as_deref());
+            spaces
+        }
+        Command::ShowKeys(v) => vec![&v.space_name],
+        Command::InfoKey(v) => vec![&v.space_name],
+        Command::InsertValue(v) => vec![&v.space_name],
+        Command::PatchValue(v) => vec![&v.space_name],
+        Command::UpdateValue(v) => vec![&v.space_name],
+        Command::DeleteValue(v) => vec![&v.space_name],
+        Command::SelectValue(v) => vec![&v.space_name],
+        Command::ShowValues(v) => vec![&v.space_name],
+        Command::SelectPyramid(v) => vec![&v.space_name],
+        Command::ImportCsv(v) => vec![&v.space_name],
+        Command::ImportGeoJson(v) => vec![&v.space_name],
+        Command::ImportAsciiGrid(v) => vec![&v.space_name],
+        Command::ValueHistory(v) => vec![&v.space_name],
+        _ => Vec::new(),
+    }
+}
+
+// API トークンのスコープでコマンドを実行できるか
+pub fn scope_allows(scope: &TokenScope, cmd: &Command) -> bool {
+    scope_allows_space(scope, command_category(cmd), &command_spaces(cmd))
+}
+
+pub fn scope_allows_space(
+    scope: &TokenScope,
+    category: CommandCategory,
+    space_names: &[&str],
+) -> bool {
+    if let Some(categories) = &scope.categories
+        && !categories.contains(&category)
+    {
+        return false;
+    }
+    // Space を絞ったトークンでは、Space を持たないコマンド（CreateToken 等）は実行できない
+    if let Some(spaces) = &scope.spaces {
+        return !space_names.is_empty()
+            && space_names
+                .iter()
+                .all(|name| spaces.iter().any(|s| s == name));
+    }
+    true
+}
+
+// scope が outer より広くないか（API トークンから作るトークンは元のスコープ内に限る）
+pub fn scope_within(scope: &TokenScope, outer: &TokenScope) -> bool {
+    fn within<T: PartialEq>(inner: &Option<Vec<T>>, outer: &Option<Vec<T>>) -> bool {
+        match (inner, outer) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(inner), Some(outer)) => inner.iter().all(|v| outer.contains(v)),
+        }
+    }
+    within(&scope.spaces, &outer.spaces) && within(&scope.categories, &outer.categories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::input::CreateToken;
+
+    fn scope(spaces: Option<&[&str]>, categories: Option<&[CommandCategory]>) -> TokenScope {
+        TokenScope {
+            spaces: spaces.map(|s| s.iter().map(|s| s.to_string()).collect()),
+            categories: categories.map(<[_]>::to_vec),
+        }
+    }
+
+    fn create_token() -> Command {
+        Command::CreateToken(CreateToken {
+            token_name: "t".to_string(),
+            user_name: None,
+            expires_in_secs: None,
+            scope: TokenScope::default(),
+        })
+    }
+
+    #[test]
+    fn space_scope_denies_commands_without_space() {
+        let s = scope(Some(&["a"]), None);
+        assert!(!scope_allows(&s, &create_token()));
+        assert!(!scope_allows(&s, &Command::ShowSpaces));
+        assert!(scope_allows_space(&s, CommandCategory::Value, &["a"]));
+        assert!(!scope_allows_space(&s, CommandCategory::Value, &["b"]));
+
+        // Space を絞らなければ従来どおり
+        let s = scope(None, Some(&[CommandCategory::Session]));
+        assert!(scope_allows(&s, &create_token()));
+    }
+
+    #[test]
+    fn created_scope_must_be_within_caller_scope() {
+        let outer = scope(Some(&["a", "b"]), Some(&[CommandCategory::Value]));
+        assert!(scope_within(
+            &scope(Some(&["a"]), Some(&[CommandCategory::Value])),
+            &outer
+        ));
+        assert!(!scope_within(
+            &scope(None, Some(&[CommandCategory::Value])),
+            &outer
+        ));
+        assert!(!scope_within(&scope(Some(&["a"]), None), &outer));
+        assert!(!scope_within(
+            &scope(Some(&["c"]), Some(&[CommandCategory::Value])),
+            &outer
+        ));
+        assert!(!scope_within(
+            &scope(
+                Some(&["a"]),
+                Some(&[CommandCategory::Value, CommandCategory::User])
+            ),
+            &outer
+        ));
+        // スコープの無い実行者（セッション）はどのスコープでも作れる
+        assert!(scope_within(&TokenScope::default(), &TokenScope::default()));
+    }
+
+    fn command(json: &str) -> Command {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn categories_and_every_named_space_must_match() {
+        let s = scope(Some(&["a"]), Some(&[CommandCategory::Value]));
+        let select = command(r#"{"showValues":{"space_name":"a","key_name":"k"}}"#);
+        assert!(scope_allows(&s, &select));
+        // 同じ Space でも分類が違えば拒否する
+        assert!(!scope_allows(
+            &s,
+            &command(r#"{"dropKey":{"space_name":"a","key_name":"k"}}"#)
+        ));
+
+        // CopyKey はコピー先の Space もスコープ内でなければならない
+        let s = scope(Some(&["a"]), None);
+        let copy = |to: &str| {
+            command(&format!(
+                r#"{{"copyKey":{{"space_name":"a","key_name":"k","to_space_name":"{}","to_key_name":"k"}}}}"#,
+                to
+            ))
+        };
+        assert!(scope_allows(&s, &copy("a")));
+        assert!(!scope_allows(&s, &copy("b")));
+        assert!(!scope_allows(
+            &s,
+            &command(r#"{"renameSpace":{"space_name":"a","new_space_name":"b"}}"#)
+        ));
+    }
+}
@@ -0,0 +1,38 @@
+use kasane_logic::id::coordinates::Point;
+
+use crate::{
+    io::tools::range::range,
+    json::input::{Function, Range, Spot},
+};
+
+// Web メルカトルで表せる緯度の上限
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+// f の範囲 (±2^z) に対応する高度の上限 [m]
+const MAX_ALTITUDE: f64 = 33_554_432.0;
+
+// 座標を検証してから Spot のビット列に変換する
+pub fn spot_bitmask(zoom: u8, point: Point) -> Result<Vec<Vec<u8>>, String> {
+    check_point(zoom, &point)?;
+
+    range(Range::Function(Function::Spot(Spot {
+        point1: point,
+        zoom,
+    })))
+}
+
+// 範囲外の座標は kasane_logic 側で panic するため先に弾く
+pub fn check_point(zoom: u8, point: &Point) -> Result<(), String> {
+    if zoom > 31 {
+        return Err(format!("Zoom level must be 0..=31. Got {}", zoom));
+    }
+    if !(-MAX_LATITUDE..=MAX_LATITUDE).contains(&point.latitude) {
+        return Err(format!("Latitude {} out of range", point.latitude));
+    }
+    if !(-180.0..180.0).contains(&point.longitude) {
+        return Err(format!("Longitude {} out of range", point.longitude));
+    }
+    if !(-MAX_ALTITUDE..MAX_ALTITUDE).contains(&point.altitude) {
+        return Err(format!("Altitude {} out of range", point.altitude));
+    }
+    Ok(())
+}
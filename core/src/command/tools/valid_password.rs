@@ -0,0 +1,10 @@
+// パスワードは 8 文字以上 256 バイト以下
+pub fn valid_password(password: &str) -> Result<(), &'static str> {
+    if password.chars().count() < 8 {
+        return Err("must be at least 8 characters");
+    }
+    if password.len() > 256 {
+        return Err("must be at most 256 bytes");
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use kasane_logic::id::SpaceTimeId;
+
+use crate::{
+    command::tools::{czml::czml_document, geojson::feature_collection},
+    error::Error,
+    io::{ValueMap, tools::range::bitmask_to_stid},
+    json::{
+        input::{ColorRamp, OutputFormat},
+        output::{Output, Value},
+    },
+};
+
+// SelectValue / ShowValues の結果を format に合わせて出力にする
+// format が Value のときは list で Output を作る
+pub fn value_output(
+    cells: ValueMap,
+    format: OutputFormat,
+    color_ramp: Option<&ColorRamp>,
+    list: fn(Vec<Value>) -> Output,
+) -> Result<Output, Error> {
+    let cells = cells
+        .into_iter()
+        .map(|(bits, values)| Ok((bitmask_to_stid(&bits)?, values)))
+        .collect::<Result<Vec<(SpaceTimeId, _)>, Error>>()?;
+
+    Ok(match format {
+        OutputFormat::Value => list(
+            cells
+                .into_iter()
+                .map(|(stid, value)| Value {
+                    id: stid,
+                    center: stid.center(),
+                    vertex: stid.vertex(),
+                    id_string: stid.to_string(),
+                    value,
+                })
+                .collect(),
+        ),
+        OutputFormat::Czml => Output::Czml(czml_document(cells, color_ramp)),
+        OutputFormat::GeoJsonPolygon => Output::GeoJson(feature_collection(cells, false)),
+        OutputFormat::GeoJsonPoint => Output::GeoJson(feature_collection(cells, true)),
+    })
+}
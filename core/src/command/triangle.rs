@@ -1,8 +1,6 @@
 use std::collections::HashSet;
 
-use kasane_logic::{
-    function::triangle::triangle as other_triangle, id::SpaceTimeId, set::SpaceTimeIdSet,
-};
+use kasane_logic::{function::triangle::triangle as other_triangle, id::SpaceTimeId};
 
 use crate::json::input::Triangle;
 
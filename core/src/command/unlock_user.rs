@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_admin::require_admin,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::UnlockUser, output},
+};
+
+pub fn unlock_user(
+    v: UnlockUser,
+    s: Arc<Storage>,
+    caller: &Caller,
+) -> Result<output::Output, Error> {
+    require_admin(caller, "command::unlock_user")?;
+    let locked = s.unlock_user(&v.user_name)?;
+    Ok(output::Output::UnlockUser(output::UnlockUser {
+        user_name: v.user_name,
+        locked,
+    }))
+}
@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use crate::{
+    auth::Caller,
+    command::tools::require_owner::require_owner,
+    error::Error,
+    io::{StorageTrait, full::Storage},
+    json::{input::UnshareSpace, output::Output},
+};
+
+pub fn unshare_space(v: UnshareSpace, s: Arc<Storage>, caller: &Caller) -> Result<Output, Error> {
+    require_owner(&s, caller, &v.space_name, "command::unshare_space")?;
+    if v.user_name.is_some() == v.role_name.is_some() {
+        return Err(Error::ParseError {
+            message: "Specify either user_name or role_name".to_string(),
+            location: "command::unshare_space",
+        });
+    }
+    s.unshare_space(
+        &v.space_name,
+        v.user_name.as_deref(),
+        v.role_name.as_deref(),
+    )
+}
@@ -3,18 +3,15 @@ use std::sync::Arc;
 use crate::{
     error::Error,
     io::{StorageTrait, full::Storage, tools::range::range},
-    json::{
-        input::{InsertValue, UpdateValue},
-        output::Output,
-    },
+    json::{input::UpdateValue, output::Output},
 };
 
-pub fn update_value(v: UpdateValue, s: Arc<Storage>) -> Result<Output, Error> {
+pub fn update_value(v: UpdateValue, s: Arc<Storage>, user: &str) -> Result<Output, Error> {
     let range = match range(v.range) {
         Ok(v) => v,
         Err(e) => {
             return Err(Error::RangeError { message: e });
         }
     };
-    s.update_value(&v.space_name, &v.key_name, range, v.value)
+    s.update_value(&v.space_name, &v.key_name, range, v.value, user)
 }
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    io::{
+        StorageTrait,
+        full::Storage,
+        tools::range::{bitmask_to_stid, range},
+    },
+    json::{
+        input::ValueHistory,
+        output::{HistoryValue, Output},
+    },
+};
+
+pub fn value_history(v: ValueHistory, s: Arc<Storage>) -> Result<Output, Error> {
+    let range = match range(v.range) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Error::RangeError { message: e });
+        }
+    };
+    let a = s.value_history(&v.space_name, &v.key_name, range)?;
+
+    let mut result = vec![];
+
+    for (bits, entry) in a {
+        let stid = bitmask_to_stid(&bits)?;
+
+        result.push(HistoryValue {
+            id_string: stid.to_string(),
+            timestamp: entry.timestamp,
+            user: entry.user,
+            old: entry.old,
+            new: entry.new,
+        });
+    }
+
+    Ok(Output::ValueHistory(result))
+}
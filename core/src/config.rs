@@ -0,0 +1,542 @@
+use std::{fmt, fs, net::ToSocketAddrs, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::{command::tools::valid_password::valid_password, io::full::NAMED_DBS};
+
+// 設定の優先順位: コマンドライン > 環境変数 > 設定ファイル > 既定値
+#[derive(Parser, Debug)]
+#[command(version, about = "Kasane spatio-temporal database server")]
+pub struct Cli {
+    /// Config file (TOML). Defaults to ./kasane.toml if present
+    #[arg(short, long, env = "KASANE_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on [server.bind]
+    #[arg(long, env = "KASANE_BIND")]
+    pub bind: Option<String>,
+    /// Port to listen on [server.port]
+    #[arg(long, env = "KASANE_PORT")]
+    pub port: Option<u16>,
+    /// Worker threads, defaults to the CPU count [server.workers]
+    #[arg(long, env = "KASANE_WORKERS")]
+    pub workers: Option<usize>,
+    /// Maximum concurrent sessions [server.max_sessions]
+    #[arg(long, env = "KASANE_MAX_SESSIONS")]
+    pub max_sessions: Option<usize>,
+    /// Idle session timeout in seconds [server.session_timeout_secs]
+    #[arg(long, env = "KASANE_SESSION_TIMEOUT_SECS")]
+    pub session_timeout_secs: Option<u64>,
+    /// Depth of the command job queue [server.job_queue_size]
+    #[arg(long, env = "KASANE_JOB_QUEUE_SIZE")]
+    pub job_queue_size: Option<usize>,
+    /// Body limit in bytes for /import/csv and /import/grid [server.max_upload_size]
+    #[arg(long, env = "KASANE_MAX_UPLOAD_SIZE")]
+    pub max_upload_size: Option<usize>,
+    /// Body limit in bytes for the other endpoints [server.max_request_size]
+    #[arg(long, env = "KASANE_MAX_REQUEST_SIZE")]
+    pub max_request_size: Option<usize>,
+    /// Seconds between expired value sweeps [server.expiry_sweep_interval_secs]
+    #[arg(long, env = "KASANE_EXPIRY_SWEEP_INTERVAL_SECS")]
+    pub expiry_sweep_interval_secs: Option<u64>,
+    /// Comma-separated proxy IPs or CIDRs whose X-Forwarded-For is trusted [server.trusted_proxies]
+    #[arg(long, env = "KASANE_TRUSTED_PROXIES", value_delimiter = ',')]
+    pub trusted_proxies: Option<Vec<String>>,
+    /// LMDB directory, defaults to the current directory [storage.path]
+    #[arg(long, env = "KASANE_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// LMDB map size in bytes [storage.map_size]
+    #[arg(long, env = "KASANE_MAP_SIZE")]
+    pub map_size: Option<usize>,
+    /// LMDB named database limit [storage.max_dbs]
+    #[arg(long, env = "KASANE_MAX_DBS")]
+    pub max_dbs: Option<u32>,
+    /// Log directory [log.dir]
+    #[arg(long, env = "KASANE_LOG_DIR")]
+    pub log_dir: Option<PathBuf>,
+    /// Log file size before rotation in bytes [log.max_size]
+    #[arg(long, env = "KASANE_LOG_MAX_SIZE")]
+    pub log_max_size: Option<u64>,
+    /// Log level spec, e.g. info or debug [log.level]
+    #[arg(long, env = "KASANE_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Initial admin password, used only when admin does not exist yet [admin.password]
+    #[arg(long, env = "KASANE_ADMIN_PASSWORD", hide_env_values = true)]
+    pub admin_password: Option<String>,
+    /// File the generated admin password is written to [admin.password_file]
+    #[arg(long, env = "KASANE_ADMIN_PASSWORD_FILE")]
+    pub admin_password_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub log: LogConfig,
+    pub admin: AdminConfig,
+    pub login: LoginConfig,
+    pub jwt: JwtConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    //省略時は CPU コア数
+    pub workers: Option<usize>,
+    pub max_sessions: usize,
+    pub session_timeout_secs: u64,
+    pub job_queue_size: usize,
+    //アップロード（/import/csv・/import/grid）の本文上限 [byte]
+    pub max_upload_size: usize,
+    //それ以外のエンドポイント（/execute の ImportSpace なども含む）の本文上限 [byte]
+    pub max_request_size: usize,
+    //期限切れの値を掃除する間隔 [秒]
+    pub expiry_sweep_interval_secs: u64,
+    //X-Forwarded-For を信用するリバースプロキシ（IP か CIDR）。空ならソケットの接続元を使う
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+            max_sessions: 100,
+            session_timeout_secs: 3600,
+            job_queue_size: 1000,
+            max_upload_size: 64 * 1024 * 1024,
+            max_request_size: 4 * 1024 * 1024,
+            expiry_sweep_interval_secs: 5,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn session_timeout(&self) -> Duration {
+        Duration::from_secs(self.session_timeout_secs)
+    }
+
+    pub fn expiry_sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.expiry_sweep_interval_secs)
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or_else(num_cpus::get)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    //LMDB のディレクトリ。省略時はカレントディレクトリ
+    pub path: Option<PathBuf>,
+    pub map_size: usize,
+    pub max_dbs: u32,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            path: None,
+            map_size: 1024 * 1024 * 1024, // 1GB
+            max_dbs: 16,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub dir: PathBuf,
+    pub max_size: u64,
+    pub keep_files: usize,
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            dir: PathBuf::from("logs"),
+            max_size: 10_000_000, // 10MB
+            keep_files: 5,
+            level: "info".to_string(),
+        }
+    }
+}
+
+// 初回起動時の admin の作り方。password が無ければ生成して password_file に書き出す
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub password: Option<String>,
+    pub password_file: PathBuf,
+    //初回ログイン後にパスワード変更を求める
+    pub force_password_change: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            password: None,
+            password_file: PathBuf::from("admin_password.txt"),
+            force_password_change: true,
+        }
+    }
+}
+
+// 起動ログにパスワードを出さない
+impl fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("password_file", &self.password_file)
+            .field("force_password_change", &self.force_password_change)
+            .finish()
+    }
+}
+
+// /login の総当たり対策。ユーザー名ごと・接続元 IP ごとに失敗を数える
+// free_attempts 回を超えた失敗からは待ち時間を倍々に延ばし、lockout_threshold 回で lockout_secs だけ止める
+// IP は共有されることがあるので ip_free_attempts / ip_lockout_threshold を別に持つ
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginConfig {
+    pub free_attempts: u32,
+    pub ip_free_attempts: u32,
+    pub backoff_base_secs: u64,
+    pub backoff_max_secs: u64,
+    pub lockout_threshold: u32,
+    pub ip_lockout_threshold: u32,
+    pub lockout_secs: u64,
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            free_attempts: 3,
+            ip_free_attempts: 10,
+            backoff_base_secs: 1,
+            backoff_max_secs: 300,
+            lockout_threshold: 10,
+            ip_lockout_threshold: 50,
+            lockout_secs: 900,
+        }
+    }
+}
+
+// Authorization: Bearer の JWT を検証する鍵。keys が空なら JWT は受け付けない
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    pub keys: Vec<JwtKeyConfig>,
+    //ユーザー名を取るクレーム
+    pub user_claim: String,
+    //権限を取るクレーム（文字列の配列か空白区切り）
+    pub roles_claim: Option<String>,
+    //true ならクレームの値を同名のローカルユーザーとして扱い、roles クレームが無ければその権限を使う
+    //false（既定）ならユーザー名に "jwt:" を付け、権限は roles クレームだけで決める
+    pub local_users: bool,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    //exp / nbf の許容誤差 [秒]
+    pub leeway_secs: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            keys: Vec::new(),
+            user_claim: "sub".to_string(),
+            roles_claim: None,
+            local_users: false,
+            issuer: None,
+            audience: None,
+            leeway_secs: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+    ES256,
+}
+
+// HS256 は secret、RS256 / ES256 は PEM の公開鍵ファイルを指定する
+// kid を指定した鍵は JWT ヘッダの kid が一致するときだけ使う
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtKeyConfig {
+    pub algorithm: JwtAlgorithm,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub public_key_file: Option<PathBuf>,
+}
+
+// 起動ログに secret を出さない
+impl fmt::Debug for JwtKeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKeyConfig")
+            .field("algorithm", &self.algorithm)
+            .field("kid", &self.kid)
+            .field("secret", &self.secret.as_ref().map(|_| "***"))
+            .field("public_key_file", &self.public_key_file)
+            .finish()
+    }
+}
+
+const DEFAULT_CONFIG_FILE: &str = "kasane.toml";
+
+impl Config {
+    // 設定ファイルを読み、コマンドライン・環境変数で上書きして検証する
+    pub fn load(cli: Cli) -> Result<Config, String> {
+        let path = match cli.config {
+            Some(path) => Some(path),
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+                path.exists().then_some(path)
+            }
+        };
+        let mut config = match path {
+            Some(path) => {
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+                toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+
+        let server = &mut config.server;
+        if let Some(v) = cli.bind {
+            server.bind = v;
+        }
+        if let Some(v) = cli.port {
+            server.port = v;
+        }
+        if let Some(v) = cli.workers {
+            server.workers = Some(v);
+        }
+        if let Some(v) = cli.max_sessions {
+            server.max_sessions = v;
+        }
+        if let Some(v) = cli.session_timeout_secs {
+            server.session_timeout_secs = v;
+        }
+        if let Some(v) = cli.job_queue_size {
+            server.job_queue_size = v;
+        }
+        if let Some(v) = cli.max_upload_size {
+            server.max_upload_size = v;
+        }
+        if let Some(v) = cli.max_request_size {
+            server.max_request_size = v;
+        }
+        if let Some(v) = cli.expiry_sweep_interval_secs {
+            server.expiry_sweep_interval_secs = v;
+        }
+        if let Some(v) = cli.trusted_proxies {
+            server.trusted_proxies = v;
+        }
+        let storage = &mut config.storage;
+        if let Some(v) = cli.data_dir {
+            storage.path = Some(v);
+        }
+        if let Some(v) = cli.map_size {
+            storage.map_size = v;
+        }
+        if let Some(v) = cli.max_dbs {
+            storage.max_dbs = v;
+        }
+        let log = &mut config.log;
+        if let Some(v) = cli.log_dir {
+            log.dir = v;
+        }
+        if let Some(v) = cli.log_max_size {
+            log.max_size = v;
+        }
+        if let Some(v) = cli.log_level {
+            log.level = v;
+        }
+        let admin = &mut config.admin;
+        if let Some(v) = cli.admin_password {
+            admin.password = Some(v);
+        }
+        if let Some(v) = cli.admin_password_file {
+            admin.password_file = v;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let server = &self.server;
+        (server.bind.as_str(), server.port)
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid bind address {}: {}", server.bind, e))?;
+        if server.workers == Some(0) {
+            return Err("server.workers must be at least 1".to_string());
+        }
+        if server.max_sessions == 0 {
+            return Err("server.max_sessions must be at least 1".to_string());
+        }
+        if server.session_timeout_secs == 0 {
+            return Err("server.session_timeout_secs must be at least 1".to_string());
+        }
+        if server.job_queue_size == 0 {
+            return Err("server.job_queue_size must be at least 1".to_string());
+        }
+        if server.max_upload_size == 0 {
+            return Err("server.max_upload_size must be at least 1".to_string());
+        }
+        if server.max_request_size == 0 {
+            return Err("server.max_request_size must be at least 1".to_string());
+        }
+        if server.expiry_sweep_interval_secs == 0 {
+            return Err("server.expiry_sweep_interval_secs must be at least 1".to_string());
+        }
+
+        let storage = &self.storage;
+        if let Some(path) = &storage.path
+            && !path.is_dir()
+        {
+            return Err(format!(
+                "storage.path {} is not a directory",
+                path.display()
+            ));
+        }
+        if storage.map_size == 0 {
+            return Err("storage.map_size must be at least 1".to_string());
+        }
+        if storage.max_dbs < NAMED_DBS {
+            return Err(format!("storage.max_dbs must be at least {}", NAMED_DBS));
+        }
+
+        if self.log.max_size == 0 {
+            return Err("log.max_size must be at least 1".to_string());
+        }
+        flexi_logger::LogSpecification::parse(&self.log.level)
+            .map_err(|e| format!("Invalid log.level {}: {}", self.log.level, e))?;
+
+        if let Some(password) = &self.admin.password {
+            valid_password(password).map_err(|e| format!("admin.password {}", e))?;
+        }
+
+        let login = &self.login;
+        if login.lockout_threshold <= login.free_attempts {
+            return Err(
+                "login.lockout_threshold must be greater than login.free_attempts".to_string(),
+            );
+        }
+        if login.ip_lockout_threshold <= login.ip_free_attempts {
+            return Err(
+                "login.ip_lockout_threshold must be greater than login.ip_free_attempts"
+                    .to_string(),
+            );
+        }
+        if login.backoff_base_secs == 0 || login.backoff_max_secs < login.backoff_base_secs {
+            return Err(
+                "login.backoff_base_secs must be at least 1 and at most login.backoff_max_secs"
+                    .to_string(),
+            );
+        }
+        if login.lockout_secs == 0 {
+            return Err("login.lockout_secs must be at least 1".to_string());
+        }
+
+        if self.jwt.user_claim.is_empty() {
+            return Err("jwt.user_claim must not be empty".to_string());
+        }
+        for (i, key) in self.jwt.keys.iter().enumerate() {
+            match (key.algorithm, &key.secret, &key.public_key_file) {
+                (JwtAlgorithm::HS256, Some(secret), None) => {
+                    if secret.len() < 32 {
+                        return Err(format!("jwt.keys[{}].secret must be at least 32 bytes", i));
+                    }
+                }
+                (JwtAlgorithm::HS256, _, _) => {
+                    return Err(format!("jwt.keys[{}] (HS256) needs secret only", i));
+                }
+                (_, None, Some(_)) => {}
+                (algorithm, _, _) => {
+                    return Err(format!(
+                        "jwt.keys[{}] ({:?}) needs public_key_file only",
+                        i, algorithm
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tools::temp_storage::TempDir;
+
+    // 設定ファイルを書いて、追加の引数と一緒に読み込む
+    fn load(dir: &TempDir, toml: &str, args: &[&str]) -> Result<Config, String> {
+        let path = dir.path().join("kasane.toml");
+        fs::write(&path, toml).unwrap();
+        let mut argv = vec!["kasane", "--config", path.to_str().unwrap()];
+        argv.extend(args);
+        Config::load(Cli::try_parse_from(argv).unwrap())
+    }
+
+    #[test]
+    fn example_file_matches_the_defaults() {
+        let example = concat!(env!("CARGO_MANIFEST_DIR"), "/../kasane.example.toml");
+        let cli = Cli::try_parse_from(["kasane", "--config", example]).unwrap();
+        let config = Config::load(cli).unwrap();
+        assert_eq!(format!("{:?}", config), format!("{:?}", Config::default()));
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let dir = TempDir::default();
+        let toml = "[server]\nport = 9000\nmax_sessions = 5\n[log]\nlevel = \"debug\"\n";
+        let config = load(
+            &dir,
+            toml,
+            &["--port", "9100", "--trusted-proxies", "10.0.0.0/8,::1"],
+        )
+        .unwrap();
+
+        assert_eq!(config.server.port, 9100);
+        assert_eq!(config.server.max_sessions, 5);
+        assert_eq!(config.server.trusted_proxies, ["10.0.0.0/8", "::1"]);
+        assert_eq!(config.log.level, "debug");
+        // 書かなかった値は既定値
+        assert_eq!(config.server.session_timeout_secs, 3600);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let dir = TempDir::default();
+        let error = |toml: &str| load(&dir, toml, &[]).unwrap_err();
+
+        assert!(error("[server]\nprot = 1\n").contains("unknown field"));
+        assert!(error("[server]\nworkers = 0\n").contains("server.workers"));
+        assert!(error("[storage]\nmax_dbs = 1\n").contains("storage.max_dbs"));
+        assert!(error("[login]\nfree_attempts = 10\n").contains("login.lockout_threshold"));
+        assert!(
+            error("[[jwt.keys]]\nalgorithm = \"HS256\"\nsecret = \"short\"\n")
+                .contains("at least 32 bytes")
+        );
+        assert!(error("[[jwt.keys]]\nalgorithm = \"RS256\"\n").contains("public_key_file"));
+
+        // コマンドラインの値も検証する
+        let e = load(&dir, "", &["--session-timeout-secs", "0"]).unwrap_err();
+        assert!(e.contains("session_timeout_secs"));
+    }
+}
@@ -21,6 +21,42 @@ pub enum Error {
     UserNotFound {
         user_name: String,
     },
+    AuthenticationFailed {
+        user_name: String,
+        location: &'static str,
+    },
+    PermissionDenied {
+        user_name: String,
+        location: &'static str,
+    },
+    PasswordChangeRequired {
+        user_name: String,
+    },
+    PasswordValidationError {
+        reason: &'static str,
+        location: &'static str,
+    },
+    SessionNotFound {
+        session_id: String,
+    },
+    SessionLimitReached {
+        max_sessions: usize,
+    },
+    TokenNotFound {
+        token_id: String,
+    },
+    TooManyLoginAttempts {
+        retry_after_secs: u64,
+    },
+    RoleAlreadyExists {
+        role_name: String,
+    },
+    RoleNotFound {
+        role_name: String,
+    },
+    BuiltinRole {
+        role_name: String,
+    },
 
     // Parse errors with context
     ParseError {
@@ -91,6 +127,37 @@ pub enum Error {
         space_name: String,
         key_name: String,
     },
+    ConstraintViolation {
+        key_name: String,
+        reason: String,
+        location: &'static str,
+    },
+    InvalidConstraint {
+        key_name: String,
+        reason: String,
+        location: &'static str,
+    },
+    UnknownKeyType {
+        id: u8,
+        location: &'static str,
+    },
+    HistoryNotEnabled {
+        key_name: String,
+        location: &'static str,
+    },
+    PyramidLevelNotFound {
+        key_name: String,
+        zoom: u8,
+        location: &'static str,
+    },
+    ExportError {
+        message: String,
+        location: &'static str,
+    },
+    FeatureNotEnabled {
+        feature: &'static str,
+        location: &'static str,
+    },
     NnKnown,
 }
 
@@ -212,6 +279,114 @@ impl fmt::Display for Error {
             Error::LmdbDbNotFound { db_name, location } => {
                 write!(f, "LMDB database '{}' not found (at {})", db_name, location)
             }
+            Error::PyramidLevelNotFound {
+                key_name,
+                zoom,
+                location,
+            } => {
+                write!(
+                    f,
+                    "Key '{}' has no pyramid level at zoom {} (at {})",
+                    key_name, zoom, location
+                )
+            }
+            Error::ConstraintViolation {
+                key_name,
+                reason,
+                location,
+            } => {
+                write!(
+                    f,
+                    "Value violates constraint of key '{}': {} (at {})",
+                    key_name, reason, location
+                )
+            }
+            Error::InvalidConstraint {
+                key_name,
+                reason,
+                location,
+            } => {
+                write!(
+                    f,
+                    "Invalid constraint for key '{}': {} (at {})",
+                    key_name, reason, location
+                )
+            }
+            Error::UnknownKeyType { id, location } => {
+                write!(f, "Unknown key type id {} (at {})", id, location)
+            }
+            Error::HistoryNotEnabled { key_name, location } => {
+                write!(
+                    f,
+                    "Key '{}' does not keep value history (at {})",
+                    key_name, location
+                )
+            }
+            Error::ExportError { message, location } => {
+                write!(f, "Export failed: {} (at {})", message, location)
+            }
+            Error::FeatureNotEnabled { feature, location } => {
+                write!(
+                    f,
+                    "This build was compiled without the '{}' feature (at {})",
+                    feature, location
+                )
+            }
+            Error::AuthenticationFailed {
+                user_name,
+                location,
+            } => {
+                write!(
+                    f,
+                    "Authentication failed for user '{}' (at {})",
+                    user_name, location
+                )
+            }
+            Error::PermissionDenied {
+                user_name,
+                location,
+            } => {
+                write!(
+                    f,
+                    "User '{}' is not allowed to run this command (at {})",
+                    user_name, location
+                )
+            }
+            Error::PasswordChangeRequired { user_name } => {
+                write!(
+                    f,
+                    "User '{}' must change the password with ChangePassword first",
+                    user_name
+                )
+            }
+            Error::PasswordValidationError { reason, location } => {
+                write!(f, "Invalid password: {} (at {})", reason, location)
+            }
+            Error::SessionNotFound { session_id } => {
+                write!(f, "Session '{}' not found", session_id)
+            }
+            Error::SessionLimitReached { max_sessions } => {
+                write!(f, "Session limit reached ({} sessions)", max_sessions)
+            }
+            Error::TokenNotFound { token_id } => {
+                write!(f, "API token '{}' not found", token_id)
+            }
+            Error::TooManyLoginAttempts { retry_after_secs } => {
+                write!(
+                    f,
+                    "Too many failed login attempts. Retry after {} seconds",
+                    retry_after_secs
+                )
+            }
+            Error::RoleAlreadyExists { role_name } => {
+                write!(f, "Role '{}' already exists", role_name)
+            }
+            Error::RoleNotFound { role_name } => {
+                write!(f, "Role '{}' not found", role_name)
+            }
+            Error::BuiltinRole { role_name } => {
+                write!(f, "Built-in role '{}' cannot be changed", role_name)
+            }
             // 他の既存バリアントは省略
             _ => write!(f, "Other error"),
         }
@@ -1,35 +1,109 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     env,
-    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
 };
 
 use crate::{
+    config::Config,
     io::{
-        StorageTrait, ValueEntry,
-        tools::keytype_id::{id_keytype, keytype_id},
+        AlterReport, ApiToken, AuditEntry, HistoryEntry, ImportReport, ImportRow, KeyOption,
+        PyramidCell, Session, SpaceAcl, StorageTrait, UserRecord, ValueEntry, ValueMap,
+        ValueVisitor,
+        tools::{
+            constraint::{check_constraint, convert_constraint, validate_constraint},
+            convert::convert_value,
+            keytype_id::{id_keytype, keytype_id},
+            login_throttle::LoginThrottle,
+            prefix::{scan_before, scan_from, scan_prefix},
+            range::{
+                bitmask_to_id_string, dedup_bitmasks, id_string_to_bitmask, legacy_bitmask_to_id,
+                pure_to_bitmask,
+            },
+            role::{ADMIN_ROLE, BUILTIN_ROLES, builtin_role},
+            time::now_millis,
+            token::{API_TOKEN_LEN, API_TOKEN_PREFIX, random_token, token_hash},
+        },
     },
     json::{
-        input::{KeyMode, KeyType},
-        output::{InfoKey, InfoSpace, InfoUser, Output, ShowUsers, Showkeys},
+        input::{Aggregation, KeyMode, KeyType, OnConflict, Privileges, SpaceShare, TokenScope},
+        ndjson::SpaceRecord,
+        output::{
+            ImportSpace, InfoKey, InfoSpace, InfoUser, Output, RoleInfo, ShowRoles, ShowUsers,
+            Showkeys,
+        },
     },
 };
 use argon2::password_hash::PasswordHasher;
 use argon2::{Argon2, PasswordHash, PasswordVerifier, password_hash::SaltString};
-use kasane_logic::id::SpaceTimeId;
 use lmdb::{Cursor, DatabaseFlags, Error as LmdbError, WriteFlags};
 use rand::rngs::OsRng;
 
 use super::Error;
-use lmdb::{Database, Environment, Transaction};
+use lmdb::{Database, Environment, RwTransaction, Transaction};
 use uuid::Uuid;
 
+const HISTORY_TERMINATOR: u8 = 2;
+// Storage が開く名前付きDBの数（max_dbs の下限）
+pub const NAMED_DBS: u32 = 15;
+// value DB などのキーに使うビット列の形式
+// 0: 下位ビットから並べ、負の f は絶対値（meta DB が無かった頃の形式）
+// 1: 上位ビットから並べ、負の f は !f
+const FORMAT_VERSION: u32 = 1;
+const FORMAT_VERSION_KEY: &str = "format_version";
+// purge_expired が1つの書き込みトランザクションで消す件数の上限
+const PURGE_BATCH: usize = 1000;
+// エクスポートが1つの読み取りトランザクションで読む件数の上限
+const EXPORT_BATCH: usize = 1000;
+
 pub struct Storage {
     pub space: Database,
     pub key: Database,
     pub value: Database,
     pub user: Database,
+    pub key_option: Database,
+    pub pyramid: Database,
+    pub history: Database,
+    pub expiry: Database,
+    pub expiry_index: Database,
+    pub session: Database,
+    pub token: Database,
+    pub role: Database,
+    pub space_acl: Database,
+    pub audit: Database,
+    pub meta: Database,
     pub env: Environment,
+    // session DB の内容をメモリにも持つ
+    sessions: Mutex<SessionTable>,
+    max_sessions: usize,
+    session_timeout: Duration,
+    login_throttle: Mutex<LoginThrottle>,
+}
+
+// セッション ID -> セッション
+// 最終アクセス時刻はリクエストごとには書かず、sweep_sessions でまとめて LMDB に書く
+#[derive(Default)]
+struct SessionTable {
+    sessions: HashMap<String, Session>,
+    touched: HashSet<String>,
+}
+
+// Keyのレコード情報
+// record: [space_uuid][keyname][keytype][keymode], uuid: value DB のプレフィックス
+struct KeyRecord {
+    record: Vec<u8>,
+    uuid: Vec<u8>,
+}
+
+impl KeyRecord {
+    fn keytype(&self) -> Result<KeyType, Error> {
+        let id = self.record[self.record.len() - 2];
+        id_keytype(id).ok_or(Error::UnknownKeyType {
+            id,
+            location: "io::KeyRecord::keytype",
+        })
+    }
 }
 
 impl From<lmdb::Error> for Error {
@@ -83,444 +157,981 @@ impl TryFrom<u8> for KeyMode {
 }
 
 impl Storage {
-    pub fn new(path: Option<PathBuf>) -> Result<Self, Error> {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let server = &config.server;
+        let login = &config.login;
+        let config = &config.storage;
+        let path = match &config.path {
+            Some(path) => path.clone(),
+            None => env::current_dir().unwrap(),
+        };
         // LMDB 環境を作成
         let env = Environment::new()
-            .set_max_dbs(10) // 名前付きDBの上限
-            .set_map_size(1024 * 1024 * 1024) // 1GB
-            .open(&path.unwrap_or(env::current_dir().unwrap()))?;
+            .set_max_dbs(config.max_dbs) // 名前付きDBの上限
+            .set_map_size(config.map_size)
+            .open(&path)?;
+
+        // pyramid DB より前に作られた環境はビット列がフォーマット0のまま
+        let legacy = env.open_db(Some("space")).is_ok() && env.open_db(Some("pyramid")).is_err();
 
         // データベースを開く（なければ作成）
         let space = env.create_db(Some("space"), DatabaseFlags::empty())?;
         let key = env.create_db(Some("key"), DatabaseFlags::empty())?;
         let value = env.create_db(Some("value"), DatabaseFlags::empty())?;
         let user = env.create_db(Some("user"), DatabaseFlags::empty())?;
+        let key_option = env.create_db(Some("key_option"), DatabaseFlags::empty())?;
+        let pyramid = env.create_db(Some("pyramid"), DatabaseFlags::empty())?;
+        let history = env.create_db(Some("history"), DatabaseFlags::empty())?;
+        // expiry: 値のキー -> 期限(UNIXミリ秒 BE)
+        // expiry_index: [期限 BE][値のキー] -> keytype_id（期限順に掃除するための索引）
+        let expiry = env.create_db(Some("expiry"), DatabaseFlags::empty())?;
+        let expiry_index = env.create_db(Some("expiry_index"), DatabaseFlags::empty())?;
+        // session: トークンの SHA-256（16進） -> Session（JSON）
+        let session = env.create_db(Some("session"), DatabaseFlags::empty())?;
+        // token: API トークンの SHA-256（16進） -> ApiToken（JSON）
+        let token = env.create_db(Some("token"), DatabaseFlags::empty())?;
+        // role: ロール名 -> Privileges（JSON）。組み込みロールは保存しない
+        let role = env.create_db(Some("role"), DatabaseFlags::empty())?;
+        // space_acl: space_uuid -> SpaceAcl（JSON）
+        let space_acl = env.create_db(Some("space_acl"), DatabaseFlags::empty())?;
+        // audit: [timestamp BE][UUID] -> AuditEntry（JSON）。追記のみ
+        let audit = env.create_db(Some("audit"), DatabaseFlags::empty())?;
+        // meta: 設定名 -> 値。format_version にビット列の形式を持つ
+        let meta = env.create_db(Some("meta"), DatabaseFlags::empty())?;
+
+        // 再起動前のセッションを読み込む（期限切れは次の掃除で消える）
+        // 読めないレコードは sweep_sessions と同じく無効なセッションとして扱い、掃除で消す
+        let mut table = SessionTable::default();
+        {
+            let txn = env.begin_ro_txn()?;
+            let mut cursor = txn.open_ro_cursor(session)?;
+            for (k, v) in scan_prefix(&mut cursor, &[]) {
+                let (Ok(id), Ok(record)) =
+                    (std::str::from_utf8(k), serde_json::from_slice::<Session>(v))
+                else {
+                    log::warn!("Ignoring an unreadable session record");
+                    continue;
+                };
+                let id = id.to_string();
+                table.sessions.insert(id.clone(), Session { id, ..record });
+            }
+        }
 
         let storage = Self {
             space,
             key,
             value,
             user,
+            key_option,
+            pyramid,
+            history,
+            expiry,
+            expiry_index,
+            session,
+            token,
+            role,
+            space_acl,
+            audit,
+            meta,
             env,
+            sessions: Mutex::new(table),
+            max_sessions: server.max_sessions,
+            session_timeout: server.session_timeout(),
+            login_throttle: Mutex::new(LoginThrottle::new(login.clone())),
         };
+        storage.check_format(legacy)?;
 
-        // === 初回起動時の admin ユーザー作成 ===
-        {
-            let txn = storage.env.begin_ro_txn()?;
-            let admin_exists = txn.get(storage.user, b"admin").is_ok();
-            drop(txn);
+        Ok(storage)
+    }
 
-            if !admin_exists {
-                // デフォルトパスワードは "admin" にしておく
-                // 必要なら env から読み込むことも可能
-                storage.create_user("admin", "nekocute")?;
-                println!(
-                    "✔ 初回起動: admin ユーザーを作成しました (username=admin, password=admin)"
-                );
+    // format_version を確認し、記録が無ければ（必要なら移行してから）書き込む
+    fn check_format(&self, legacy: bool) -> Result<(), Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.get(self.meta, &FORMAT_VERSION_KEY) {
+            Ok(v) => {
+                let version = <[u8; 4]>::try_from(v)
+                    .map(u32::from_be_bytes)
+                    .map_err(|_| Error::ParseError {
+                        message: "Invalid format_version record".to_string(),
+                        location: "Storage::check_format",
+                    })?;
+                if version > FORMAT_VERSION {
+                    return Err(Error::LmdbError {
+                        message: format!(
+                            "Data format version {} is newer than supported version {}",
+                            version, FORMAT_VERSION
+                        ),
+                        location: "Storage::check_format",
+                    });
+                }
+                return Ok(());
             }
+            Err(LmdbError::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+        if legacy {
+            self.migrate_legacy_values(&mut txn)?;
         }
+        txn.put(
+            self.meta,
+            &FORMAT_VERSION_KEY,
+            &FORMAT_VERSION.to_be_bytes(),
+            WriteFlags::empty(),
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
 
-        Ok(storage)
+    // フォーマット0の value DB のキー [key_uuid][ビット列] を現在の形式に書き換える
+    fn migrate_legacy_values(&self, txn: &mut RwTransaction) -> Result<(), Error> {
+        let mut rows = Vec::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.value)?;
+            for (k, v) in scan_prefix(&mut cursor, &[]) {
+                let id = k.get(16..).and_then(legacy_bitmask_to_id).ok_or_else(|| {
+                    Error::ParseError {
+                        message: "Invalid value record in legacy database".to_string(),
+                        location: "Storage::migrate_legacy_values",
+                    }
+                })?;
+                rows.push(([&k[..16], &pure_to_bitmask(&id)].concat(), v.to_vec()));
+            }
+        }
+        txn.clear_db(self.value)?;
+        for (k, v) in &rows {
+            txn.put(self.value, k, v, WriteFlags::empty())?;
+        }
+        log::info!(
+            "Migrated {} values to data format {}",
+            rows.len(),
+            FORMAT_VERSION
+        );
+        Ok(())
     }
-}
 
-impl StorageTrait for Storage {
-    fn create_space(&self, spacename: &str) -> Result<Output, Error> {
-        let space_id: [u8; 16] = *Uuid::new_v4().as_bytes();
-        let space_bytes = spacename.as_bytes();
+    pub fn user_exists(&self, username: &str) -> Result<bool, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        Ok(txn.get(self.user, &username.as_bytes()).is_ok())
+    }
+
+    // ユーザーを作成する。初回起動時の admin は must_change_password を付けて作る
+    pub fn insert_user(
+        &self,
+        username: &str,
+        password: &str,
+        must_change_password: bool,
+        roles: &[String],
+    ) -> Result<(), Error> {
         let mut txn = self.env.begin_rw_txn()?;
-        txn.put(
-            self.space,
-            &space_bytes,
-            &space_id,
-            lmdb::WriteFlags::empty(),
-        )
-        //既に同じ名前のSpaceが存在する場合にはエラーを返す
-        .map_err(|e| match e {
-            LmdbError::KeyExist => Error::SpaceAlreadyExists {
-                space_name: spacename.to_owned(),
-            },
-            _ => Error::from(e),
-        })?;
+
+        // ユーザー名が既に存在するか確認
+        if txn.get(self.user, &username.as_bytes()).is_ok() {
+            return Err(Error::UserAlreadyExists {
+                user_name: username.to_string(),
+            });
+        }
+        for role in roles {
+            self.role_record(&txn, role)?;
+        }
+
+        let record = UserRecord {
+            password_hash: hash_password(password, "create_user")?,
+            must_change_password,
+            roles: Some(roles.to_vec()),
+        };
+        self.put_user(&mut txn, username, &record)?;
         txn.commit()?;
-        Ok(Output::Success)
+        Ok(())
     }
 
-    fn drop_space(&self, spacename: &str) -> Result<crate::json::output::Output, Error> {
-        let space_bytes = spacename.as_bytes();
+    // パスワードが password と一致すれば must_change_password を付けて true を返す
+    pub fn flag_known_password(&self, username: &str, password: &str) -> Result<bool, Error> {
         let mut txn = self.env.begin_rw_txn()?;
+        let mut record = match self.user_record(&txn, username) {
+            Ok(record) => record,
+            Err(Error::UserNotFound { .. }) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if !verify_password(&record, password)? {
+            return Ok(false);
+        }
+        if !record.must_change_password {
+            record.must_change_password = true;
+            self.put_user(&mut txn, username, &record)?;
+            txn.commit()?;
+        }
+        Ok(true)
+    }
 
-        // 1. Space の削除
-        txn.del(self.space, &space_bytes, None)
+    fn user_record<T: Transaction>(&self, txn: &T, username: &str) -> Result<UserRecord, Error> {
+        let bytes = txn
+            .get(self.user, &username.as_bytes())
+            .map_err(|_| Error::UserNotFound {
+                user_name: username.to_string(),
+            })?;
+        parse_user_record(bytes)
+    }
+
+    // 組み込みロールか role DB のロールの権限
+    fn role_record<T: Transaction>(&self, txn: &T, rolename: &str) -> Result<Privileges, Error> {
+        if let Some(privileges) = builtin_role(rolename) {
+            return Ok(privileges);
+        }
+        let bytes = txn
+            .get(self.role, &rolename.as_bytes())
             .map_err(|e| match e {
-                LmdbError::NotFound => Error::SpaceNotFound {
-                    space_name: spacename.to_string(),
+                LmdbError::NotFound => Error::RoleNotFound {
+                    role_name: rolename.to_string(),
                 },
                 _ => Error::from(e),
             })?;
+        serde_json::from_slice(bytes).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "role_record",
+        })
+    }
 
-        // 2. Space に属するキーを先にコピー
-        let mut keys_to_delete = Vec::new();
-        {
-            let mut cursor = txn.open_ro_cursor(self.key)?; // 読み取り専用カーソルで走査
-            let prefix = space_bytes; // space UUID が key のプレフィックス
-            for result in cursor.iter_start() {
-                let (k, _) = result;
-                if k.starts_with(prefix) {
-                    keys_to_delete.push(k.to_vec()); // Vec にコピー
-                }
-            }
-        }
-
-        // 3. コピーしたキーを削除
-        for k in keys_to_delete {
-            txn.del(self.key, &k, None)?;
-        }
-
-        txn.commit()?;
-        Ok(Output::Success)
+    fn put_user(
+        &self,
+        txn: &mut RwTransaction,
+        username: &str,
+        record: &UserRecord,
+    ) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(record).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "put_user",
+        })?;
+        txn.put(self.user, &username.as_bytes(), &bytes, WriteFlags::empty())?;
+        Ok(())
     }
 
-    fn info_space(&self, spacename: &str) -> Result<crate::json::output::Output, Error> {
-        // 1. Space の存在確認
-        let space_bytes = spacename.as_bytes();
-        let txn = self.env.begin_ro_txn()?;
-        let space_uuid = match txn.get(self.space, &space_bytes) {
-            Ok(v) => std::str::from_utf8(v)?,
-            Err(LmdbError::NotFound) => {
-                return Err(Error::SpaceNotFound {
-                    space_name: spacename.to_string(),
-                });
+    // 期限切れのセッションをメモリから取り除いてから返す（LMDB からは sweep_sessions で消す）
+    fn live_sessions(&self) -> MutexGuard<'_, SessionTable> {
+        let mut table = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let now = now_millis();
+        let SessionTable { sessions, touched } = &mut *table;
+        sessions.retain(|id, session| {
+            let live = session.expires_at > now;
+            if !live {
+                touched.remove(id);
             }
-            Err(e) => return Err(Error::from(e)),
-        };
+            live
+        });
+        table
+    }
 
-        // 2. キーを走査してこのスペースに属するものだけ抽出
-        let mut cursor = txn.open_ro_cursor(self.key)?;
-        let mut keys_info: Vec<InfoKey> = Vec::new();
-        let prefix = format!("{}:", space_uuid);
+    fn login_throttle(&self) -> MutexGuard<'_, LoginThrottle> {
+        self.login_throttle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
 
-        for result in cursor.iter_start() {
-            let (k, _v) = result;
-            if k.starts_with(prefix.as_bytes()) {
-                let key_str = std::str::from_utf8(k)?;
-                let parts: Vec<&str> = key_str.split(':').collect();
-                if parts.len() != 4 {
-                    return Err(Error::ParseError {
-                        message: format!("Invalid key format: {}", key_str),
-                        location: "io::info_space",
-                    });
-                }
+    fn put_session(&self, txn: &mut RwTransaction, session: &Session) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(session).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "put_session",
+        })?;
+        txn.put(
+            self.session,
+            &session.id.as_bytes(),
+            &bytes,
+            WriteFlags::empty(),
+        )?;
+        Ok(())
+    }
 
-                keys_info.push(InfoKey {
-                    keyname: parts[1].to_string(),
-                    keytype: parts[2].to_string(),
-                    keymode: parts[3].to_string(),
-                });
+    // メモリと LMDB の両方からセッションを消す
+    // LMDB のコミット中に認証を止めないよう、コミットしてからロックを取る
+    fn remove_sessions(&self, ids: &[String]) -> Result<(), Error> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut txn = self.env.begin_rw_txn()?;
+        for id in ids {
+            match txn.del(self.session, &id.as_bytes(), None) {
+                Ok(()) | Err(LmdbError::NotFound) => {}
+                Err(e) => return Err(e.into()),
             }
         }
+        txn.commit()?;
+        let mut table = self.live_sessions();
+        for id in ids {
+            table.sessions.remove(id);
+            table.touched.remove(id);
+        }
+        Ok(())
+    }
 
-        let info = InfoSpace {
-            spacename: spacename.to_string(),
-            keynames: keys_info,
-        };
-
-        Ok(crate::json::output::Output::InfoSpace(info))
+    // ユーザーのセッションを keep 以外すべて消す
+    fn remove_user_sessions(&self, username: &str, keep: Option<&str>) -> Result<usize, Error> {
+        let ids: Vec<String> = self
+            .live_sessions()
+            .sessions
+            .values()
+            .filter(|session| session.user_name == username && Some(session.id.as_str()) != keep)
+            .map(|session| session.id.clone())
+            .collect();
+        self.remove_sessions(&ids)?;
+        Ok(ids.len())
     }
 
-    fn show_spaces(&self) -> Result<crate::json::output::Output, Error> {
-        let txn = self.env.begin_ro_txn()?; // 読み取り専用トランザクション
-        let mut cursor = txn.open_ro_cursor(self.space)?; // space DB のカーソルを開く
+    fn delete_user_tokens(&self, txn: &mut RwTransaction, username: &str) -> Result<(), Error> {
+        for token in self.api_tokens(txn)? {
+            if token.user_name == username {
+                txn.del(self.token, &token.id.as_bytes(), None)?;
+            }
+        }
+        Ok(())
+    }
 
-        let mut spaces = Vec::new();
+    // 記録が無い Space は所有者・共有先なし
+    fn read_acl<T: Transaction>(&self, txn: &T, space_uuid: &[u8]) -> Result<SpaceAcl, Error> {
+        match txn.get(self.space_acl, &space_uuid) {
+            Ok(v) => serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                message: e.to_string(),
+                location: "io::read_acl",
+            }),
+            Err(LmdbError::NotFound) => Ok(SpaceAcl::default()),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
 
-        for result in cursor.iter_start() {
-            let (key_bytes, _val_bytes) = result;
+    fn put_acl(
+        &self,
+        txn: &mut RwTransaction,
+        space_uuid: &[u8],
+        acl: &SpaceAcl,
+    ) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(acl).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "io::put_acl",
+        })?;
+        txn.put(self.space_acl, &space_uuid, &bytes, WriteFlags::empty())?;
+        Ok(())
+    }
 
-            // &[u8] -> &str への変換。? で Error に変換可能
-            let s: &str = std::str::from_utf8(key_bytes)?;
+    fn put_audit(&self, key: &[u8], entry: &AuditEntry, flags: WriteFlags) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(entry).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "io::put_audit",
+        })?;
+        let mut txn = self.env.begin_rw_txn()?;
+        txn.put(self.audit, &key, &bytes, flags)?;
+        txn.commit()?;
+        Ok(())
+    }
 
-            // &str -> String
-            let string: String = s.to_string();
+    // 削除したユーザー・ロールを全 Space の共有先（と所有者）から外す
+    fn remove_from_acls(
+        &self,
+        txn: &mut RwTransaction,
+        username: Option<&str>,
+        rolename: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut updated = Vec::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.space_acl)?;
+            for (k, v) in scan_prefix(&mut cursor, &[]) {
+                let mut acl: SpaceAcl =
+                    serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                        message: e.to_string(),
+                        location: "io::remove_from_acls",
+                    })?;
+                let before = acl.shares.len();
+                acl.shares.retain(|share| {
+                    (username.is_none() || share.user_name.as_deref() != username)
+                        && (rolename.is_none() || share.role_name.as_deref() != rolename)
+                });
+                let orphaned = username.is_some() && acl.owner.as_deref() == username;
+                if orphaned {
+                    acl.owner = None;
+                }
+                if orphaned || acl.shares.len() != before {
+                    updated.push((k.to_vec(), acl));
+                }
+            }
+        }
+        for (space_uuid, acl) in updated {
+            self.put_acl(txn, &space_uuid, &acl)?;
+        }
+        Ok(())
+    }
 
-            spaces.push(string);
+    fn space_uuid<T: Transaction>(&self, txn: &T, spacename: &str) -> Result<Vec<u8>, Error> {
+        match txn.get(self.space, &spacename.as_bytes()) {
+            Ok(v) => Ok(v.to_vec()),
+            Err(LmdbError::NotFound) => Err(Error::SpaceNotFound {
+                space_name: spacename.to_string(),
+            }),
+            Err(e) => Err(Error::from(e)),
         }
+    }
 
-        Ok(Output::ShowSpaces(crate::json::output::ShowSpaces {
-            spacenames: spaces,
-        }))
+    fn api_tokens<T: Transaction>(&self, txn: &T) -> Result<Vec<ApiToken>, Error> {
+        let mut cursor = txn.open_ro_cursor(self.token)?;
+        scan_prefix(&mut cursor, &[])
+            .map(|(k, v)| {
+                let id = std::str::from_utf8(k)?.to_string();
+                let record: ApiToken =
+                    serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                        message: e.to_string(),
+                        location: "api_tokens",
+                    })?;
+                Ok(ApiToken { id, ..record })
+            })
+            .collect()
     }
 
-    fn create_key(
+    // space_uuid 配下から名前が完全一致する Key を探す
+    fn find_key<T: Transaction>(
+        &self,
+        txn: &T,
+        space_uuid: &[u8],
+        keyname: &str,
+    ) -> Result<Option<KeyRecord>, Error> {
+        let prefix = [space_uuid, keyname.as_bytes()].concat();
+        let mut cursor = txn.open_ro_cursor(self.key)?;
+        let found = scan_prefix(&mut cursor, &prefix)
+            .find(|(k, _)| k.len() == prefix.len() + 2)
+            .map(|(k, v)| KeyRecord {
+                record: k.to_vec(),
+                uuid: v.to_vec(),
+            });
+        Ok(found)
+    }
+
+    fn get_key<T: Transaction>(
         &self,
+        txn: &T,
         spacename: &str,
         keyname: &str,
-        keytype: crate::json::input::KeyType,
-        keymode: crate::json::input::KeyMode,
-    ) -> Result<crate::json::output::Output, Error> {
-        let space_bytes = spacename.as_bytes();
-        let mut txn = self.env.begin_rw_txn()?;
-        let space_uuid = match txn.get(self.space, &space_bytes) {
-            Ok(v) => v,
-            Err(LmdbError::NotFound) => {
-                return Err(Error::SpaceNotFound {
+        location: &'static str,
+    ) -> Result<KeyRecord, Error> {
+        let space_uuid = txn
+            .get(self.space, &spacename.as_bytes())
+            .map_err(|e| match e {
+                LmdbError::NotFound => Error::SpaceNotFound {
                     space_name: spacename.to_string(),
-                });
-            }
-            Err(e) => return Err(Error::from(e)),
-        };
+                },
+                _ => Error::from(e),
+            })?;
+        self.find_key(txn, space_uuid, keyname)?
+            .ok_or(Error::KeyNotFound {
+                space_name: spacename.to_string(),
+                key_name: keyname.to_string(),
+                location,
+            })
+    }
+
+    // Key 作成時の設定を検証する
+    fn check_key_option(
+        &self,
+        keyname: &str,
+        keytype: KeyType,
+        option: &KeyOption,
+        location: &'static str,
+    ) -> Result<(), Error> {
+        // Sum/Avg は数値型の Key でのみ集約できる
+        if let Some(pyramid) = &option.pyramid
+            && !keytype.is_numeric()
+            && pyramid
+                .aggregations
+                .iter()
+                .any(|a| matches!(a, Aggregation::Sum | Aggregation::Avg))
+        {
+            return Err(Error::TypeMismatchValue {
+                expected_type: "INT|FLOAT|INT64|DOUBLE".to_string(),
+                received_type: format!("{:?}", keytype),
+                location,
+            });
+        }
+
+        if let Some(constraint) = &option.constraint {
+            validate_constraint(keyname, keytype, constraint)?;
+        }
+        Ok(())
+    }
 
+    // Key レコードと設定を書き込み、新しい key_uuid を返す
+    fn put_key(
+        &self,
+        txn: &mut RwTransaction,
+        space_uuid: &[u8],
+        keyname: &str,
+        keytype: KeyType,
+        keymode: KeyMode,
+        option: &KeyOption,
+    ) -> Result<Vec<u8>, Error> {
         let key_id: [u8; 16] = *Uuid::new_v4().as_bytes();
 
         // バイト列形式: [space_uuid][keyname][keytype][keymode]
         let key_bytes = [
-            &space_uuid[..],
+            space_uuid,
             keyname.as_bytes(),
             &[keytype_id(keytype)],
             &[keymode as u8],
         ]
         .concat();
+        txn.put(self.key, &key_bytes, &key_id, WriteFlags::empty())?;
 
-        txn.put(self.key, &key_bytes, &key_id, lmdb::WriteFlags::empty())
-            .map_err(|e| match e {
-                LmdbError::KeyExist => Error::KeyAlreadyExists {
-                    space_name: spacename.to_string(),
-                    key_name: keyname.to_string(),
-                    location: "io::create_key",
-                },
-                _ => Error::from(e),
-            })?;
+        let option_bytes = serde_json::to_vec(option).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "io::put_key",
+        })?;
+        txn.put(self.key_option, &key_id, &option_bytes, WriteFlags::empty())?;
+        Ok(key_id.to_vec())
+    }
 
-        txn.commit()?;
-        Ok(Output::Success)
+    fn key_option<T: Transaction>(&self, txn: &T, key_uuid: &[u8]) -> Result<KeyOption, Error> {
+        match txn.get(self.key_option, &key_uuid) {
+            Ok(v) => serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                message: e.to_string(),
+                location: "io::key_option",
+            }),
+            Err(LmdbError::NotFound) => Ok(KeyOption::default()),
+            Err(e) => Err(Error::from(e)),
+        }
     }
 
-    fn drop_key(
+    // 履歴DBのキー: [key_uuid][空間IDビット列][HISTORY_TERMINATOR][timestamp(BE)]
+    // ビット列は 0/1 のみなので終端バイトでセルごとの履歴がまとまり、時刻順に並ぶ
+    fn record_history(
         &self,
-        spacename: &str,
-        keyname: &str,
-    ) -> Result<crate::json::output::Output, Error> {
-        let space_bytes = spacename.as_bytes();
-        let mut txn = self.env.begin_rw_txn()?;
-
-        // Space の存在確認
-        let space_uuid = match txn.get(self.space, &space_bytes) {
-            Ok(v) => v,
-            Err(LmdbError::NotFound) => {
-                return Err(Error::SpaceNotFound {
-                    space_name: spacename.to_string(),
-                });
-            }
-            Err(e) => return Err(Error::from(e)),
-        };
+        txn: &mut RwTransaction,
+        key_uuid: &[u8],
+        option: &KeyOption,
+        id: &[u8],
+        entry: HistoryEntry,
+    ) -> Result<(), Error> {
+        if !option.history {
+            return Ok(());
+        }
 
-        let key_prefix = [space_uuid, keyname.as_bytes()].concat();
+        let mut timestamp = entry.timestamp;
+        let bytes = serde_json::to_vec(&entry).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "io::record_history",
+        })?;
 
-        // Cursor を使って削除対象のキーを収集
-        let mut keys_to_delete = Vec::new();
-        {
-            let mut cursor = txn.open_ro_cursor(self.key)?;
-            for (k, _) in cursor.iter_start() {
-                if k.starts_with(&key_prefix) {
-                    keys_to_delete.push(k.to_vec());
-                }
+        // 同一ミリ秒の書き込みは 1ms ずらして順序を保つ
+        loop {
+            let db_key = [
+                key_uuid,
+                id,
+                &[HISTORY_TERMINATOR],
+                &timestamp.to_be_bytes(),
+            ]
+            .concat();
+            match txn.put(self.history, &db_key, &bytes, WriteFlags::NO_OVERWRITE) {
+                Ok(()) => return Ok(()),
+                Err(LmdbError::KeyExist) => timestamp += 1,
+                Err(e) => return Err(Error::from(e)),
             }
-        } // <- cursor がここで drop され、txn は再び mutable に
+        }
+    }
 
-        if keys_to_delete.is_empty() {
-            return Err(Error::KeyNotFound {
-                space_name: spacename.to_string(),
+    // prefix 配下の履歴を (空間IDビット列, エントリ) の時刻順で返す
+    fn scan_history<T: Transaction>(
+        &self,
+        txn: &T,
+        key_uuid: &[u8],
+        id: &[u8],
+    ) -> Result<Vec<(Vec<u8>, HistoryEntry)>, Error> {
+        let prefix = [key_uuid, id].concat();
+        let mut cursor = txn.open_ro_cursor(self.history)?;
+        let mut result = Vec::new();
+        for (k, v) in scan_prefix(&mut cursor, &prefix) {
+            // 末尾は [終端バイト][timestamp 8バイト]
+            let id_bytes = k[key_uuid.len()..k.len() - 9].to_vec();
+            let entry: HistoryEntry = serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                message: e.to_string(),
+                location: "io::scan_history",
+            })?;
+            result.push((id_bytes, entry));
+        }
+        Ok(result)
+    }
+
+    // 省略された値を Key の default で補い、型と制約を検証する
+    fn checked_value(
+        &self,
+        keyname: &str,
+        keytype: KeyType,
+        option: &KeyOption,
+        value: Option<ValueEntry>,
+        location: &'static str,
+    ) -> Result<ValueEntry, Error> {
+        let constraint = option.constraint.as_ref();
+        let value = value
+            .or_else(|| constraint.and_then(|c| c.default.clone()))
+            .ok_or(Error::ConstraintViolation {
                 key_name: keyname.to_string(),
-                location: "drop_key",
+                reason: "value is required".to_string(),
+                location,
+            })?;
+
+        if value.keytype() != keytype {
+            return Err(Error::TypeMismatchFilter {
+                expected_type: format!("{:?}", keytype),
+                operation: format!("{:?}", value),
+                location,
             });
         }
-
-        // コピーしたキーを削除
-        for k in keys_to_delete {
-            txn.del(self.key, &k, None)?;
+        if let Some(constraint) = constraint {
+            check_constraint(keyname, constraint, &value)?;
         }
-
-        txn.commit()?;
-        Ok(Output::Success)
+        Ok(value)
     }
 
-    fn show_keys(&self, spacename: &str) -> Result<crate::json::output::Output, Error> {
-        let space_bytes = spacename.as_bytes();
-        let txn = self.env.begin_ro_txn()?;
-        let space_uuid = match txn.get(self.space, &space_bytes) {
-            Ok(v) => v,
-            Err(LmdbError::NotFound) => {
-                return Err(Error::SpaceNotFound {
-                    space_name: spacename.to_string(),
-                });
-            }
+    // 値を1セル書き込み、ピラミッド・履歴・有効期限を更新する
+    // 既存の値は置き換え、履歴には old→new の1件だけを残す（有効期限は expires_at にする）
+    #[allow(clippy::too_many_arguments)]
+    fn put_value(
+        &self,
+        txn: &mut RwTransaction,
+        key_uuid: &[u8],
+        keytype: KeyType,
+        option: &KeyOption,
+        id: &[u8],
+        value: &ValueEntry,
+        expires_at: Option<u64>,
+        timestamp: u64,
+        user: &str,
+    ) -> Result<(), Error> {
+        let db_key = [key_uuid, id].concat();
+        let old = match txn.get(self.value, &db_key) {
+            Ok(v) => Some(ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?),
+            Err(LmdbError::NotFound) => None,
             Err(e) => return Err(Error::from(e)),
         };
+        if let Some(old) = &old {
+            self.apply_pyramid(txn, key_uuid, option, id, old, -1)?;
+            self.clear_expiry(txn, &db_key)?;
+        }
 
-        let mut cursor = txn.open_ro_cursor(self.key)?;
-        let mut keys = Vec::new();
-        for (k, _v) in cursor.iter_start() {
-            if k.starts_with(space_uuid) {
-                let keyname_len = k.len() - space_uuid.len() - 2;
-                let keyname_bytes = &k[space_uuid.len()..space_uuid.len() + keyname_len];
-                let keyname = std::str::from_utf8(keyname_bytes)?.to_string();
-                keys.push(keyname);
+        txn.put(self.value, &db_key, &value.to_bytes(), WriteFlags::empty())?;
+        self.apply_pyramid(txn, key_uuid, option, id, value, 1)?;
+        let entry = HistoryEntry {
+            timestamp,
+            user: user.to_string(),
+            old,
+            new: Some(value.clone()),
+        };
+        self.record_history(txn, key_uuid, option, id, entry)?;
+        if let Some(expires_at) = expires_at {
+            txn.put(
+                self.expiry,
+                &db_key,
+                &expires_at.to_be_bytes(),
+                WriteFlags::empty(),
+            )?;
+            let index_key = [&expires_at.to_be_bytes(), db_key.as_slice()].concat();
+            txn.put(
+                self.expiry_index,
+                &index_key,
+                &[keytype_id(keytype)],
+                WriteFlags::empty(),
+            )?;
+        }
+        Ok(())
+    }
+
+    // 期限が now 以前の値を最大 PURGE_BATCH 件消す。残りが無ければ true も返す
+    fn purge_expired_batch(&self, now: u64) -> Result<(usize, bool), Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+
+        // 期限順の索引を now まで辿って対象を集める
+        let mut targets = Vec::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.expiry_index)?;
+            for (k, v) in scan_prefix(&mut cursor, &[]) {
+                let mut ts = [0u8; 8];
+                ts.copy_from_slice(&k[..8]);
+                if u64::from_be_bytes(ts) > now || targets.len() == PURGE_BATCH {
+                    break;
+                }
+                let keytype = id_keytype(v[0]).ok_or(Error::UnknownKeyType {
+                    id: v[0],
+                    location: "purge_expired",
+                })?;
+                targets.push((k.to_vec(), keytype));
+            }
+        }
+        let done = targets.len() < PURGE_BATCH;
+
+        let mut purged = 0;
+        for (index_key, keytype) in targets {
+            let (key_uuid, id) = index_key[8..].split_at(16);
+            let option = self.key_option(&txn, key_uuid)?;
+            if self.remove_value(&mut txn, key_uuid, keytype, &option, id, now, "expiry")? {
+                purged += 1;
+            } else {
+                // 値が既に無い索引は残すと毎回拾ってしまうので消す
+                match txn.del(self.expiry_index, &index_key, None) {
+                    Ok(()) | Err(LmdbError::NotFound) => {}
+                    Err(e) => return Err(Error::from(e)),
+                }
             }
         }
 
-        Ok(Output::Showkeys(Showkeys { keynames: keys }))
+        txn.commit()?;
+        Ok((purged, done))
     }
-    fn info_key(
-        &self,
-        spacename: &str,
-        keyname: &str,
-    ) -> Result<crate::json::output::Output, Error> {
-        let space_bytes = spacename.as_bytes();
-        let txn = self.env.begin_ro_txn()?;
 
-        // Space の存在確認
-        let space_uuid = match txn.get(self.space, &space_bytes) {
-            Ok(v) => v,
-            Err(LmdbError::NotFound) => {
-                return Err(Error::SpaceNotFound {
-                    space_name: spacename.to_string(),
-                });
-            }
+    // 1セル分の値を削除し、ピラミッド・履歴・有効期限を更新する
+    // 値が無ければ何もしない
+    #[allow(clippy::too_many_arguments)]
+    fn remove_value(
+        &self,
+        txn: &mut RwTransaction,
+        key_uuid: &[u8],
+        keytype: KeyType,
+        option: &KeyOption,
+        id: &[u8],
+        timestamp: u64,
+        user: &str,
+    ) -> Result<bool, Error> {
+        let db_key = [key_uuid, id].concat();
+        let old = match txn.get(self.value, &db_key) {
+            Ok(v) => ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?,
+            Err(LmdbError::NotFound) => return Ok(false),
             Err(e) => return Err(Error::from(e)),
         };
+        txn.del(self.value, &db_key, None)?;
+        let expires_at = self.clear_expiry(txn, &db_key)?;
 
-        let key_prefix = [space_uuid, keyname.as_bytes()].concat();
-        let mut found_key: Option<&[u8]> = None;
+        // 期限切れの値は掃除した時刻ではなく期限の時刻に消えたものとして履歴に残す
+        let (timestamp, user) = match expires_at {
+            Some(expires_at) if expires_at <= timestamp => (expires_at, "expiry"),
+            _ => (timestamp, user),
+        };
 
-        // Cursor のスコープをブロックで限定
-        {
-            let mut cursor = txn.open_ro_cursor(self.key)?;
-            for (k, _v) in cursor.iter_start() {
-                if k.starts_with(&key_prefix) {
-                    found_key = Some(k);
-                    break;
-                }
+        self.apply_pyramid(txn, key_uuid, option, id, &old, -1)?;
+        let entry = HistoryEntry {
+            timestamp,
+            user: user.to_string(),
+            old: Some(old),
+            new: None,
+        };
+        self.record_history(txn, key_uuid, option, id, entry)?;
+        Ok(true)
+    }
+
+    // 値の有効期限と期限順の索引を消し、消した期限を返す
+    fn clear_expiry(&self, txn: &mut RwTransaction, db_key: &[u8]) -> Result<Option<u64>, Error> {
+        let expires_at = self.expires_at(txn, db_key)?;
+        if let Some(expires_at) = expires_at {
+            txn.del(self.expiry, &db_key, None)?;
+            let index_key = [&expires_at.to_be_bytes(), db_key].concat();
+            match txn.del(self.expiry_index, &index_key, None) {
+                Ok(()) | Err(LmdbError::NotFound) => {}
+                Err(e) => return Err(Error::from(e)),
             }
-        } // <- cursor がここで drop される
+        }
+        Ok(expires_at)
+    }
 
-        let k = match found_key {
-            Some(k) => k,
-            None => {
-                return Err(Error::KeyNotFound {
-                    space_name: spacename.to_string(),
-                    key_name: keyname.to_string(),
-                    location: "info_key",
-                });
+    fn expires_at<T: Transaction>(&self, txn: &T, db_key: &[u8]) -> Result<Option<u64>, Error> {
+        match txn.get(self.expiry, &db_key) {
+            Ok(v) => {
+                let bytes: [u8; 8] = v.try_into().map_err(|_| Error::ParseError {
+                    message: "Invalid expiry timestamp".to_string(),
+                    location: "io::expires_at",
+                })?;
+                Ok(Some(u64::from_be_bytes(bytes)))
             }
+            Err(LmdbError::NotFound) => Ok(None),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    // 期限切れの値は掃除前でも読み出しから見えなくする
+    fn is_expired<T: Transaction>(&self, txn: &T, db_key: &[u8], now: u64) -> Result<bool, Error> {
+        Ok(self.expires_at(txn, db_key)?.is_some_and(|t| t <= now))
+    }
+
+    // prefix 配下のエントリを new_prefix 配下へ複製し、複製したキーを返す
+    fn copy_prefixed(
+        &self,
+        txn: &mut RwTransaction,
+        db: Database,
+        prefix: &[u8],
+        new_prefix: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = {
+            let mut cursor = txn.open_ro_cursor(db)?;
+            scan_prefix(&mut cursor, prefix)
+                .map(|(k, v)| ([new_prefix, &k[prefix.len()..]].concat(), v.to_vec()))
+                .collect()
         };
+        let mut copied = Vec::with_capacity(entries.len());
+        for (k, v) in entries {
+            txn.put(db, &k, &v, WriteFlags::empty())?;
+            copied.push(k);
+        }
+        Ok(copied)
+    }
 
-        if k.len() < space_uuid.len() + keyname.len() + 2 {
-            return Err(Error::ParseError {
-                message: "Invalid key length".to_string(),
-                location: "info_key",
-            });
+    // Key レコードと設定（制約を含む）、key_uuid で紐づく値・ピラミッド・履歴・有効期限を消す
+    fn remove_key(&self, txn: &mut RwTransaction, key: &KeyRecord) -> Result<(), Error> {
+        txn.del(self.key, &key.record, None)?;
+        match txn.del(self.key_option, &key.uuid, None) {
+            Ok(()) | Err(LmdbError::NotFound) => {}
+            Err(e) => return Err(Error::from(e)),
         }
 
-        // keytype と keymode を取得
-        let keytype = id_keytype(k[k.len() - 2]);
-        let keymode = KeyMode::try_from(k[k.len() - 1]).map_err(|_| Error::ParseError {
-            message: "Invalid keymode value".to_string(),
-            location: "info_key",
-        })?;
+        // 期限順の索引のキーは [期限][key_uuid][ビット列] なので expiry DB から組み立てる
+        let index_keys: Vec<Vec<u8>> = {
+            let mut cursor = txn.open_ro_cursor(self.expiry)?;
+            scan_prefix(&mut cursor, &key.uuid)
+                .map(|(k, v)| [v, k].concat())
+                .collect()
+        };
+        for index_key in index_keys {
+            match txn.del(self.expiry_index, &index_key, None) {
+                Ok(()) | Err(LmdbError::NotFound) => {}
+                Err(e) => return Err(Error::from(e)),
+            }
+        }
 
-        Ok(Output::InfoKey(InfoKey {
-            keyname: keyname.to_string(),
-            keytype: format!("{:?}", keytype),
-            keymode: format!("{:?}", keymode),
-        }))
+        for db in [self.value, self.pyramid, self.history, self.expiry] {
+            self.delete_prefixed(txn, db, &key.uuid)?;
+        }
+        Ok(())
     }
 
-    fn insert_value(
+    fn delete_prefixed(
         &self,
-        spacename: &str,
-        keyname: &str,
-        ids: Vec<Vec<u8>>,
-        value: ValueEntry,
-    ) -> Result<Output, Error> {
-        let mut txn = self.env.begin_rw_txn()?;
+        txn: &mut RwTransaction,
+        db: Database,
+        prefix: &[u8],
+    ) -> Result<(), Error> {
+        let keys: Vec<Vec<u8>> = {
+            let mut cursor = txn.open_ro_cursor(db)?;
+            scan_prefix(&mut cursor, prefix)
+                .map(|(k, _)| k.to_vec())
+                .collect()
+        };
+        for k in keys {
+            txn.del(db, &k, None)?;
+        }
+        Ok(())
+    }
+
+    // 値の追加(sign=1)・削除(sign=-1)をピラミッドの各レベルへ反映する
+    // セルより細かいレベルには寄与しない
+    fn apply_pyramid(
+        &self,
+        txn: &mut RwTransaction,
+        key_uuid: &[u8],
+        option: &KeyOption,
+        id: &[u8],
+        value: &ValueEntry,
+        sign: i64,
+    ) -> Result<(), Error> {
+        let Some(pyramid) = &option.pyramid else {
+            return Ok(());
+        };
+
+        for zoom in &pyramid.zooms {
+            let len = 1 + (*zoom as usize) * 3;
+            if id.len() < len {
+                continue;
+            }
+            // ピラミッドDBのキー: [key_uuid][zoom][zoom までの空間IDビット列]
+            let db_key = [key_uuid, &[*zoom], &id[..len]].concat();
+            let mut cell = match txn.get(self.pyramid, &db_key) {
+                Ok(v) => PyramidCell::from_bytes(v).ok_or(Error::ParseError {
+                    message: "Invalid pyramid cell".to_string(),
+                    location: "io::apply_pyramid",
+                })?,
+                Err(LmdbError::NotFound) => PyramidCell::default(),
+                Err(e) => return Err(Error::from(e)),
+            };
 
-        // 1. Space存在確認
+            cell.count = cell.count.saturating_add_signed(sign);
+            cell.sum += sign as f64 * value.as_f64().unwrap_or(0.0);
+
+            if cell.count == 0 {
+                match txn.del(self.pyramid, &db_key, None) {
+                    Ok(()) | Err(LmdbError::NotFound) => {}
+                    Err(e) => return Err(Error::from(e)),
+                }
+            } else {
+                txn.put(self.pyramid, &db_key, &cell.to_bytes(), WriteFlags::empty())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageTrait for Storage {
+    fn create_space(&self, spacename: &str, owner: &str) -> Result<Output, Error> {
+        let space_id: [u8; 16] = *Uuid::new_v4().as_bytes();
         let space_bytes = spacename.as_bytes();
-        let space_uuid_bytes = txn.get(self.space, &space_bytes).map_err(|e| match e {
-            LmdbError::NotFound => Error::SpaceNotFound {
-                space_name: spacename.to_string(),
+        let mut txn = self.env.begin_rw_txn()?;
+        txn.put(
+            self.space,
+            &space_bytes,
+            &space_id,
+            lmdb::WriteFlags::empty(),
+        )
+        //既に同じ名前のSpaceが存在する場合にはエラーを返す
+        .map_err(|e| match e {
+            LmdbError::KeyExist => Error::SpaceAlreadyExists {
+                space_name: spacename.to_owned(),
             },
             _ => Error::from(e),
         })?;
+        self.put_acl(
+            &mut txn,
+            &space_id,
+            &SpaceAcl {
+                owner: Some(owner.to_string()),
+                shares: Vec::new(),
+            },
+        )?;
+        txn.commit()?;
+        Ok(Output::Success)
+    }
 
-        // 2. Key存在確認 & key_uuid取得
-        let (key_uuid, keytype) = {
-            let mut key_cursor = txn.open_ro_cursor(self.key)?;
-            let mut found = None;
-            for (k, v) in key_cursor.iter_start() {
-                if k.starts_with(space_uuid_bytes) && std::str::from_utf8(k)?.contains(keyname) {
-                    let keytype_id_byte = k[k.len() - 2];
-                    found = Some((v.to_vec(), id_keytype(keytype_id_byte)));
-                    break;
-                }
-            }
-            found.ok_or(Error::KeyNotFound {
-                space_name: spacename.to_string(),
-                key_name: keyname.to_string(),
-                location: "insert_value",
-            })?
-        }; // <- key_cursor drop で txn が再び可変借用可能
-
-        // 3. ValueEntry と Key の型チェック
-        let type_matches = match (&keytype, &value) {
-            (KeyType::INT, ValueEntry::INT(_)) => true,
-            (KeyType::FLOAT, ValueEntry::FLOAT(_)) => true,
-            (KeyType::BOOLEAN, ValueEntry::BOOLEAN(_)) => true,
-            (KeyType::TEXT, ValueEntry::TEXT(_)) => true,
-            _ => false,
-        };
-        if !type_matches {
-            return Err(Error::TypeMismatchFilter {
-                expected_type: format!("{:?}", keytype),
-                operation: format!("{:?}", value),
-                location: "insert_value",
-            });
-        }
+    fn drop_space(&self, spacename: &str) -> Result<crate::json::output::Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
 
-        // 4. すべてのIDを事前チェック（重複が1つでもあればエラー）
-        for id in &ids {
-            let db_key = [key_uuid.clone(), id.clone()].concat();
-            if txn.get(self.value, &db_key).is_ok() {
-                return Err(Error::InsertError {
-                    space_name: spacename.to_string(),
-                    key_name: keyname.to_string(),
-                });
-            }
+        // 1. Space の削除
+        let space_uuid = self.space_uuid(&txn, spacename)?;
+        txn.del(self.space, &spacename.as_bytes(), None)?;
+        match txn.del(self.space_acl, &space_uuid, None) {
+            Ok(()) | Err(LmdbError::NotFound) => {}
+            Err(e) => return Err(Error::from(e)),
         }
 
-        // 5. すべて重複なしならまとめて LMDB に保存
-        for id in ids {
-            let db_key = [key_uuid.clone(), id].concat();
-            txn.put(self.value, &db_key, &value.to_bytes(), WriteFlags::empty())?;
+        // 2. key DB のキーは [space_uuid][keyname][keytype][keymode] なので space_uuid で集める
+        let keys: Vec<KeyRecord> = {
+            let mut cursor = txn.open_ro_cursor(self.key)?;
+            scan_prefix(&mut cursor, &space_uuid)
+                .map(|(k, v)| KeyRecord {
+                    record: k.to_vec(),
+                    uuid: v.to_vec(),
+                })
+                .collect()
+        };
+
+        // 3. Key と、その値・ピラミッド・履歴・有効期限を削除
+        for key in keys {
+            self.remove_key(&mut txn, &key)?;
         }
 
         txn.commit()?;
         Ok(Output::Success)
     }
 
-    fn patch_value(
-        &self,
-        spacename: &str,
-        keyname: &str,
-        ids: Vec<Vec<u8>>,
-        value: super::ValueEntry,
-    ) -> Result<crate::json::output::Output, Error> {
+    // Key は space_uuid で紐づくため、名前の付け替えだけで済む
+    fn rename_space(&self, spacename: &str, new_spacename: &str) -> Result<Output, Error> {
         let mut txn = self.env.begin_rw_txn()?;
 
-        // 1. Space存在確認
-        let space_bytes = spacename.as_bytes();
-        let space_uuid_bytes = match txn.get(self.space, &space_bytes) {
-            Ok(v) => v,
+        let space_uuid = match txn.get(self.space, &spacename.as_bytes()) {
+            Ok(v) => v.to_vec(),
             Err(LmdbError::NotFound) => {
                 return Err(Error::SpaceNotFound {
                     space_name: spacename.to_string(),
@@ -529,182 +1140,1064 @@ impl StorageTrait for Storage {
             Err(e) => return Err(Error::from(e)),
         };
 
-        // 2. Key存在確認（カーソルはスコープで即 drop）
-        let (key_bytes, _key_id_bytes) = {
-            let mut key_cursor = txn.open_ro_cursor(self.key)?;
-            let mut key_found = None;
+        txn.put(
+            self.space,
+            &new_spacename.as_bytes(),
+            &space_uuid,
+            WriteFlags::NO_OVERWRITE,
+        )
+        .map_err(|e| match e {
+            LmdbError::KeyExist => Error::SpaceAlreadyExists {
+                space_name: new_spacename.to_string(),
+            },
+            _ => Error::from(e),
+        })?;
+        txn.del(self.space, &spacename.as_bytes(), None)?;
 
-            for result in key_cursor.iter_start() {
-                let (k, v) = result;
-                if k.starts_with(space_uuid_bytes) && std::str::from_utf8(k)?.contains(keyname) {
-                    key_found = Some((k.to_vec(), v.to_vec()));
-                    break;
-                }
-            }
+        txn.commit()?;
+        Ok(Output::Success)
+    }
 
-            key_found.ok_or(Error::KeyNotFound {
-                space_name: spacename.to_string(),
-                key_name: keyname.to_string(),
-                location: "patch_value",
-            })?
+    // Key と値を新しい UUID で複製する（ピラミッド・履歴・有効期限も引き継ぐ）
+    // 複製した Space の所有者は owner（共有先は引き継がない）
+    fn clone_space(
+        &self,
+        spacename: &str,
+        new_spacename: &str,
+        owner: &str,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+
+        let space_uuid = match txn.get(self.space, &spacename.as_bytes()) {
+            Ok(v) => v.to_vec(),
+            Err(LmdbError::NotFound) => {
+                return Err(Error::SpaceNotFound {
+                    space_name: spacename.to_string(),
+                });
+            }
+            Err(e) => return Err(Error::from(e)),
         };
 
-        // 3. ValueEntry と Key の型チェック
-        let keytype_id_byte = key_bytes[key_bytes.len() - 2];
-        let keytype = id_keytype(keytype_id_byte);
+        let new_space_uuid: [u8; 16] = *Uuid::new_v4().as_bytes();
+        txn.put(
+            self.space,
+            &new_spacename.as_bytes(),
+            &new_space_uuid,
+            WriteFlags::NO_OVERWRITE,
+        )
+        .map_err(|e| match e {
+            LmdbError::KeyExist => Error::SpaceAlreadyExists {
+                space_name: new_spacename.to_string(),
+            },
+            _ => Error::from(e),
+        })?;
+        self.put_acl(
+            &mut txn,
+            &new_space_uuid,
+            &SpaceAcl {
+                owner: Some(owner.to_string()),
+                shares: Vec::new(),
+            },
+        )?;
 
-        let type_matches = match (keytype, &value) {
-            (KeyType::INT, ValueEntry::INT(_)) => true,
-            (KeyType::FLOAT, ValueEntry::FLOAT(_)) => true,
-            (KeyType::BOOLEAN, ValueEntry::BOOLEAN(_)) => true,
-            (KeyType::TEXT, ValueEntry::TEXT(_)) => true,
-            _ => false,
+        let keys: Vec<(Vec<u8>, Vec<u8>)> = {
+            let mut cursor = txn.open_ro_cursor(self.key)?;
+            scan_prefix(&mut cursor, &space_uuid)
+                .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                .collect()
         };
 
-        if !type_matches {
-            return Err(Error::TypeMismatchFilter {
-                expected_type: format!("{:?}", keytype),
-                operation: format!("{:?}", value),
-                location: "patch_value",
-            });
-        }
-
-        // 4. IDごとに既存値確認 & 新規挿入
-        for id in ids {
-            let db_key = [key_bytes.clone(), id.clone()].concat();
+        for (record, key_uuid) in keys {
+            let new_key_uuid: [u8; 16] = *Uuid::new_v4().as_bytes();
+            let new_record = [&new_space_uuid, &record[space_uuid.len()..]].concat();
+            txn.put(self.key, &new_record, &new_key_uuid, WriteFlags::empty())?;
 
-            // 既に存在する場合はスキップ
-            if txn.get(self.value, &db_key).is_ok() {
-                continue;
+            if let Ok(option) = txn.get(self.key_option, &key_uuid) {
+                let option = option.to_vec();
+                txn.put(self.key_option, &new_key_uuid, &option, WriteFlags::empty())?;
+            }
+            for db in [self.value, self.pyramid, self.history] {
+                self.copy_prefixed(&mut txn, db, &key_uuid, &new_key_uuid)?;
             }
 
-            // 存在しなければ挿入
-            txn.put(self.value, &db_key, &value.to_bytes(), WriteFlags::empty())?;
+            // 有効期限は索引も新しい値キーで張り直す
+            let keytype = id_keytype(record[record.len() - 2]).ok_or(Error::UnknownKeyType {
+                id: record[record.len() - 2],
+                location: "clone_space",
+            })?;
+            for db_key in self.copy_prefixed(&mut txn, self.expiry, &key_uuid, &new_key_uuid)? {
+                if let Some(expires_at) = self.expires_at(&txn, &db_key)? {
+                    let index_key = [&expires_at.to_be_bytes(), db_key.as_slice()].concat();
+                    txn.put(
+                        self.expiry_index,
+                        &index_key,
+                        &[keytype_id(keytype)],
+                        WriteFlags::empty(),
+                    )?;
+                }
+            }
         }
 
         txn.commit()?;
         Ok(Output::Success)
     }
 
-    // fn update_value(
-    //     &self,
-    //     spacename: &str,
-    //     keyname: &str,
-    //     ids: Vec<Vec<u8>>,
-    //     value: super::ValueEntry,
-    // ) -> Result<crate::json::output::Output, Error> {
-    // }
-    fn delete_value(
+    // Key 定義と有効な値を NDJSON の行単位で返す
+    fn export_space(
         &self,
         spacename: &str,
-        keyname: &str,
-        ids: Vec<Vec<u8>>,
-    ) -> Result<crate::json::output::Output, Error> {
-        let mut txn = self.env.begin_rw_txn()?;
-
-        // 1. Space存在確認
-        let space_bytes = spacename.as_bytes();
-        let space_uuid_bytes = txn.get(self.space, &space_bytes).map_err(|e| match e {
-            LmdbError::NotFound => Error::SpaceNotFound {
-                space_name: spacename.to_string(),
-            },
-            _ => Error::from(e),
-        })?;
+        visit: &mut dyn FnMut(SpaceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let keys: Vec<(Vec<u8>, SpaceRecord)> = {
+            let txn = self.env.begin_ro_txn()?;
+            let space_uuid = txn
+                .get(self.space, &spacename.as_bytes())
+                .map_err(|e| match e {
+                    LmdbError::NotFound => Error::SpaceNotFound {
+                        space_name: spacename.to_string(),
+                    },
+                    _ => Error::from(e),
+                })?;
 
-        // 2. Key存在確認 & key_uuid取得
-        let key_uuid = {
-            let mut key_cursor = txn.open_ro_cursor(self.key)?;
-            let mut found = None;
-            for (k, v) in key_cursor.iter_start() {
-                if k.starts_with(space_uuid_bytes) && std::str::from_utf8(k)?.contains(keyname) {
-                    found = Some(v.to_vec());
-                    break;
-                }
+            let mut cursor = txn.open_ro_cursor(self.key)?;
+            let mut keys = Vec::new();
+            for (k, v) in scan_prefix(&mut cursor, space_uuid) {
+                let key = KeyRecord {
+                    record: k.to_vec(),
+                    uuid: v.to_vec(),
+                };
+                let key_name = String::from_utf8(k[space_uuid.len()..k.len() - 2].to_vec())?;
+                let option = self.key_option(&txn, &key.uuid)?;
+                let key_mode =
+                    KeyMode::try_from(k[k.len() - 1]).map_err(|_| Error::ParseError {
+                        message: "Invalid keymode value".to_string(),
+                        location: "export_space",
+                    })?;
+                let record = SpaceRecord::Key {
+                    key_name,
+                    key_type: key.keytype()?,
+                    key_mode,
+                    option,
+                };
+                keys.push((key.uuid, record));
             }
-            found.ok_or(Error::KeyNotFound {
-                space_name: spacename.to_string(),
-                key_name: keyname.to_string(),
-                location: "delete_value",
-            })?
+            keys
         };
 
-        // 3. IDsごとに前方一致で削除
-        for id in ids {
-            let mut cursor = txn.open_rw_cursor(self.value)?;
-            let mut keys_to_delete = Vec::new();
-
-            for (k, _v) in cursor.iter_start() {
-                if k.starts_with(&key_uuid) {
-                    let id_bytes = &k[key_uuid.len()..]; // key_uuid を除いた部分
-                    if id_bytes.starts_with(&id) {
-                        keys_to_delete.push(k.to_vec());
+        // 値は EXPORT_BATCH 件ごとに読み取りトランザクションを開き直し、閉じてから visit に渡す
+        // （書き出し先が遅くても LMDB の古いページを持ち続けない）
+        let now = now_millis();
+        for (key_uuid, record) in keys {
+            let (key_name, keytype) = match &record {
+                SpaceRecord::Key {
+                    key_name, key_type, ..
+                } => (key_name.clone(), *key_type),
+                SpaceRecord::Value { .. } => continue,
+            };
+            visit(record)?;
+
+            let mut start = key_uuid.clone();
+            loop {
+                let mut batch = Vec::new();
+                let mut next = None;
+                {
+                    let txn = self.env.begin_ro_txn()?;
+                    let mut cursor = txn.open_ro_cursor(self.value)?;
+                    for (i, (k, v)) in scan_from(&mut cursor, &start).enumerate() {
+                        if !k.starts_with(&key_uuid) {
+                            break;
+                        }
+                        if i == EXPORT_BATCH {
+                            next = Some(k.to_vec());
+                            break;
+                        }
+                        let expires_at = self.expires_at(&txn, k)?;
+                        if expires_at.is_some_and(|t| t <= now) {
+                            continue;
+                        }
+                        batch.push(SpaceRecord::Value {
+                            key_name: key_name.clone(),
+                            id: bitmask_to_id_string(&k[key_uuid.len()..])?,
+                            value: ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?,
+                            expires_at,
+                        });
                     }
                 }
-            }
-            drop(cursor);
-
-            for k in keys_to_delete {
-                txn.del(self.value, &k, None)?;
+                for record in batch {
+                    visit(record)?;
+                }
+                match next {
+                    Some(k) => start = k,
+                    None => break,
+                }
             }
         }
 
-        txn.commit()?;
-        Ok(crate::json::output::Output::Success)
+        Ok(())
     }
 
-    fn select_value(
+    fn import_space(
         &self,
         spacename: &str,
-        keynames: Vec<String>,
-        ids: Vec<Vec<u8>>,
-    ) -> Result<HashMap<Vec<u8>, Vec<(String, ValueEntry)>>, Error> {
-        let txn = self.env.begin_ro_txn()?;
+        records: Vec<SpaceRecord>,
+        on_conflict: OnConflict,
+        user: &str,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
 
-        // 1. Space UUID の取得
-        let space_bytes = spacename.as_bytes();
-        let space_uuid = txn.get(self.space, &space_bytes).map_err(|e| match e {
-            LmdbError::NotFound => Error::SpaceNotFound {
-                space_name: spacename.to_string(),
+        // Space が無ければ作成する
+        let space_uuid = match txn.get(self.space, &spacename.as_bytes()) {
+            Ok(v) => v.to_vec(),
+            Err(LmdbError::NotFound) => {
+                let space_id = Uuid::new_v4().as_bytes().to_vec();
+                txn.put(
+                    self.space,
+                    &spacename.as_bytes(),
+                    &space_id,
+                    WriteFlags::empty(),
+                )?;
+                self.put_acl(
+                    &mut txn,
+                    &space_id,
+                    &SpaceAcl {
+                        owner: Some(user.to_string()),
+                        shares: Vec::new(),
+                    },
+                )?;
+                space_id
+            }
+            Err(e) => return Err(Error::from(e)),
+        };
+
+        let mut report = ImportSpace {
+            keys_created: 0,
+            values_written: 0,
+            values_skipped: 0,
+        };
+        // キー名 -> (key_uuid, keytype, option)
+        let mut keys: HashMap<String, (Vec<u8>, KeyType, KeyOption)> = HashMap::new();
+        let timestamp = now_millis();
+
+        for record in records {
+            match record {
+                SpaceRecord::Key {
+                    key_name,
+                    key_type,
+                    key_mode,
+                    option,
+                } => {
+                    let entry = match self.find_key(&txn, &space_uuid, &key_name)? {
+                        // 既存の Key は設定を保ったまま使う
+                        Some(key) => {
+                            if on_conflict == OnConflict::Error {
+                                return Err(Error::KeyAlreadyExists {
+                                    space_name: spacename.to_string(),
+                                    key_name,
+                                    location: "import_space",
+                                });
+                            }
+                            let keytype = key.keytype()?;
+                            if keytype != key_type {
+                                return Err(Error::TypeMismatchValue {
+                                    expected_type: format!("{:?}", keytype),
+                                    received_type: format!("{:?}", key_type),
+                                    location: "import_space",
+                                });
+                            }
+                            let option = self.key_option(&txn, &key.uuid)?;
+                            (key.uuid, keytype, option)
+                        }
+                        None => {
+                            self.check_key_option(&key_name, key_type, &option, "import_space")?;
+                            let uuid = self.put_key(
+                                &mut txn,
+                                &space_uuid,
+                                &key_name,
+                                key_type,
+                                key_mode,
+                                &option,
+                            )?;
+                            report.keys_created += 1;
+                            (uuid, key_type, option)
+                        }
+                    };
+                    keys.insert(key_name, entry);
+                }
+                SpaceRecord::Value {
+                    key_name,
+                    id,
+                    value,
+                    expires_at,
+                } => {
+                    // Key 行が無い場合は既存の Key に書き込む
+                    if !keys.contains_key(&key_name) {
+                        let key = self.get_key(&txn, spacename, &key_name, "import_space")?;
+                        let keytype = key.keytype()?;
+                        let option = self.key_option(&txn, &key.uuid)?;
+                        keys.insert(key_name.clone(), (key.uuid, keytype, option));
+                    }
+                    let (key_uuid, keytype, option) = &keys[&key_name];
+
+                    let bits = id_string_to_bitmask(&id).map_err(|e| Error::ParseError {
+                        message: e,
+                        location: "import_space",
+                    })?;
+                    let value = self.checked_value(
+                        &key_name,
+                        *keytype,
+                        option,
+                        Some(value),
+                        "import_space",
+                    )?;
+
+                    let db_key = [key_uuid.as_slice(), &bits].concat();
+                    if txn.get(self.value, &db_key).is_ok() {
+                        if self.is_expired(&txn, &db_key, timestamp)? {
+                            self.remove_value(
+                                &mut txn, key_uuid, *keytype, option, &bits, timestamp, "expiry",
+                            )?;
+                        } else {
+                            match on_conflict {
+                                OnConflict::Error => {
+                                    return Err(Error::ValueAlreadyExists {
+                                        space_time_id: id,
+                                        location: "import_space",
+                                    });
+                                }
+                                OnConflict::Skip => {
+                                    report.values_skipped += 1;
+                                    continue;
+                                }
+                                // put_value が置き換える
+                                OnConflict::Overwrite => {}
+                            }
+                        }
+                    }
+
+                    self.put_value(
+                        &mut txn, key_uuid, *keytype, option, &bits, &value, expires_at, timestamp,
+                        user,
+                    )?;
+                    report.values_written += 1;
+                }
+            }
+        }
+
+        txn.commit()?;
+        Ok(Output::ImportSpace(report))
+    }
+
+    fn info_space(&self, spacename: &str) -> Result<crate::json::output::Output, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let space_uuid = self.space_uuid(&txn, spacename)?;
+
+        // key DB のキーは [space_uuid][keyname][keytype][keymode]
+        let keys: Vec<KeyRecord> = {
+            let mut cursor = txn.open_ro_cursor(self.key)?;
+            scan_prefix(&mut cursor, &space_uuid)
+                .map(|(k, v)| KeyRecord {
+                    record: k.to_vec(),
+                    uuid: v.to_vec(),
+                })
+                .collect()
+        };
+        let mut keys_info: Vec<InfoKey> = Vec::new();
+        for key in keys {
+            let r = &key.record;
+            let keymode = KeyMode::try_from(r[r.len() - 1]).map_err(|_| Error::ParseError {
+                message: "Invalid keymode value".to_string(),
+                location: "io::info_space",
+            })?;
+            let option = self.key_option(&txn, &key.uuid)?;
+            keys_info.push(InfoKey {
+                keyname: String::from_utf8(r[space_uuid.len()..r.len() - 2].to_vec())?,
+                keytype: format!("{:?}", key.keytype()?),
+                keymode: format!("{:?}", keymode),
+                pyramid: option.pyramid,
+                history: option.history,
+                constraint: option.constraint.map(Box::new),
+            });
+        }
+
+        let acl = self.read_acl(&txn, &space_uuid)?;
+        let info = InfoSpace {
+            spacename: spacename.to_string(),
+            keynames: keys_info,
+            owner: acl.owner,
+            shares: acl.shares,
+        };
+
+        Ok(crate::json::output::Output::InfoSpace(info))
+    }
+
+    // 全 Space と所有者・共有先。見える Space への絞り込みは command 側で行う
+    fn show_spaces(&self) -> Result<Vec<(String, SpaceAcl)>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self.space)?;
+        scan_prefix(&mut cursor, &[])
+            .map(|(name, space_uuid)| {
+                Ok((
+                    std::str::from_utf8(name)?.to_string(),
+                    self.read_acl(&txn, space_uuid)?,
+                ))
+            })
+            .collect()
+    }
+
+    fn space_acl(&self, spacename: &str) -> Result<SpaceAcl, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let space_uuid = self.space_uuid(&txn, spacename)?;
+        self.read_acl(&txn, &space_uuid)
+    }
+
+    // 共有先のユーザー・ロールは存在するものに限る
+    fn share_space(&self, spacename: &str, share: SpaceShare) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let space_uuid = self.space_uuid(&txn, spacename)?;
+        if let Some(username) = &share.user_name {
+            self.user_record(&txn, username)?;
+        }
+        if let Some(rolename) = &share.role_name {
+            self.role_record(&txn, rolename)?;
+        }
+
+        let mut acl = self.read_acl(&txn, &space_uuid)?;
+        acl.shares
+            .retain(|s| s.user_name != share.user_name || s.role_name != share.role_name);
+        acl.shares.push(share);
+        self.put_acl(&mut txn, &space_uuid, &acl)?;
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn unshare_space(
+        &self,
+        spacename: &str,
+        username: Option<&str>,
+        rolename: Option<&str>,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let space_uuid = self.space_uuid(&txn, spacename)?;
+        let mut acl = self.read_acl(&txn, &space_uuid)?;
+        let before = acl.shares.len();
+        acl.shares
+            .retain(|s| s.user_name.as_deref() != username || s.role_name.as_deref() != rolename);
+        if acl.shares.len() != before {
+            self.put_acl(&mut txn, &space_uuid, &acl)?;
+            txn.commit()?;
+        }
+        Ok(Output::Success)
+    }
+
+    fn create_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        keytype: crate::json::input::KeyType,
+        keymode: crate::json::input::KeyMode,
+        option: KeyOption,
+    ) -> Result<crate::json::output::Output, Error> {
+        self.check_key_option(keyname, keytype, &option, "io::create_key")?;
+
+        let space_bytes = spacename.as_bytes();
+        let mut txn = self.env.begin_rw_txn()?;
+        let space_uuid = match txn.get(self.space, &space_bytes) {
+            Ok(v) => v.to_vec(),
+            Err(LmdbError::NotFound) => {
+                return Err(Error::SpaceNotFound {
+                    space_name: spacename.to_string(),
+                });
+            }
+            Err(e) => return Err(Error::from(e)),
+        };
+
+        // 型やモードが違っても同名の Key は作れない
+        if self.find_key(&txn, &space_uuid, keyname)?.is_some() {
+            return Err(Error::KeyAlreadyExists {
+                space_name: spacename.to_string(),
+                key_name: keyname.to_string(),
+                location: "io::create_key",
+            });
+        }
+
+        self.put_key(&mut txn, &space_uuid, keyname, keytype, keymode, &option)?;
+
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn drop_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+    ) -> Result<crate::json::output::Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        // 名前が完全一致する Key だけ（"a" で "ab" を消さない）
+        let key = self.get_key(&txn, spacename, keyname, "drop_key")?;
+        self.remove_key(&mut txn, &key)?;
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    // 値は key_uuid で紐づくため、Key レコードの付け替えだけで済む
+    fn rename_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        new_keyname: &str,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let key = self.get_key(&txn, spacename, keyname, "rename_key")?;
+        let space_uuid = &key.record[..16];
+
+        if self.find_key(&txn, space_uuid, new_keyname)?.is_some() {
+            return Err(Error::KeyAlreadyExists {
+                space_name: spacename.to_string(),
+                key_name: new_keyname.to_string(),
+                location: "rename_key",
+            });
+        }
+
+        // [space_uuid][keyname][keytype][keymode] の keyname 部分を差し替える
+        let suffix = &key.record[key.record.len() - 2..];
+        let new_record = [space_uuid, new_keyname.as_bytes(), suffix].concat();
+        txn.del(self.key, &key.record, None)?;
+        txn.put(self.key, &new_record, &key.uuid, WriteFlags::empty())?;
+
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    // 全値を新しい型へ変換する。制約は旧型に対して定義されているため解除する
+    fn alter_key_type(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        keytype: KeyType,
+        drop_failed: bool,
+        user: &str,
+    ) -> Result<AlterReport, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let key = self.get_key(&txn, spacename, keyname, "alter_key_type")?;
+        let old_keytype = key.keytype()?;
+        let mut option = self.key_option(&txn, &key.uuid)?;
+
+        if old_keytype == keytype {
+            return Ok(AlterReport {
+                applied: true,
+                converted: 0,
+                failed: Vec::new(),
+                dropped_constraint: None,
+            });
+        }
+
+        if let Some(pyramid) = &option.pyramid
+            && !keytype.is_numeric()
+            && pyramid
+                .aggregations
+                .iter()
+                .any(|a| matches!(a, Aggregation::Sum | Aggregation::Avg))
+        {
+            return Err(Error::TypeMismatchValue {
+                expected_type: "INT|FLOAT|INT64|DOUBLE".to_string(),
+                received_type: format!("{:?}", keytype),
+                location: "io::alter_key_type",
+            });
+        }
+
+        // 制約は新しい型でも妥当なら引き継ぎ、そうでなければ外して報告する
+        let (constraint, dropped_constraint) = match option.constraint.take() {
+            Some(c) => match convert_constraint(keyname, &c, keytype) {
+                Some(converted) => (Some(converted), None),
+                None => (None, Some(c)),
             },
-            _ => Error::from(e),
+            None => (None, None),
+        };
+
+        // 1. 全値を読み出して変換を試す（引き継ぐ制約に反する値も失敗とする）
+        let mut converted = Vec::new();
+        let mut failed = Vec::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.value)?;
+            for (k, v) in scan_prefix(&mut cursor, &key.uuid) {
+                let id = k[key.uuid.len()..].to_vec();
+                let old = ValueEntry::from_bytes(old_keytype, v);
+                match old
+                    .as_ref()
+                    .and_then(|old| convert_value(old, keytype).map(|new| (old, new)))
+                    .filter(|(_, new)| {
+                        constraint
+                            .as_ref()
+                            .is_none_or(|c| check_constraint(keyname, c, new).is_ok())
+                    }) {
+                    Some((old, new)) => converted.push((id, old.clone(), new)),
+                    None => failed.push(id),
+                }
+            }
+        }
+
+        // 失敗があり削除も許可されていなければ何も変更しない
+        if !failed.is_empty() && !drop_failed {
+            return Ok(AlterReport {
+                applied: false,
+                converted: converted.len(),
+                failed,
+                dropped_constraint,
+            });
+        }
+
+        // 2. 変換できなかった値を削除する
+        let timestamp = now_millis();
+        for id in &failed {
+            self.remove_value(
+                &mut txn,
+                &key.uuid,
+                old_keytype,
+                &option,
+                id,
+                timestamp,
+                user,
+            )?;
+        }
+
+        // 3. 変換した値を書き戻し、ピラミッド・履歴・期限索引を更新する
+        for (id, old, new) in &converted {
+            let db_key = [key.uuid.as_slice(), id].concat();
+            txn.put(self.value, &db_key, &new.to_bytes(), WriteFlags::empty())?;
+            self.apply_pyramid(&mut txn, &key.uuid, &option, id, old, -1)?;
+            self.apply_pyramid(&mut txn, &key.uuid, &option, id, new, 1)?;
+            let entry = HistoryEntry {
+                timestamp,
+                user: user.to_string(),
+                old: Some(old.clone()),
+                new: Some(new.clone()),
+            };
+            self.record_history(&mut txn, &key.uuid, &option, id, entry)?;
+            if let Some(expires_at) = self.expires_at(&txn, &db_key)? {
+                let index_key = [&expires_at.to_be_bytes(), db_key.as_slice()].concat();
+                txn.put(
+                    self.expiry_index,
+                    &index_key,
+                    &[keytype_id(keytype)],
+                    WriteFlags::empty(),
+                )?;
+            }
+        }
+
+        // 4. Key レコードの型と制約を差し替える
+        let mut new_record = key.record.clone();
+        let type_pos = new_record.len() - 2;
+        new_record[type_pos] = keytype_id(keytype);
+        txn.del(self.key, &key.record, None)?;
+        txn.put(self.key, &new_record, &key.uuid, WriteFlags::empty())?;
+
+        option.constraint = constraint;
+        let option_bytes = serde_json::to_vec(&option).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "io::alter_key_type",
         })?;
+        txn.put(
+            self.key_option,
+            &key.uuid,
+            &option_bytes,
+            WriteFlags::empty(),
+        )?;
 
-        let mut result_map: HashMap<Vec<u8>, Vec<(String, ValueEntry)>> = HashMap::new();
+        txn.commit()?;
+        Ok(AlterReport {
+            applied: true,
+            converted: converted.len(),
+            failed,
+            dropped_constraint,
+        })
+    }
 
-        for keyname in keynames {
-            // 2. Key UUID と KeyType の取得
-            let (key_uuid, keytype) = {
-                let mut key_cursor = txn.open_ro_cursor(self.key)?;
-                let mut found = None;
-                for (k, v) in key_cursor.iter_start() {
-                    if k.starts_with(space_uuid) && std::str::from_utf8(k)?.contains(&keyname) {
-                        let keytype_id_byte = k[k.len() - 2];
-                        found = Some((v.to_vec(), id_keytype(keytype_id_byte)));
-                        break;
-                    }
+    fn copy_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        to_spacename: &str,
+        to_keyname: &str,
+        ids: Option<Vec<Vec<u8>>>,
+        user: &str,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let from = self.get_key(&txn, spacename, keyname, "copy_key")?;
+        let to = self.get_key(&txn, to_spacename, to_keyname, "copy_key")?;
+        let keytype = from.keytype()?;
+        let to_keytype = to.keytype()?;
+        if keytype != to_keytype {
+            return Err(Error::TypeMismatchValue {
+                expected_type: format!("{:?}", to_keytype),
+                received_type: format!("{:?}", keytype),
+                location: "copy_key",
+            });
+        }
+        let option = self.key_option(&txn, &to.uuid)?;
+
+        // 1. コピー元の値を集める（範囲の重複は BTreeMap でまとめる）
+        let timestamp = now_millis();
+        let prefixes = ids.unwrap_or_else(|| vec![Vec::new()]);
+        let mut values = BTreeMap::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.value)?;
+            for id in &prefixes {
+                let prefix = [from.uuid.as_slice(), id].concat();
+                for (k, v) in scan_prefix(&mut cursor, &prefix) {
+                    values.insert(k.to_vec(), v.to_vec());
                 }
-                found.ok_or(Error::KeyNotFound {
+            }
+        }
+
+        // 2. コピー先の制約を確認しつつ、既存値を置き換える
+        for (db_key, bytes) in values {
+            if self.is_expired(&txn, &db_key, timestamp)? {
+                continue;
+            }
+            let id = &db_key[from.uuid.len()..];
+            let value = ValueEntry::from_bytes(keytype, &bytes).ok_or(Error::NnKnown)?;
+            let value =
+                self.checked_value(to_keyname, keytype, &option, Some(value), "copy_key")?;
+            let expires_at = self.expires_at(&txn, &db_key)?;
+
+            // コピー先の期限切れの値は先に期限切れとして消し、それ以外は put_value で置き換える
+            let to_key = [to.uuid.as_slice(), id].concat();
+            if self.is_expired(&txn, &to_key, timestamp)? {
+                self.remove_value(
+                    &mut txn, &to.uuid, keytype, &option, id, timestamp, "expiry",
+                )?;
+            }
+            self.put_value(
+                &mut txn, &to.uuid, keytype, &option, id, &value, expires_at, timestamp, user,
+            )?;
+        }
+
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn show_keys(&self, spacename: &str) -> Result<crate::json::output::Output, Error> {
+        let space_bytes = spacename.as_bytes();
+        let txn = self.env.begin_ro_txn()?;
+        let space_uuid = match txn.get(self.space, &space_bytes) {
+            Ok(v) => v,
+            Err(LmdbError::NotFound) => {
+                return Err(Error::SpaceNotFound {
+                    space_name: spacename.to_string(),
+                });
+            }
+            Err(e) => return Err(Error::from(e)),
+        };
+
+        let mut cursor = txn.open_ro_cursor(self.key)?;
+        let mut keys = Vec::new();
+        for (k, _v) in cursor.iter_start() {
+            if k.starts_with(space_uuid) {
+                let keyname_len = k.len() - space_uuid.len() - 2;
+                let keyname_bytes = &k[space_uuid.len()..space_uuid.len() + keyname_len];
+                let keyname = std::str::from_utf8(keyname_bytes)?.to_string();
+                keys.push(keyname);
+            }
+        }
+
+        Ok(Output::Showkeys(Showkeys { keynames: keys }))
+    }
+    fn info_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+    ) -> Result<crate::json::output::Output, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let key = self.get_key(&txn, spacename, keyname, "info_key")?;
+        let k = &key.record;
+
+        // keytype と keymode を取得
+        let keytype = key.keytype()?;
+        let keymode = KeyMode::try_from(k[k.len() - 1]).map_err(|_| Error::ParseError {
+            message: "Invalid keymode value".to_string(),
+            location: "info_key",
+        })?;
+        let option = self.key_option(&txn, &key.uuid)?;
+
+        Ok(Output::InfoKey(InfoKey {
+            keyname: keyname.to_string(),
+            keytype: format!("{:?}", keytype),
+            keymode: format!("{:?}", keymode),
+            pyramid: option.pyramid,
+            history: option.history,
+            constraint: option.constraint.map(Box::new),
+        }))
+    }
+
+    fn key_type(&self, spacename: &str, keyname: &str) -> Result<KeyType, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        self.get_key(&txn, spacename, keyname, "key_type")?
+            .keytype()
+    }
+
+    fn insert_value(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        ids: Vec<Vec<u8>>,
+        value: ValueEntry,
+        expires_at: Option<u64>,
+        user: &str,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+
+        // 1. Space / Key 存在確認 & key_uuid取得
+        let key = self.get_key(&txn, spacename, keyname, "insert_value")?;
+        let keytype = key.keytype()?;
+        let option = self.key_option(&txn, &key.uuid)?;
+
+        // 2. 型チェック、制約チェック
+        let value = self.checked_value(keyname, keytype, &option, Some(value), "insert_value")?;
+
+        // 3. すべてのIDを事前チェック（重複が1つでもあればエラー）
+        //    期限切れでまだ掃除されていない値は存在しないものとして扱う
+        let timestamp = now_millis();
+        for id in &ids {
+            let db_key = [key.uuid.as_slice(), id].concat();
+            if txn.get(self.value, &db_key).is_ok() && !self.is_expired(&txn, &db_key, timestamp)? {
+                return Err(Error::InsertError {
                     space_name: spacename.to_string(),
                     key_name: keyname.to_string(),
-                    location: "select_value",
-                })?
+                });
+            }
+        }
+
+        // 4. すべて重複なしならまとめて LMDB に保存し、ピラミッド・履歴も同じトランザクションで更新
+        for id in ids {
+            self.remove_value(
+                &mut txn, &key.uuid, keytype, &option, &id, timestamp, "expiry",
+            )?;
+            self.put_value(
+                &mut txn, &key.uuid, keytype, &option, &id, &value, expires_at, timestamp, user,
+            )?;
+        }
+
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn patch_value(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        ids: Vec<Vec<u8>>,
+        value: ValueEntry,
+        expires_at: Option<u64>,
+        user: &str,
+    ) -> Result<crate::json::output::Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+
+        // 1. Space / Key 存在確認
+        let key = self.get_key(&txn, spacename, keyname, "patch_value")?;
+        let keytype = key.keytype()?;
+        let option = self.key_option(&txn, &key.uuid)?;
+
+        // 2. 型チェック、制約チェック
+        let value = self.checked_value(keyname, keytype, &option, Some(value), "patch_value")?;
+
+        // 3. IDごとに既存値確認 & 新規挿入
+        let timestamp = now_millis();
+        for id in ids {
+            let db_key = [key.uuid.as_slice(), &id].concat();
+
+            // 既に存在する場合はスキップ（期限切れなら先に取り除く）
+            if txn.get(self.value, &db_key).is_ok() {
+                if !self.is_expired(&txn, &db_key, timestamp)? {
+                    continue;
+                }
+                self.remove_value(
+                    &mut txn, &key.uuid, keytype, &option, &id, timestamp, "expiry",
+                )?;
+            }
+
+            // 存在しなければ挿入
+            self.put_value(
+                &mut txn, &key.uuid, keytype, &option, &id, &value, expires_at, timestamp, user,
+            )?;
+        }
+
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn update_value(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        ids: Vec<Vec<u8>>,
+        value: Option<ValueEntry>,
+        user: &str,
+    ) -> Result<crate::json::output::Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+
+        // 1. Space / Key 存在確認
+        let key = self.get_key(&txn, spacename, keyname, "update_value")?;
+        let keytype = key.keytype()?;
+        let option = self.key_option(&txn, &key.uuid)?;
+
+        // 2. 省略時の default 解決、型チェック、制約チェック
+        let value = self.checked_value(keyname, keytype, &option, value, "update_value")?;
+
+        // 3. 範囲内に既に存在する値だけを上書きする（有効期限はそのまま）
+        let mut targets: Vec<Vec<u8>> = Vec::new();
+        for id in &ids {
+            let prefix = [key.uuid.as_slice(), id].concat();
+            let mut cursor = txn.open_ro_cursor(self.value)?;
+            for (k, _v) in scan_prefix(&mut cursor, &prefix) {
+                targets.push(k.to_vec());
+            }
+        }
+        targets.sort();
+        targets.dedup();
+
+        let timestamp = now_millis();
+        for db_key in targets {
+            if self.is_expired(&txn, &db_key, timestamp)? {
+                continue;
+            }
+            let id = &db_key[key.uuid.len()..];
+            let old = ValueEntry::from_bytes(keytype, txn.get(self.value, &db_key)?)
+                .ok_or(Error::NnKnown)?;
+            txn.put(self.value, &db_key, &value.to_bytes(), WriteFlags::empty())?;
+            self.apply_pyramid(&mut txn, &key.uuid, &option, id, &old, -1)?;
+            self.apply_pyramid(&mut txn, &key.uuid, &option, id, &value, 1)?;
+            let entry = HistoryEntry {
+                timestamp,
+                user: user.to_string(),
+                old: Some(old),
+                new: Some(value.clone()),
             };
+            self.record_history(&mut txn, &key.uuid, &option, id, entry)?;
+        }
 
-            // 3. value DB を全走査
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn delete_value(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        ids: Vec<Vec<u8>>,
+        user: &str,
+    ) -> Result<crate::json::output::Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+
+        // 1. Space / Key 存在確認 & key_uuid取得
+        let key = self.get_key(&txn, spacename, keyname, "delete_value")?;
+        let keytype = key.keytype()?;
+        let option = self.key_option(&txn, &key.uuid)?;
+
+        // 2. IDsごとに前方一致で削除対象を集める
+        let mut to_delete: Vec<Vec<u8>> = Vec::new();
+        for id in &ids {
+            let prefix = [key.uuid.as_slice(), id].concat();
             let mut cursor = txn.open_ro_cursor(self.value)?;
-            for (k, v) in cursor.iter_start() {
-                if !k.starts_with(&key_uuid) {
-                    continue;
+            for (k, _v) in scan_prefix(&mut cursor, &prefix) {
+                to_delete.push(k[key.uuid.len()..].to_vec());
+            }
+        }
+        to_delete.sort();
+        to_delete.dedup();
+
+        // 3. 削除（ピラミッド・履歴・有効期限も同じトランザクションで更新）
+        let timestamp = now_millis();
+        for id in to_delete {
+            self.remove_value(&mut txn, &key.uuid, keytype, &option, &id, timestamp, user)?;
+        }
+
+        txn.commit()?;
+        Ok(crate::json::output::Output::Success)
+    }
+
+    fn select_value(
+        &self,
+        spacename: &str,
+        keynames: Vec<String>,
+        ids: Vec<Vec<u8>>,
+        as_of: Option<u64>,
+    ) -> Result<ValueMap, Error> {
+        let txn = self.env.begin_ro_txn()?;
+
+        // 1. Space UUID の取得
+        let space_bytes = spacename.as_bytes();
+        let space_uuid = txn.get(self.space, &space_bytes).map_err(|e| match e {
+            LmdbError::NotFound => Error::SpaceNotFound {
+                space_name: spacename.to_string(),
+            },
+            _ => Error::from(e),
+        })?;
+
+        let mut result_map: ValueMap = HashMap::new();
+        let now = now_millis();
+
+        for keyname in keynames {
+            // 2. Key UUID と KeyType の取得
+            let (key_uuid, keytype) = match self.find_key(&txn, space_uuid, &keyname)? {
+                Some(key) => (key.uuid.clone(), key.keytype()?),
+                None => {
+                    return Err(Error::KeyNotFound {
+                        space_name: spacename.to_string(),
+                        key_name: keyname.to_string(),
+                        location: "select_value",
+                    });
                 }
+            };
 
+            // as_of 指定時は履歴から各セルの当時の値を復元する
+            if let Some(as_of) = as_of {
+                if !self.key_option(&txn, &key_uuid)?.history {
+                    return Err(Error::HistoryNotEnabled {
+                        key_name: keyname.to_string(),
+                        location: "select_value",
+                    });
+                }
+
+                // セルごとに as_of 以前の最後の記録と、全体で最後の記録の時刻
+                let mut latest: HashMap<Vec<u8>, (u64, Option<ValueEntry>)> = HashMap::new();
+                let mut last: HashMap<Vec<u8>, u64> = HashMap::new();
+                for id in &ids {
+                    for (id_bytes, entry) in self.scan_history(&txn, &key_uuid, id)? {
+                        last.insert(id_bytes.clone(), entry.timestamp);
+                        if entry.timestamp <= as_of {
+                            latest.insert(id_bytes, (entry.timestamp, entry.new));
+                        }
+                    }
+                }
+                for (id_bytes, (timestamp, value)) in latest {
+                    // 今の値が as_of までに期限切れなら、掃除で履歴に残る前でも無いものとする
+                    let db_key = [key_uuid.as_slice(), &id_bytes].concat();
+                    if last[&id_bytes] == timestamp && self.is_expired(&txn, &db_key, as_of)? {
+                        continue;
+                    }
+                    if let Some(value_entry) = value {
+                        result_map
+                            .entry(id_bytes)
+                            .or_default()
+                            .push((keyname.to_string(), value_entry));
+                    }
+                }
+                continue;
+            }
+
+            // 3. value DB から key_uuid で始まる値を走査
+            let mut cursor = txn.open_ro_cursor(self.value)?;
+            for (k, v) in scan_prefix(&mut cursor, &key_uuid) {
                 let id_bytes = k[key_uuid.len()..].to_vec();
 
                 // 入力された ids のいずれかで前方一致するか
-                if ids.iter().any(|input_id| id_bytes.starts_with(input_id)) {
+                if ids.iter().any(|input_id| id_bytes.starts_with(input_id))
+                    && !self.is_expired(&txn, k, now)?
+                {
                     let value_entry = ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?;
                     result_map
                         .entry(id_bytes.clone())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push((keyname.to_string(), value_entry));
                 }
             }
@@ -713,11 +2206,7 @@ impl StorageTrait for Storage {
         Ok(result_map)
     }
 
-    fn show_values(
-        &self,
-        spacename: &str,
-        keyname: &str,
-    ) -> Result<HashMap<Vec<u8>, Vec<(String, ValueEntry)>>, Error> {
+    fn show_values(&self, spacename: &str, keyname: &str) -> Result<ValueMap, Error> {
         let txn = self.env.begin_ro_txn()?;
 
         // 1. SpaceのUUIDを取得
@@ -730,78 +2219,362 @@ impl StorageTrait for Storage {
         })?;
 
         // 2. KeyのUUIDとKeyTypeを取得
-        let (key_uuid, keytype) = {
-            let mut key_cursor = txn.open_ro_cursor(self.key)?;
-            let mut found = None;
-            for (k, v) in key_cursor.iter_start() {
-                if k.starts_with(space_uuid) && std::str::from_utf8(k)?.contains(keyname) {
-                    let keytype_id_byte = k[k.len() - 2];
-                    found = Some((v.to_vec(), id_keytype(keytype_id_byte)));
-                    break;
-                }
+        let (key_uuid, keytype) = match self.find_key(&txn, space_uuid, keyname)? {
+            Some(key) => (key.uuid.clone(), key.keytype()?),
+            None => {
+                return Err(Error::KeyNotFound {
+                    space_name: spacename.to_string(),
+                    key_name: keyname.to_string(),
+                    location: "show_values",
+                });
             }
-            found.ok_or(Error::KeyNotFound {
-                space_name: spacename.to_string(),
-                key_name: keyname.to_string(),
-                location: "show_values",
-            })?
         };
 
         // 3. value DB から key_uuid で始まる全ての値を取得
         let mut cursor = txn.open_ro_cursor(self.value)?;
-        let mut result_map: HashMap<Vec<u8>, Vec<(String, ValueEntry)>> = HashMap::new();
+        let mut result_map: ValueMap = HashMap::new();
 
-        for result in cursor.iter_start() {
-            let (k, v) = result;
-            if k.starts_with(&key_uuid) {
-                // k の先頭16バイトは key_uuid, 残りが id_bytes
-                let id_bytes = k[key_uuid.len()..].to_vec();
-                let value_entry = ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?;
+        let now = now_millis();
 
-                result_map
-                    .entry(id_bytes)
-                    .or_insert_with(Vec::new)
-                    .push((keyname.to_string(), value_entry));
+        for (k, v) in scan_prefix(&mut cursor, &key_uuid) {
+            if self.is_expired(&txn, k, now)? {
+                continue;
             }
+            // k の先頭16バイトは key_uuid, 残りが id_bytes
+            let id_bytes = k[key_uuid.len()..].to_vec();
+            let value_entry = ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?;
+
+            result_map
+                .entry(id_bytes)
+                .or_default()
+                .push((keyname.to_string(), value_entry));
         }
 
         Ok(result_map)
     }
 
-    fn create_user(&self, username: &str, password: &str) -> Result<Output, Error> {
-        let mut txn = self.env.begin_rw_txn()?;
+    fn import_rows(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        rows: Vec<ImportRow>,
+        on_conflict: OnConflict,
+        batch_size: usize,
+        user: &str,
+    ) -> Result<ImportReport, Error> {
+        let mut report = ImportReport {
+            imported: 0,
+            skipped: 0,
+            errors: Vec::new(),
+        };
 
-        // ユーザー名が既に存在するか確認
-        if txn.get(self.user, &username.as_bytes()).is_ok() {
-            return Err(Error::UserAlreadyExists {
-                user_name: username.to_string(),
+        for batch in rows.chunks(batch_size.max(1)) {
+            let mut txn = self.env.begin_rw_txn()?;
+            let key = self.get_key(&txn, spacename, keyname, "import_rows")?;
+            let keytype = key.keytype()?;
+            let option = self.key_option(&txn, &key.uuid)?;
+            let timestamp = now_millis();
+
+            for row in batch {
+                let value = match self.checked_value(
+                    keyname,
+                    keytype,
+                    &option,
+                    row.value.clone(),
+                    "import_rows",
+                ) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        report.errors.push((row.row, e));
+                        continue;
+                    }
+                };
+
+                let db_key = [key.uuid.as_slice(), &row.id].concat();
+                if txn.get(self.value, &db_key).is_ok() {
+                    if self.is_expired(&txn, &db_key, timestamp)? {
+                        self.remove_value(
+                            &mut txn, &key.uuid, keytype, &option, &row.id, timestamp, "expiry",
+                        )?;
+                    } else {
+                        match on_conflict {
+                            OnConflict::Error => {
+                                report.errors.push((
+                                    row.row,
+                                    Error::ValueAlreadyExists {
+                                        space_time_id: bitmask_to_id_string(&row.id)?,
+                                        location: "import_rows",
+                                    },
+                                ));
+                                continue;
+                            }
+                            OnConflict::Skip => {
+                                report.skipped += 1;
+                                continue;
+                            }
+                            // put_value が置き換える
+                            OnConflict::Overwrite => {}
+                        }
+                    }
+                }
+
+                self.put_value(
+                    &mut txn, &key.uuid, keytype, &option, &row.id, &value, None, timestamp, user,
+                )?;
+                report.imported += 1;
+            }
+
+            txn.commit()?;
+        }
+
+        Ok(report)
+    }
+
+    fn scan_values(
+        &self,
+        spacename: &str,
+        keynames: &[String],
+        ids: Option<Vec<Vec<u8>>>,
+        visit: &mut ValueVisitor,
+    ) -> Result<(), Error> {
+        let keys = {
+            let txn = self.env.begin_ro_txn()?;
+            let mut keys = Vec::with_capacity(keynames.len());
+            for keyname in keynames {
+                let key = self.get_key(&txn, spacename, keyname, "scan_values")?;
+                let keytype = key.keytype()?;
+                keys.push((key.uuid, keytype));
+            }
+            keys
+        };
+
+        // 重なる範囲をまとめ、ビット列順に走査する
+        let mut prefixes = dedup_bitmasks(ids.unwrap_or_else(|| vec![Vec::new()]));
+        prefixes.sort();
+
+        // export_space と同じく EXPORT_BATCH セルごとにトランザクションを開き直し、続きから読む
+        let now = now_millis();
+        for prefix in prefixes {
+            let key_prefixes: Vec<Vec<u8>> = keys
+                .iter()
+                .map(|(uuid, _)| [uuid.as_slice(), &prefix].concat())
+                .collect();
+            let mut start = prefix.clone();
+            loop {
+                let mut batch = Vec::new();
+                let mut next = None;
+                {
+                    let txn = self.env.begin_ro_txn()?;
+                    let starts: Vec<Vec<u8>> = keys
+                        .iter()
+                        .map(|(uuid, _)| [uuid.as_slice(), &start].concat())
+                        .collect();
+                    // Key ごとのカーソルはどれもビット列順なので、先頭が最小のものから取り出して併合する
+                    let mut cursors = keys
+                        .iter()
+                        .map(|_| txn.open_ro_cursor(self.value))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let mut iters: Vec<_> = cursors
+                        .iter_mut()
+                        .zip(&starts)
+                        .zip(&key_prefixes)
+                        .map(|((cursor, start), p)| {
+                            scan_from(cursor, start)
+                                .take_while(move |(k, _)| k.starts_with(p))
+                                .peekable()
+                        })
+                        .collect();
+
+                    let mut scanned = 0;
+                    loop {
+                        let id = iters
+                            .iter_mut()
+                            .zip(&keys)
+                            .filter_map(|(it, (uuid, _))| it.peek().map(|(k, _)| &k[uuid.len()..]))
+                            .min()
+                            .map(|id| id.to_vec());
+                        let Some(id) = id else {
+                            break;
+                        };
+                        if scanned == EXPORT_BATCH {
+                            next = Some(id);
+                            break;
+                        }
+                        scanned += 1;
+
+                        let mut row = vec![None; keys.len()];
+                        for (i, (it, (uuid, keytype))) in iters.iter_mut().zip(&keys).enumerate() {
+                            let Some((k, v)) = it.next_if(|(k, _)| k[uuid.len()..] == id[..])
+                            else {
+                                continue;
+                            };
+                            if !self.is_expired(&txn, k, now)? {
+                                row[i] = Some(
+                                    ValueEntry::from_bytes(*keytype, v).ok_or(Error::NnKnown)?,
+                                );
+                            }
+                        }
+                        if row.iter().any(Option::is_some) {
+                            batch.push((id, row));
+                        }
+                    }
+                }
+                for (id, row) in &batch {
+                    visit(id, row)?;
+                }
+                match next {
+                    Some(id) => start = id,
+                    None => break,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn select_tile(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> Result<Vec<(Vec<u8>, ValueEntry)>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let key = self.get_key(&txn, spacename, keyname, "select_tile")?;
+        let keytype = key.keytype()?;
+
+        // f のビットが x, y と交互に並ぶため前方一致では絞れない。x, y のビットが
+        // タイルと食い違ったら、その位置までを共有する部分木を丸ごと読み飛ばす
+        let now = now_millis();
+        let mut result = Vec::new();
+        let cursor = txn.open_ro_cursor(self.value)?;
+        let mut next = cursor.get(Some(&key.uuid), None, lmdb_sys::MDB_SET_RANGE);
+        while let Ok((Some(k), v)) = next {
+            if !k.starts_with(&key.uuid) {
+                break;
+            }
+            let bits = &k[key.uuid.len()..];
+            let levels = ((bits.len() - 1) / 3).min(z as usize);
+            let mismatch = (0..levels)
+                .flat_map(|level| {
+                    let shift = z as usize - 1 - level;
+                    [
+                        (1 + level * 3, ((x >> shift) & 1) as u8),
+                        (2 + level * 3, ((y >> shift) & 1) as u8),
+                    ]
+                })
+                .find(|&(pos, bit)| bits[pos] != bit);
+            if let Some((pos, _)) = mismatch {
+                // ビットは 0 / 1 なので、その位置を 1 増やしたキーが次の候補の下限になる
+                let mut seek = k[..key.uuid.len() + pos].to_vec();
+                seek.push(bits[pos] + 1);
+                next = cursor.get(Some(&seek), None, lmdb_sys::MDB_SET_RANGE);
+                continue;
+            }
+            if !self.is_expired(&txn, k, now)? {
+                let value = ValueEntry::from_bytes(keytype, v).ok_or(Error::NnKnown)?;
+                result.push((bits.to_vec(), value));
+            }
+            next = cursor.get(None, None, lmdb_sys::MDB_NEXT);
+        }
+
+        Ok(result)
+    }
+
+    fn select_pyramid(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        zoom: u8,
+        ids: Vec<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, PyramidCell)>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let key = self.get_key(&txn, spacename, keyname, "select_pyramid")?;
+        let option = self.key_option(&txn, &key.uuid)?;
+
+        let configured = option
+            .pyramid
+            .as_ref()
+            .is_some_and(|p| p.zooms.contains(&zoom));
+        if !configured {
+            return Err(Error::PyramidLevelNotFound {
+                key_name: keyname.to_string(),
+                zoom,
+                location: "select_pyramid",
+            });
+        }
+
+        let len = 1 + (zoom as usize) * 3;
+        let level_prefix = [key.uuid.as_slice(), &[zoom]].concat();
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+
+        for id in ids {
+            // 範囲がレベルより細かい場合は、それを含む上位セルを返す
+            let id = if id.len() > len { &id[..len] } else { &id[..] };
+            let prefix = [level_prefix.as_slice(), id].concat();
+            let mut cursor = txn.open_ro_cursor(self.pyramid)?;
+            for (k, v) in scan_prefix(&mut cursor, &prefix) {
+                let id_bytes = k[level_prefix.len()..].to_vec();
+                if !seen.insert(id_bytes.clone()) {
+                    continue;
+                }
+                let cell = PyramidCell::from_bytes(v).ok_or(Error::ParseError {
+                    message: "Invalid pyramid cell".to_string(),
+                    location: "select_pyramid",
+                })?;
+                result.push((id_bytes, cell));
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn value_history(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        ids: Vec<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, HistoryEntry)>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let key = self.get_key(&txn, spacename, keyname, "value_history")?;
+
+        if !self.key_option(&txn, &key.uuid)?.history {
+            return Err(Error::HistoryNotEnabled {
+                key_name: keyname.to_string(),
+                location: "value_history",
             });
         }
 
-        // ソルトを生成
-        let salt = SaltString::generate(&mut OsRng);
-
-        // Argon2 インスタンスを作成
-        let argon2 = Argon2::default();
+        let mut result = Vec::new();
+        for id in &ids {
+            result.extend(self.scan_history(&txn, &key.uuid, id)?);
+        }
+        result.sort_by_key(|(_, entry)| entry.timestamp);
 
-        // パスワードをハッシュ化
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|_| Error::LmdbError {
-                message: "Password hash failed".to_string(),
-                location: "create_user",
-            })?
-            .to_string();
+        Ok(result)
+    }
 
-        // ユーザー名とハッシュを保存
-        txn.put(
-            self.user,
-            &username.as_bytes(),
-            &password_hash.as_bytes(),
-            lmdb::WriteFlags::empty(),
-        )?;
-        txn.commit()?;
+    fn purge_expired(&self, now: u64) -> Result<usize, Error> {
+        // 書き込みトランザクションを長く持たないよう PURGE_BATCH 件ずつ消す
+        let mut purged = 0;
+        loop {
+            let (n, done) = self.purge_expired_batch(now)?;
+            purged += n;
+            if done {
+                return Ok(purged);
+            }
+        }
+    }
 
+    fn create_user(
+        &self,
+        username: &str,
+        password: &str,
+        roles: &[String],
+    ) -> Result<Output, Error> {
+        self.insert_user(username, password, false, roles)?;
         Ok(Output::Success)
     }
 
@@ -815,7 +2588,11 @@ impl StorageTrait for Storage {
         let mut txn = self.env.begin_rw_txn()?;
         match txn.del(self.user, &username.as_bytes(), None) {
             Ok(_) => {
+                // ユーザーの API トークンも消す
+                self.delete_user_tokens(&mut txn, username)?;
+                self.remove_from_acls(&mut txn, Some(username), None)?;
                 txn.commit()?;
+                self.kill_user_sessions(username)?;
                 Ok(Output::Success)
             }
             Err(LmdbError::NotFound) => Err(Error::UserNotFound {
@@ -827,13 +2604,10 @@ impl StorageTrait for Storage {
 
     fn info_user(&self, username: &str) -> Result<Output, Error> {
         let txn = self.env.begin_ro_txn()?;
-        let hash_bytes =
-            txn.get(self.user, &username.as_bytes())
-                .map_err(|_| Error::UserNotFound {
-                    user_name: username.to_string(),
-                })?;
+        let record = self.user_record(&txn, username)?;
         Ok(Output::InfoUser(InfoUser {
             user_name: username.to_string(),
+            roles: record.roles(username),
         }))
     }
     fn show_users(&self) -> Result<Output, Error> {
@@ -854,29 +2628,944 @@ impl StorageTrait for Storage {
     fn verify_user(&self, username: &str, password: &str) -> Result<bool, Error> {
         let txn = self.env.begin_ro_txn()?;
 
-        // ユーザー名に対応するハッシュを取得
-        let hash_bytes = match txn.get(self.user, &username.as_bytes()) {
-            Ok(v) => v,
-            Err(_) => return Ok(false), // ユーザーが存在しない場合は false
+        // ユーザーが存在しない場合は false
+        match self.user_record(&txn, username) {
+            Ok(record) => verify_password(&record, password),
+            Err(Error::UserNotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn change_password(
+        &self,
+        username: &str,
+        old_password: &str,
+        new_password: &str,
+        keep_session: Option<&str>,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let record = self.user_record(&txn, username)?;
+        if !verify_password(&record, old_password)? {
+            return Err(Error::AuthenticationFailed {
+                user_name: username.to_string(),
+                location: "change_password",
+            });
+        }
+
+        let mut record = record;
+        record.password_hash = hash_password(new_password, "change_password")?;
+        record.must_change_password = false;
+        self.put_user(&mut txn, username, &record)?;
+        self.delete_user_tokens(&mut txn, username)?;
+        txn.commit()?;
+        self.remove_user_sessions(username, keep_session)?;
+        Ok(Output::Success)
+    }
+
+    fn reset_password(
+        &self,
+        username: &str,
+        new_password: &str,
+        must_change_password: bool,
+    ) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let mut record = self.user_record(&txn, username)?;
+        record.password_hash = hash_password(new_password, "reset_password")?;
+        record.must_change_password = must_change_password;
+        self.put_user(&mut txn, username, &record)?;
+        self.delete_user_tokens(&mut txn, username)?;
+        txn.commit()?;
+        self.remove_user_sessions(username, None)?;
+        Ok(Output::Success)
+    }
+
+    fn must_change_password(&self, username: &str) -> Result<bool, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        Ok(self.user_record(&txn, username)?.must_change_password)
+    }
+
+    fn begin_login(&self, username: &str, ip: &str) -> Result<Option<u64>, Error> {
+        Ok(self
+            .login_throttle()
+            .begin_attempt(username, ip, now_millis()))
+    }
+
+    fn record_login_success(&self, username: &str, ip: &str) -> Result<(), Error> {
+        self.login_throttle().record_success(username, ip);
+        Ok(())
+    }
+
+    fn unlock_user(&self, username: &str) -> Result<bool, Error> {
+        if !self.user_exists(username)? {
+            return Err(Error::UserNotFound {
+                user_name: username.to_string(),
+            });
+        }
+        Ok(self.login_throttle().unlock_user(username, now_millis()))
+    }
+
+    fn create_session(&self, username: &str) -> Result<String, Error> {
+        let mut table = self.live_sessions();
+        if table.sessions.len() >= self.max_sessions {
+            return Err(Error::SessionLimitReached {
+                max_sessions: self.max_sessions,
+            });
+        }
+
+        let token = Uuid::new_v4().to_string();
+        let now = now_millis();
+        let session = Session {
+            id: token_hash(&token),
+            user_name: username.to_string(),
+            created_at: now,
+            last_access: now,
+            expires_at: now + self.session_timeout.as_millis() as u64,
+        };
+        let mut txn = self.env.begin_rw_txn()?;
+        self.put_session(&mut txn, &session)?;
+        txn.commit()?;
+        table.sessions.insert(session.id.clone(), session);
+        Ok(token)
+    }
+
+    // 有効なら最終アクセス時刻を延ばしてユーザー名を返す
+    fn session_user(&self, token: &str) -> Result<Option<String>, Error> {
+        let id = token_hash(token);
+        let mut table = self.live_sessions();
+        let Some(session) = table.sessions.get_mut(&id) else {
+            return Ok(None);
+        };
+        let now = now_millis();
+        session.last_access = now;
+        session.expires_at = now + self.session_timeout.as_millis() as u64;
+        let user = session.user_name.clone();
+        table.touched.insert(id);
+        Ok(Some(user))
+    }
+
+    // ログアウト。終了したセッションのユーザー名を返す
+    fn drop_session(&self, token: &str) -> Result<Option<String>, Error> {
+        let id = token_hash(token);
+        let user = self
+            .live_sessions()
+            .sessions
+            .get(&id)
+            .map(|s| s.user_name.clone());
+        let Some(user) = user else {
+            return Ok(None);
+        };
+        self.remove_sessions(&[id])?;
+        Ok(Some(user))
+    }
+
+    fn show_sessions(&self) -> Result<Vec<Session>, Error> {
+        let mut sessions: Vec<Session> = self.live_sessions().sessions.values().cloned().collect();
+        sessions.sort_by_key(|session| session.created_at);
+        Ok(sessions)
+    }
+
+    fn kill_session(&self, id: &str) -> Result<Output, Error> {
+        if !self.live_sessions().sessions.contains_key(id) {
+            return Err(Error::SessionNotFound {
+                session_id: id.to_string(),
+            });
+        }
+        self.remove_sessions(&[id.to_string()])?;
+        Ok(Output::Success)
+    }
+
+    fn kill_user_sessions(&self, username: &str) -> Result<usize, Error> {
+        self.remove_user_sessions(username, None)
+    }
+
+    // 延長したセッションの期限を書き込み、期限切れのセッションを session DB から消す
+    // 戻り値は消したセッションの数
+    fn sweep_sessions(&self, now: u64) -> Result<usize, Error> {
+        // 書き込む行を写してからロックを外し、LMDB のコミット中に認証を止めない
+        let touched: Vec<Session> = {
+            let mut table = self.live_sessions();
+            let SessionTable { sessions, touched } = &mut *table;
+            touched
+                .drain()
+                .filter_map(|id| sessions.get(&id).cloned())
+                .collect()
+        };
+
+        let mut txn = self.env.begin_rw_txn()?;
+        for session in &touched {
+            // 写した後にログアウト等で消えたセッションを書き戻さない
+            // （書き込みトランザクションは直列なので、ここで無ければ消されている）
+            if txn.get(self.session, &session.id.as_bytes()).is_ok() {
+                self.put_session(&mut txn, session)?;
+            }
+        }
+
+        let mut expired = Vec::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.session)?;
+            for (k, v) in scan_prefix(&mut cursor, &[]) {
+                let live = serde_json::from_slice::<Session>(v)
+                    .map(|session| session.expires_at > now)
+                    .unwrap_or(false);
+                if !live {
+                    expired.push(k.to_vec());
+                }
+            }
+        }
+        for k in &expired {
+            txn.del(self.session, k, None)?;
+        }
+        txn.commit()?;
+        Ok(expired.len())
+    }
+
+    fn create_token(
+        &self,
+        username: &str,
+        token_name: &str,
+        expires_at: Option<u64>,
+        scope: TokenScope,
+    ) -> Result<(String, ApiToken), Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        self.user_record(&txn, username)?;
+
+        let token = format!("{}{}", API_TOKEN_PREFIX, random_token(API_TOKEN_LEN));
+        let record = ApiToken {
+            id: token_hash(&token),
+            token_name: token_name.to_string(),
+            user_name: username.to_string(),
+            created_at: now_millis(),
+            expires_at,
+            scope,
         };
+        let bytes = serde_json::to_vec(&record).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "create_token",
+        })?;
+        txn.put(
+            self.token,
+            &record.id.as_bytes(),
+            &bytes,
+            WriteFlags::empty(),
+        )?;
+        txn.commit()?;
+        Ok((token, record))
+    }
+
+    // 有効な API トークンならその内容を返す
+    fn token_user(&self, token: &str) -> Result<Option<ApiToken>, Error> {
+        match self.get_token(&token_hash(token)) {
+            Ok(record) if record.expires_at.is_none_or(|t| t > now_millis()) => Ok(Some(record)),
+            Ok(_) | Err(Error::TokenNotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_token(&self, id: &str) -> Result<ApiToken, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let bytes = txn.get(self.token, &id.as_bytes()).map_err(|e| match e {
+            LmdbError::NotFound => Error::TokenNotFound {
+                token_id: id.to_string(),
+            },
+            _ => Error::from(e),
+        })?;
+        let record: ApiToken = serde_json::from_slice(bytes).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "get_token",
+        })?;
+        Ok(ApiToken {
+            id: id.to_string(),
+            ..record
+        })
+    }
+
+    // username を指定するとそのユーザーのトークンだけを返す
+    fn show_tokens(&self, username: Option<&str>) -> Result<Vec<ApiToken>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let mut tokens: Vec<ApiToken> = self
+            .api_tokens(&txn)?
+            .into_iter()
+            .filter(|token| username.is_none_or(|u| token.user_name == u))
+            .collect();
+        tokens.sort_by_key(|token| token.created_at);
+        Ok(tokens)
+    }
 
-        // ハッシュ文字列に変換
-        let hash_str = std::str::from_utf8(hash_bytes)?;
+    fn revoke_token(&self, id: &str) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.del(self.token, &id.as_bytes(), None) {
+            Ok(()) => {
+                txn.commit()?;
+                Ok(Output::Success)
+            }
+            Err(LmdbError::NotFound) => Err(Error::TokenNotFound {
+                token_id: id.to_string(),
+            }),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
 
-        // PasswordHash に変換
-        let parsed_hash = PasswordHash::new(hash_str).map_err(|_| Error::LmdbError {
-            message: "Invalid stored password hash".to_string(),
-            location: "verify_user",
+    fn create_role(&self, rolename: &str, privileges: &Privileges) -> Result<Output, Error> {
+        if builtin_role(rolename).is_some() {
+            return Err(Error::BuiltinRole {
+                role_name: rolename.to_string(),
+            });
+        }
+        let bytes = serde_json::to_vec(privileges).map_err(|e| Error::ParseError {
+            message: e.to_string(),
+            location: "create_role",
         })?;
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.put(
+            self.role,
+            &rolename.as_bytes(),
+            &bytes,
+            WriteFlags::NO_OVERWRITE,
+        ) {
+            Ok(()) => {
+                txn.commit()?;
+                Ok(Output::Success)
+            }
+            Err(LmdbError::KeyExist) => Err(Error::RoleAlreadyExists {
+                role_name: rolename.to_string(),
+            }),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    // ロールを付与されているユーザーからも外す
+    fn drop_role(&self, rolename: &str) -> Result<Output, Error> {
+        if builtin_role(rolename).is_some() {
+            return Err(Error::BuiltinRole {
+                role_name: rolename.to_string(),
+            });
+        }
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.del(self.role, &rolename.as_bytes(), None) {
+            Ok(()) => {}
+            Err(LmdbError::NotFound) => {
+                return Err(Error::RoleNotFound {
+                    role_name: rolename.to_string(),
+                });
+            }
+            Err(e) => return Err(Error::from(e)),
+        }
+
+        let mut updated = Vec::new();
+        {
+            let mut cursor = txn.open_ro_cursor(self.user)?;
+            for (k, v) in scan_prefix(&mut cursor, &[]) {
+                let mut record = parse_user_record(v)?;
+                if let Some(roles) = &mut record.roles
+                    && roles.iter().any(|r| r == rolename)
+                {
+                    roles.retain(|r| r != rolename);
+                    updated.push((std::str::from_utf8(k)?.to_string(), record));
+                }
+            }
+        }
+        for (username, record) in updated {
+            self.put_user(&mut txn, &username, &record)?;
+        }
+        self.remove_from_acls(&mut txn, None, Some(rolename))?;
+        txn.commit()?;
+        Ok(Output::Success)
+    }
+
+    fn grant_role(&self, username: &str, rolename: &str) -> Result<Output, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        self.role_record(&txn, rolename)?;
+        let mut record = self.user_record(&txn, username)?;
+        let mut roles = record.roles(username);
+        if !roles.iter().any(|r| r == rolename) {
+            roles.push(rolename.to_string());
+            record.roles = Some(roles);
+            self.put_user(&mut txn, username, &record)?;
+            txn.commit()?;
+        }
+        Ok(Output::Success)
+    }
+
+    fn revoke_role(&self, username: &str, rolename: &str) -> Result<Output, Error> {
+        // admin ユーザーから admin ロールは外せない
+        if username == ADMIN_ROLE && rolename == ADMIN_ROLE {
+            return Err(Error::BuiltinRole {
+                role_name: rolename.to_string(),
+            });
+        }
+        let mut txn = self.env.begin_rw_txn()?;
+        let mut record = self.user_record(&txn, username)?;
+        let mut roles = record.roles(username);
+        if roles.iter().any(|r| r == rolename) {
+            roles.retain(|r| r != rolename);
+            record.roles = Some(roles);
+            self.put_user(&mut txn, username, &record)?;
+            txn.commit()?;
+        }
+        Ok(Output::Success)
+    }
+
+    fn show_roles(&self) -> Result<Output, Error> {
+        let mut roles: Vec<RoleInfo> = BUILTIN_ROLES
+            .iter()
+            .filter_map(|name| {
+                builtin_role(name).map(|privileges| RoleInfo {
+                    role_name: name.to_string(),
+                    builtin: true,
+                    privileges,
+                })
+            })
+            .collect();
+
+        let txn = self.env.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self.role)?;
+        for (k, v) in scan_prefix(&mut cursor, &[]) {
+            let privileges = serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                message: e.to_string(),
+                location: "show_roles",
+            })?;
+            roles.push(RoleInfo {
+                role_name: std::str::from_utf8(k)?.to_string(),
+                builtin: false,
+                privileges,
+            });
+        }
+        Ok(Output::ShowRoles(ShowRoles { roles }))
+    }
+
+    fn user_roles(&self, username: &str) -> Result<Vec<String>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        Ok(self.user_record(&txn, username)?.roles(username))
+    }
+
+    // 存在しないロール（JWT の roles クレーム等）は無視する
+    fn role_privileges(&self, roles: &[String]) -> Result<Vec<Privileges>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let mut privileges = Vec::new();
+        for role in roles {
+            match self.role_record(&txn, role) {
+                Ok(p) => privileges.push(p),
+                Err(Error::RoleNotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(privileges)
+    }
+
+    fn append_audit(&self, entry: &AuditEntry) -> Result<Vec<u8>, Error> {
+        let key = [
+            entry.timestamp.to_be_bytes().as_slice(),
+            Uuid::new_v4().as_bytes(),
+        ]
+        .concat();
+        self.put_audit(&key, entry, WriteFlags::NO_OVERWRITE)?;
+        Ok(key)
+    }
+
+    fn finish_audit(&self, key: &[u8], entry: &AuditEntry) -> Result<(), Error> {
+        self.put_audit(key, entry, WriteFlags::empty())
+    }
+
+    // from 以上 to 未満の時刻の記録を新しい順に limit 件まで返す
+    fn show_audit(
+        &self,
+        username: Option<&str>,
+        spacename: Option<&str>,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        let txn = self.env.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self.audit)?;
+        // キーの先頭8バイトが timestamp。to の手前から新しい順に辿り、from より前で止める
+        let end = to.map(|to| to.to_be_bytes().to_vec()).unwrap_or_default();
+        let start = from.unwrap_or(0).to_be_bytes();
+        let records = scan_before(&mut cursor, &end).take_while(|(k, _)| k[..8] >= start[..]);
+
+        let mut entries = Vec::new();
+        for (_, v) in records {
+            if entries.len() >= limit {
+                break;
+            }
+            let entry: AuditEntry = serde_json::from_slice(v).map_err(|e| Error::ParseError {
+                message: e.to_string(),
+                location: "show_audit",
+            })?;
+            if username.is_some_and(|u| entry.user_name != u)
+                || spacename.is_some_and(|sp| !entry.space_names.iter().any(|n| n == sp))
+            {
+                continue;
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+// user DB の値を読む。旧形式はハッシュ文字列だけが入っている
+fn parse_user_record(bytes: &[u8]) -> Result<UserRecord, Error> {
+    let text = std::str::from_utf8(bytes)?;
+    if text.starts_with('$') {
+        return Ok(UserRecord {
+            password_hash: text.to_string(),
+            must_change_password: false,
+            roles: None,
+        });
+    }
+    serde_json::from_str(text).map_err(|e| Error::ParseError {
+        message: e.to_string(),
+        location: "user_record",
+    })
+}
+
+fn hash_password(password: &str, location: &'static str) -> Result<String, Error> {
+    // ソルトを生成してハッシュ化
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| Error::LmdbError {
+            message: "Password hash failed".to_string(),
+            location,
+        })
+}
+
+fn verify_password(record: &UserRecord, password: &str) -> Result<bool, Error> {
+    let parsed_hash = PasswordHash::new(&record.password_hash).map_err(|_| Error::LmdbError {
+        message: "Invalid stored password hash".to_string(),
+        location: "verify_user",
+    })?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::tools::temp_storage::{TempDir, TempStorage},
+        json::input::Pyramid,
+    };
+
+    fn id(s: &str) -> Vec<u8> {
+        id_string_to_bitmask(s).unwrap()
+    }
+
+    // ピラミッドと履歴を持つ INT の Key を作る
+    fn create_key(s: &Storage, space: &str, key: &str) {
+        let option = KeyOption {
+            pyramid: Some(Pyramid {
+                zooms: vec![1],
+                aggregations: vec![Aggregation::Count],
+            }),
+            history: true,
+            constraint: None,
+        };
+        s.create_key(space, key, KeyType::INT, KeyMode::UniqueKey, option)
+            .unwrap();
+    }
+
+    fn key_uuid(s: &Storage, space: &str, key: &str) -> Vec<u8> {
+        let txn = s.env.begin_ro_txn().unwrap();
+        s.get_key(&txn, space, key, "test").unwrap().uuid
+    }
+
+    // key_uuid で始まる行の数（expiry_index は期限の後ろに key_uuid が来る）
+    fn rows(s: &Storage, db: Database, key_uuid: &[u8]) -> usize {
+        let txn = s.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(db).unwrap();
+        scan_prefix(&mut cursor, &[])
+            .filter(|(k, _)| {
+                let k = if db == s.expiry_index { &k[8..] } else { k };
+                k.starts_with(key_uuid)
+            })
+            .count()
+    }
+
+    fn key_rows(s: &Storage, key_uuid: &[u8]) -> Vec<usize> {
+        [
+            s.key_option,
+            s.value,
+            s.pyramid,
+            s.history,
+            s.expiry,
+            s.expiry_index,
+        ]
+        .into_iter()
+        .map(|db| rows(s, db, key_uuid))
+        .collect()
+    }
+
+    fn insert(s: &Storage, space: &str, key: &str, ids: &[&str], value: i32) {
+        let ids = ids.iter().map(|i| id(i)).collect();
+        let expires_at = Some(now_millis() + 3_600_000);
+        s.insert_value(space, key, ids, ValueEntry::INT(value), expires_at, "alice")
+            .unwrap();
+    }
+
+    #[test]
+    fn drop_key_removes_only_that_key_and_its_rows() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        create_key(&s, "s", "a");
+        create_key(&s, "s", "ab");
+        insert(&s, "s", "a", &["3/5/2/7_0/-"], 1);
+        insert(&s, "s", "ab", &["3/5/2/7_0/-"], 2);
+        let a = key_uuid(&s, "s", "a");
+        let ab = key_uuid(&s, "s", "ab");
+        assert_eq!(key_rows(&s, &a), [1, 1, 1, 1, 1, 1]);
+
+        s.drop_key("s", "a").unwrap();
+        assert_eq!(key_rows(&s, &a), [0, 0, 0, 0, 0, 0]);
+        // 名前が前方一致するだけの Key は残る
+        assert_eq!(key_rows(&s, &ab), [1, 1, 1, 1, 1, 1]);
+        assert_eq!(key_uuid(&s, "s", "ab"), ab);
+        assert!(matches!(
+            s.drop_key("s", "a"),
+            Err(Error::KeyNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn drop_space_removes_its_keys_and_rows() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        s.create_space("t", "alice").unwrap();
+        create_key(&s, "s", "a");
+        create_key(&s, "t", "a");
+        insert(&s, "s", "a", &["3/5/2/7_0/-", "3/5/2/6_0/-"], 1);
+        insert(&s, "t", "a", &["3/5/2/7_0/-"], 2);
+        let s_a = key_uuid(&s, "s", "a");
+        let t_a = key_uuid(&s, "t", "a");
+
+        s.drop_space("s").unwrap();
+        assert_eq!(key_rows(&s, &s_a), [0, 0, 0, 0, 0, 0]);
+        assert_eq!(rows(&s, s.key, &[]), 1);
+        assert_eq!(key_rows(&s, &t_a), [1, 1, 1, 1, 1, 1]);
+
+        // 同じ名前で作り直した Space には古い Key が見えない
+        s.create_space("s", "alice").unwrap();
+        create_key(&s, "s", "a");
+        let values = s.show_values("s", "a").unwrap();
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn drop_key_removes_the_constraint() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        let option: KeyOption =
+            serde_json::from_str(r#"{"constraint":{"min":0,"max":10}}"#).unwrap();
+        s.create_key("s", "a", KeyType::INT, KeyMode::UniqueKey, option)
+            .unwrap();
+        let a = key_uuid(&s, "s", "a");
+        assert_eq!(rows(&s, s.key_option, &a), 1);
+
+        s.drop_key("s", "a").unwrap();
+        assert_eq!(rows(&s, s.key_option, &a), 0);
+    }
+
+    // 書き込みごとに時刻がずれるよう少し待ち、書き込み直後の時刻を返す
+    fn tick() -> u64 {
+        std::thread::sleep(Duration::from_millis(3));
+        let t = now_millis();
+        std::thread::sleep(Duration::from_millis(3));
+        t
+    }
+
+    fn value_as_of(s: &Storage, space: &str, key: &str, cell: &str, as_of: u64) -> Option<i32> {
+        let values = s
+            .select_value(space, vec![key.to_string()], vec![id(cell)], Some(as_of))
+            .unwrap();
+        values.get(&id(cell)).map(|v| match v[0].1 {
+            ValueEntry::INT(i) => i,
+            _ => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn as_of_follows_writes_and_deletes() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        create_key(&s, "s", "a");
+        let cell = "3/5/2/7_0/-";
+
+        let before = tick();
+        s.insert_value("s", "a", vec![id(cell)], ValueEntry::INT(1), None, "alice")
+            .unwrap();
+        let inserted = tick();
+        s.update_value("s", "a", vec![id(cell)], Some(ValueEntry::INT(2)), "bob")
+            .unwrap();
+        let updated = tick();
+        s.delete_value("s", "a", vec![id(cell)], "carol").unwrap();
+        let deleted = tick();
+
+        assert_eq!(value_as_of(&s, "s", "a", cell, before), None);
+        assert_eq!(value_as_of(&s, "s", "a", cell, inserted), Some(1));
+        assert_eq!(value_as_of(&s, "s", "a", cell, updated), Some(2));
+        assert_eq!(value_as_of(&s, "s", "a", cell, deleted), None);
+
+        let history = s.value_history("s", "a", vec![id(cell)]).unwrap();
+        let users: Vec<&str> = history.iter().map(|(_, e)| e.user.as_str()).collect();
+        assert_eq!(users, ["alice", "bob", "carol"]);
+    }
+
+    #[test]
+    fn expiry_is_recorded_at_the_expiry_time() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        create_key(&s, "s", "a");
+        let cell = "3/5/2/7_0/-";
+
+        let expires_at = now_millis() + 20;
+        s.insert_value(
+            "s",
+            "a",
+            vec![id(cell)],
+            ValueEntry::INT(1),
+            Some(expires_at),
+            "alice",
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+
+        // 掃除の前でも期限以降は無い
+        assert_eq!(value_as_of(&s, "s", "a", cell, expires_at - 1), Some(1));
+        assert_eq!(value_as_of(&s, "s", "a", cell, expires_at), None);
+
+        let swept_at = now_millis();
+        assert_eq!(s.purge_expired(swept_at).unwrap(), 1);
+        let history = s.value_history("s", "a", vec![id(cell)]).unwrap();
+        let (_, last) = history.last().unwrap();
+        assert_eq!((last.timestamp, last.user.as_str()), (expires_at, "expiry"));
+        assert!(last.timestamp < swept_at);
+        assert_eq!(value_as_of(&s, "s", "a", cell, expires_at - 1), Some(1));
+        assert_eq!(value_as_of(&s, "s", "a", cell, expires_at), None);
+
+        // 同じセルへの再挿入で、期限切れの値と新しい値が順に並ぶ
+        s.insert_value("s", "a", vec![id(cell)], ValueEntry::INT(2), None, "bob")
+            .unwrap();
+        let now = tick();
+        assert_eq!(value_as_of(&s, "s", "a", cell, now), Some(2));
+        assert_eq!(value_as_of(&s, "s", "a", cell, expires_at), None);
+    }
+
+    #[test]
+    fn removed_sessions_leave_memory_and_lmdb() {
+        let s = TempStorage::default();
+        let a1 = s.create_session("alice").unwrap();
+        let a2 = s.create_session("alice").unwrap();
+        let b = s.create_session("bob").unwrap();
+
+        assert_eq!(s.drop_session(&a1).unwrap().as_deref(), Some("alice"));
+        assert_eq!(s.session_user(&a1).unwrap(), None);
+        assert_eq!(s.drop_session(&a1).unwrap(), None);
+
+        assert_eq!(s.kill_user_sessions("alice").unwrap(), 1);
+        assert_eq!(s.session_user(&a2).unwrap(), None);
+
+        s.kill_session(&token_hash(&b)).unwrap();
+        assert!(matches!(
+            s.kill_session(&token_hash(&b)),
+            Err(Error::SessionNotFound { .. })
+        ));
+        assert!(s.show_sessions().unwrap().is_empty());
+        assert_eq!(rows(&s, s.session, &[]), 0);
+    }
+
+    // pyramid DB が無い頃の形式（ビット列はフォーマット0）で書かれた環境を作る
+    fn legacy_env(dir: &TempDir, values: &[(&[u8], i32)]) {
+        let env = Environment::new().set_max_dbs(32).open(dir.path()).unwrap();
+        let space = env
+            .create_db(Some("space"), DatabaseFlags::empty())
+            .unwrap();
+        let key = env.create_db(Some("key"), DatabaseFlags::empty()).unwrap();
+        let value = env
+            .create_db(Some("value"), DatabaseFlags::empty())
+            .unwrap();
+        env.create_db(Some("user"), DatabaseFlags::empty()).unwrap();
+
+        let space_uuid = *Uuid::new_v4().as_bytes();
+        let key_uuid = *Uuid::new_v4().as_bytes();
+        let key_bytes = [
+            &space_uuid[..],
+            b"a",
+            &[keytype_id(KeyType::INT)],
+            &[KeyMode::UniqueKey as u8],
+        ]
+        .concat();
+        let mut txn = env.begin_rw_txn().unwrap();
+        txn.put(space, b"s", &space_uuid, WriteFlags::empty())
+            .unwrap();
+        txn.put(key, &key_bytes, &key_uuid, WriteFlags::empty())
+            .unwrap();
+        for (bits, v) in values {
+            let k = [&key_uuid[..], bits].concat();
+            txn.put(
+                value,
+                &k,
+                &ValueEntry::INT(*v).to_bytes(),
+                WriteFlags::empty(),
+            )
+            .unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    fn value(s: &Storage, cell: &str) -> Option<i32> {
+        let values = s
+            .select_value("s", vec!["a".to_string()], vec![id(cell)], None)
+            .unwrap();
+        values.get(&id(cell)).map(|v| match v[0].1 {
+            ValueEntry::INT(i) => i,
+            _ => unreachable!(),
+        })
+    }
+
+    fn tile(s: &Storage, z: u8, x: u32, y: u32) -> Vec<String> {
+        let mut cells: Vec<String> = s
+            .select_tile("s", "a", z, x, y)
+            .unwrap()
+            .iter()
+            .map(|(bits, _)| bitmask_to_id_string(bits).unwrap())
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn legacy_values_are_migrated_on_open() {
+        let dir = TempDir::default();
+        // 旧形式は符号ビットの後に x, y, |f| を下位ビットから並べる
+        legacy_env(
+            &dir,
+            &[
+                (&[0, 0, 1, 1, 1, 0, 1, 1, 0, 0], 1),
+                (&[1, 0, 1, 0, 1, 0, 1, 1, 0, 0], 2),
+                (&[1, 0, 0, 0, 0, 0, 1, 0, 0, 0], 3),
+            ],
+        );
+        let s = TempStorage::open(dir, Config::default());
+
+        assert_eq!(value(&s, "3/-3/6/1_0/-"), Some(1));
+        assert_eq!(value(&s, "3/2/6/1_0/-"), Some(2));
+        assert_eq!(value(&s, "3/2/0/0_0/-"), Some(3));
+
+        // タイルの絞り込みは新しいビット列の並びに頼るので、移行後に正しく引ける
+        assert_eq!(tile(&s, 3, 6, 1), ["3/-3/6/1_0/-", "3/2/6/1_0/-"]);
+        assert_eq!(tile(&s, 1, 1, 0), ["3/-3/6/1_0/-", "3/2/6/1_0/-"]);
+        assert_eq!(tile(&s, 3, 0, 0), ["3/2/0/0_0/-"]);
+
+        let txn = s.env.begin_ro_txn().unwrap();
+        assert_eq!(
+            txn.get(s.meta, &FORMAT_VERSION_KEY).unwrap(),
+            FORMAT_VERSION.to_be_bytes()
+        );
+    }
+
+    fn pyramid_count(s: &Storage, cell: &str) -> u64 {
+        s.select_pyramid("s", "a", 1, vec![id(cell)])
+            .unwrap()
+            .first()
+            .map_or(0, |(_, c)| c.count)
+    }
+
+    #[test]
+    fn purge_removes_expired_values_and_their_rows() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        create_key(&s, "s", "a");
+        let a = key_uuid(&s, "s", "a");
+        let put = |cell: &str, expires_at: Option<u64>| {
+            s.insert_value(
+                "s",
+                "a",
+                vec![id(cell)],
+                ValueEntry::INT(1),
+                expires_at,
+                "alice",
+            )
+            .unwrap();
+        };
+        put("3/5/2/7_0/-", Some(now_millis() + 1));
+        put("3/5/2/6_0/-", None);
+        put("3/5/2/5_0/-", Some(now_millis() + 3_600_000));
+        let now = tick();
+
+        // 掃除の前でも期限切れの値は見えない
+        assert_eq!(value(&s, "3/5/2/7_0/-"), None);
+        assert_eq!(rows(&s, s.value, &a), 3);
+
+        assert_eq!(s.purge_expired(now).unwrap(), 1);
+        assert_eq!(rows(&s, s.value, &a), 2);
+        assert_eq!(rows(&s, s.expiry, &a), 1);
+        assert_eq!(rows(&s, s.expiry_index, &a), 1);
+        assert_eq!(pyramid_count(&s, "1/1/0/1_0/-"), 2);
+        assert_eq!(value(&s, "3/5/2/6_0/-"), Some(1));
+        assert_eq!(value(&s, "3/5/2/5_0/-"), Some(1));
+        assert_eq!(s.purge_expired(now).unwrap(), 0);
+    }
 
-        // Argon2 インスタンス
-        let argon2 = Argon2::default();
+    #[test]
+    fn purge_continues_past_one_batch() {
+        let s = TempStorage::default();
+        s.create_space("s", "alice").unwrap();
+        create_key(&s, "s", "a");
+        let a = key_uuid(&s, "s", "a");
+        let n = PURGE_BATCH + 5;
+        let ids = (0..n).map(|x| id(&format!("11/0/{}/0_0/-", x))).collect();
+        s.insert_value(
+            "s",
+            "a",
+            ids,
+            ValueEntry::INT(1),
+            Some(now_millis() + 1),
+            "alice",
+        )
+        .unwrap();
+        let now = tick();
+
+        assert_eq!(s.purge_expired(now).unwrap(), n);
+        // 履歴には書き込みと期限切れが1件ずつ残る
+        assert_eq!(key_rows(&s, &a)[1..], [0, 0, 2 * n, 0, 0]);
+    }
 
-        // パスワードを検証
-        let valid = argon2
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_ok();
+    fn session_rows(s: &Storage) -> Vec<Session> {
+        let txn = s.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(s.session).unwrap();
+        scan_prefix(&mut cursor, &[])
+            .map(|(_, v)| serde_json::from_slice(v).unwrap())
+            .collect()
+    }
 
-        Ok(valid)
+    #[test]
+    fn sweep_writes_back_touched_sessions_and_drops_expired_ones() {
+        let s = TempStorage::default();
+        let a = s.create_session("alice").unwrap();
+        let b = s.create_session("bob").unwrap();
+        tick();
+
+        // 延長はメモリだけで行い、掃除のときに書き込む（ログアウトしたセッションは書き戻さない）
+        s.session_user(&a).unwrap();
+        s.session_user(&b).unwrap();
+        s.drop_session(&b).unwrap();
+        let created = session_rows(&s)[0].expires_at;
+        assert_eq!(s.sweep_sessions(now_millis()).unwrap(), 0);
+        let rows = session_rows(&s);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].user_name, "alice");
+        assert!(rows[0].expires_at > created);
+
+        // 再起動してもセッションは有効
+        let s = s.reopen(Config::default());
+        assert_eq!(s.session_user(&a).unwrap().as_deref(), Some("alice"));
+        assert_eq!(s.session_user(&b).unwrap(), None);
+
+        let later = now_millis() + Config::default().server.session_timeout_secs * 2000;
+        assert_eq!(s.sweep_sessions(later).unwrap(), 1);
+        assert!(session_rows(&s).is_empty());
+        let s = s.reopen(Config::default());
+        assert!(s.show_sessions().unwrap().is_empty());
     }
 }
@@ -1,13 +1,18 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use crate::{
     error::Error,
+    io::tools::role::{ADMIN_ROLE, LEGACY_ROLE},
     json::{
-        input::{KeyMode, KeyType},
+        input::{
+            KeyConstraint, KeyMode, KeyType, OnConflict, Privileges, Pyramid, ShareLevel,
+            SpaceShare, TokenScope,
+        },
+        ndjson::SpaceRecord,
         output::Output,
     },
 };
-use kasane_logic::id::SpaceTimeId;
+use base64::{Engine, engine::general_purpose::STANDARD};
 use serde::{Deserialize, Serialize};
 pub mod full;
 pub mod tools;
@@ -18,6 +23,13 @@ pub enum ValueEntry {
     BOOLEAN(bool),
     INT(i32),
     FLOAT(f32),
+    INT64(i64),
+    DOUBLE(f64),
+    //UNIXエポックからのミリ秒
+    TIMESTAMP(i64),
+    JSON(serde_json::Value),
+    //JSON 上では base64 文字列
+    BLOB(#[serde(with = "tools::base64_bytes")] Vec<u8>),
 }
 
 impl ValueEntry {
@@ -27,6 +39,11 @@ impl ValueEntry {
             ValueEntry::BOOLEAN(b) => vec![*b as u8], // true=1, false=0
             ValueEntry::INT(i) => i.to_le_bytes().to_vec(), // i32 → 4バイト
             ValueEntry::FLOAT(f) => f.to_le_bytes().to_vec(), // f32 → 4バイト
+            ValueEntry::INT64(i) => i.to_le_bytes().to_vec(), // i64 → 8バイト
+            ValueEntry::DOUBLE(f) => f.to_le_bytes().to_vec(), // f64 → 8バイト
+            ValueEntry::TIMESTAMP(t) => t.to_le_bytes().to_vec(), // i64 → 8バイト
+            ValueEntry::JSON(v) => v.to_string().into_bytes(),
+            ValueEntry::BLOB(b) => b.clone(),
         }
     }
 
@@ -34,33 +51,265 @@ impl ValueEntry {
     pub fn from_bytes(keytype: KeyType, data: &[u8]) -> Option<Self> {
         match keytype {
             KeyType::TEXT => Some(ValueEntry::TEXT(String::from_utf8_lossy(data).to_string())),
-            KeyType::BOOLEAN => Some(ValueEntry::BOOLEAN(data.get(0)? != &0)),
-            KeyType::INT => {
-                if data.len() != 4 {
-                    return None;
-                }
-                let mut arr = [0u8; 4];
-                arr.copy_from_slice(data);
-                Some(ValueEntry::INT(i32::from_le_bytes(arr)))
-            }
-            KeyType::FLOAT => {
-                if data.len() != 4 {
-                    return None;
-                }
-                let mut arr = [0u8; 4];
-                arr.copy_from_slice(data);
-                Some(ValueEntry::FLOAT(f32::from_le_bytes(arr)))
-            }
+            KeyType::BOOLEAN => Some(ValueEntry::BOOLEAN(data.first()? != &0)),
+            KeyType::INT => Some(ValueEntry::INT(i32::from_le_bytes(data.try_into().ok()?))),
+            KeyType::FLOAT => Some(ValueEntry::FLOAT(f32::from_le_bytes(data.try_into().ok()?))),
+            KeyType::INT64 => Some(ValueEntry::INT64(i64::from_le_bytes(data.try_into().ok()?))),
+            KeyType::DOUBLE => Some(ValueEntry::DOUBLE(f64::from_le_bytes(
+                data.try_into().ok()?,
+            ))),
+            KeyType::TIMESTAMP => Some(ValueEntry::TIMESTAMP(i64::from_le_bytes(
+                data.try_into().ok()?,
+            ))),
+            KeyType::JSON => Some(ValueEntry::JSON(serde_json::from_slice(data).ok()?)),
+            KeyType::BLOB => Some(ValueEntry::BLOB(data.to_vec())),
         }
     }
+
+    // 値に対応する KeyType（型チェック用）
+    pub fn keytype(&self) -> KeyType {
+        match self {
+            ValueEntry::TEXT(_) => KeyType::TEXT,
+            ValueEntry::BOOLEAN(_) => KeyType::BOOLEAN,
+            ValueEntry::INT(_) => KeyType::INT,
+            ValueEntry::FLOAT(_) => KeyType::FLOAT,
+            ValueEntry::INT64(_) => KeyType::INT64,
+            ValueEntry::DOUBLE(_) => KeyType::DOUBLE,
+            ValueEntry::TIMESTAMP(_) => KeyType::TIMESTAMP,
+            ValueEntry::JSON(_) => KeyType::JSON,
+            ValueEntry::BLOB(_) => KeyType::BLOB,
+        }
+    }
+
+    // 型タグを外した素の JSON 値（GeoJSON の properties 用）
+    pub fn to_plain_json(&self) -> serde_json::Value {
+        match self {
+            ValueEntry::TEXT(s) => s.clone().into(),
+            ValueEntry::BOOLEAN(b) => (*b).into(),
+            ValueEntry::INT(i) => (*i).into(),
+            ValueEntry::FLOAT(f) => (*f as f64).into(),
+            ValueEntry::INT64(i) => (*i).into(),
+            ValueEntry::DOUBLE(f) => (*f).into(),
+            ValueEntry::TIMESTAMP(t) => (*t).into(),
+            ValueEntry::JSON(v) => v.clone(),
+            ValueEntry::BLOB(b) => STANDARD.encode(b).into(),
+        }
+    }
+
+    // 集約用に数値として取り出す（数値型以外は None）
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ValueEntry::INT(i) => Some(*i as f64),
+            ValueEntry::FLOAT(f) => Some(*f as f64),
+            ValueEntry::INT64(i) => Some(*i as f64),
+            ValueEntry::DOUBLE(f) => Some(*f),
+            _ => None,
+        }
+    }
+}
+
+// Key作成時に指定される追加設定（key_option DB に JSON で保存）
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct KeyOption {
+    #[serde(default)]
+    pub pyramid: Option<Pyramid>,
+    #[serde(default)]
+    pub history: bool,
+    #[serde(default)]
+    pub constraint: Option<KeyConstraint>,
+}
+
+// user DB の値（JSON）。旧形式（PHC 文字列のみ）の値も読める
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub password_hash: String,
+    //次回ログイン時にパスワード変更が必要
+    #[serde(default)]
+    pub must_change_password: bool,
+    //None はロール導入前に作られたユーザーで、legacy ロールを持つ
+    #[serde(default)]
+    pub roles: Option<Vec<String>>,
+}
+
+impl UserRecord {
+    // 付与されているロール。admin ユーザーは常に admin ロールを持つ
+    pub fn roles(&self, username: &str) -> Vec<String> {
+        let mut roles = self
+            .roles
+            .clone()
+            .unwrap_or_else(|| vec![LEGACY_ROLE.to_string()]);
+        if username == ADMIN_ROLE && !roles.iter().any(|r| r == ADMIN_ROLE) {
+            roles.insert(0, ADMIN_ROLE.to_string());
+        }
+        roles
+    }
+}
+
+// space_acl DB の値（JSON）。キーは Space の UUID なので RenameSpace 後も引き継ぐ
+// owner が None なのは所有者を記録する前に作られた Space
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SpaceAcl {
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub shares: Vec<SpaceShare>,
+}
+
+impl SpaceAcl {
+    // user（とそのロール）に与えられた最も強い共有レベル。所有者は Write
+    pub fn level(&self, user: &str, roles: &[String]) -> Option<ShareLevel> {
+        if self.owner.as_deref() == Some(user) {
+            return Some(ShareLevel::Write);
+        }
+        self.shares
+            .iter()
+            .filter(|share| {
+                share.user_name.as_deref() == Some(user)
+                    || share
+                        .role_name
+                        .as_ref()
+                        .is_some_and(|role| roles.contains(role))
+            })
+            .map(|share| share.level)
+            .max()
+    }
 }
+
+// session DB の値（JSON）。キーはトークンの SHA-256（16進）で、ShowSessions の ID にもなる
+// 時刻は UNIX ミリ秒。expires_at は最終アクセスからセッションのタイムアウト後
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(skip)]
+    pub id: String,
+    pub user_name: String,
+    pub created_at: u64,
+    pub last_access: u64,
+    pub expires_at: u64,
+}
+
+// token DB の値（JSON）。キーはトークンの SHA-256（16進）で、ShowTokens の ID にもなる
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiToken {
+    #[serde(skip)]
+    pub id: String,
+    pub token_name: String,
+    pub user_name: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub scope: TokenScope,
+}
+
+// audit DB の値（JSON）。キーは [timestamp BE][UUID] で時刻順に並ぶ
+// command はパスワードを伏せた Command の JSON、result は Ok か Err(Error の JSON)
+// result が None なのは実行前に書いたまま、結果を書けなかったエントリ
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub user_name: String,
+    pub request_id: String,
+    pub space_names: Vec<String>,
+    pub command: serde_json::Value,
+    pub result: Option<Result<(), serde_json::Value>>,
+}
+
+// 履歴モードの Key で書き込みごとに history DB へ追記されるエントリ
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub user: String,
+    pub old: Option<ValueEntry>,
+    pub new: Option<ValueEntry>,
+}
+
+// ピラミッドの1セル分の集約値 (count: u64 + sum: f64 = 16バイト)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PyramidCell {
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl PyramidCell {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.count.to_le_bytes(), self.sum.to_le_bytes()].concat()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != 16 {
+            return None;
+        }
+        let mut count = [0u8; 8];
+        let mut sum = [0u8; 8];
+        count.copy_from_slice(&data[..8]);
+        sum.copy_from_slice(&data[8..]);
+        Some(PyramidCell {
+            count: u64::from_le_bytes(count),
+            sum: f64::from_le_bytes(sum),
+        })
+    }
+}
+// AlterKeyType の結果。failed は変換できなかった値の空間IDビット列
+pub struct AlterReport {
+    pub applied: bool,
+    pub converted: usize,
+    pub failed: Vec<Vec<u8>>,
+    //新しい型に引き継げず外した制約
+    pub dropped_constraint: Option<KeyConstraint>,
+}
+
+// ファイル取り込みの1行分。row はエラー報告用の行番号
+pub struct ImportRow {
+    pub row: usize,
+    pub id: Vec<u8>,
+    pub value: Option<ValueEntry>,
+}
+
+// 取り込み結果。errors は行単位のエラー
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+    pub errors: Vec<(usize, Error)>,
+}
+
+// 空間IDのビット列 -> (キー名, 値) の一覧
+pub type ValueMap = HashMap<Vec<u8>, Vec<(String, ValueEntry)>>;
+// scan_values のコールバック (空間IDビット列, Key ごとの値)
+pub type ValueVisitor<'a> = dyn FnMut(&[u8], &[Option<ValueEntry>]) -> Result<(), Error> + 'a;
+
 // StorageTrait は共通
 pub trait StorageTrait {
     //データベース操作系
-    fn create_space(&self, spacename: &str) -> Result<Output, Error>;
+    fn create_space(&self, spacename: &str, owner: &str) -> Result<Output, Error>;
     fn drop_space(&self, spacename: &str) -> Result<Output, Error>;
+    fn rename_space(&self, spacename: &str, new_spacename: &str) -> Result<Output, Error>;
+    fn clone_space(
+        &self,
+        spacename: &str,
+        new_spacename: &str,
+        owner: &str,
+    ) -> Result<Output, Error>;
+    //Key の行、その Key の値の行の順に visit に渡す
+    fn export_space(
+        &self,
+        spacename: &str,
+        visit: &mut dyn FnMut(SpaceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+    fn import_space(
+        &self,
+        spacename: &str,
+        records: Vec<SpaceRecord>,
+        on_conflict: OnConflict,
+        user: &str,
+    ) -> Result<Output, Error>;
     fn info_space(&self, spacename: &str) -> Result<Output, Error>;
-    fn show_spaces(&self) -> Result<Output, Error>;
+    fn show_spaces(&self) -> Result<Vec<(String, SpaceAcl)>, Error>;
+    fn space_acl(&self, spacename: &str) -> Result<SpaceAcl, Error>;
+    fn share_space(&self, spacename: &str, share: SpaceShare) -> Result<Output, Error>;
+    fn unshare_space(
+        &self,
+        spacename: &str,
+        username: Option<&str>,
+        rolename: Option<&str>,
+    ) -> Result<Output, Error>;
 
     //key操作系
     fn create_key(
@@ -69,10 +318,36 @@ pub trait StorageTrait {
         keyname: &str,
         keytype: KeyType,
         keymode: KeyMode,
+        option: KeyOption,
     ) -> Result<Output, Error>;
     fn drop_key(&self, spacename: &str, keyname: &str) -> Result<Output, Error>;
+    fn rename_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        new_keyname: &str,
+    ) -> Result<Output, Error>;
+    fn alter_key_type(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        keytype: KeyType,
+        drop_failed: bool,
+        user: &str,
+    ) -> Result<AlterReport, Error>;
+    //ids が None なら全ての値をコピーする
+    fn copy_key(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        to_spacename: &str,
+        to_keyname: &str,
+        ids: Option<Vec<Vec<u8>>>,
+        user: &str,
+    ) -> Result<Output, Error>;
     fn show_keys(&self, spacename: &str) -> Result<Output, Error>;
     fn info_key(&self, spacename: &str, keyname: &str) -> Result<Output, Error>;
+    fn key_type(&self, spacename: &str, keyname: &str) -> Result<KeyType, Error>;
 
     //Value操作系
 
@@ -82,6 +357,8 @@ pub trait StorageTrait {
         keyname: &str,
         ids: Vec<Vec<u8>>,
         value: ValueEntry,
+        expires_at: Option<u64>,
+        user: &str,
     ) -> Result<Output, Error>;
     fn patch_value(
         &self,
@@ -89,36 +366,148 @@ pub trait StorageTrait {
         keyname: &str,
         ids: Vec<Vec<u8>>,
         value: ValueEntry,
+        expires_at: Option<u64>,
+        user: &str,
+    ) -> Result<Output, Error>;
+    fn update_value(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        ids: Vec<Vec<u8>>,
+        value: Option<ValueEntry>,
+        user: &str,
     ) -> Result<Output, Error>;
-    // fn update_value(
-    //     &self,
-    //     spacename: &str,
-    //     keyname: &str,
-    //     ids: Vec<Vec<u8>>,
-    //     value: ValueEntry,
-    // ) -> Result<Output, Error>;
     fn delete_value(
         &self,
         spacename: &str,
         keyname: &str,
         ids: Vec<Vec<u8>>,
+        user: &str,
     ) -> Result<Output, Error>;
     fn select_value(
         &self,
         spacename: &str,
         keyname: Vec<String>,
         id: Vec<Vec<u8>>,
-    ) -> Result<HashMap<Vec<u8>, Vec<(String, ValueEntry)>>, Error>;
-    fn show_values(
+        as_of: Option<u64>,
+    ) -> Result<ValueMap, Error>;
+    fn show_values(&self, spacename: &str, keyname: &str) -> Result<ValueMap, Error>;
+    //ValueMap を作らずに、セルをビット列順に1つずつ visit へ渡す
+    //値は keynames の順に並び、値の無い Key は None
+    fn scan_values(
+        &self,
+        spacename: &str,
+        keynames: &[String],
+        ids: Option<Vec<Vec<u8>>>,
+        visit: &mut ValueVisitor,
+    ) -> Result<(), Error>;
+    //行ごとに検証して batch_size 行ずつコミットする。行のエラーでは中断しない
+    fn import_rows(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        rows: Vec<ImportRow>,
+        on_conflict: OnConflict,
+        batch_size: usize,
+        user: &str,
+    ) -> Result<ImportReport, Error>;
+    //XYZ タイルと重なるセルの値（タイルより粗いセルも含む）
+    fn select_tile(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> Result<Vec<(Vec<u8>, ValueEntry)>, Error>;
+    fn select_pyramid(
+        &self,
+        spacename: &str,
+        keyname: &str,
+        zoom: u8,
+        ids: Vec<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, PyramidCell)>, Error>;
+    //期限切れの値を削除し、削除件数を返す
+    fn purge_expired(&self, now: u64) -> Result<usize, Error>;
+    fn value_history(
         &self,
         spacename: &str,
         keyname: &str,
-    ) -> Result<HashMap<Vec<u8>, Vec<(String, ValueEntry)>>, Error>;
+        ids: Vec<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, HistoryEntry)>, Error>;
 
     //ユーザー操作系
-    fn create_user(&self, username: &str, password: &str) -> Result<Output, Error>;
+    fn create_user(
+        &self,
+        username: &str,
+        password: &str,
+        roles: &[String],
+    ) -> Result<Output, Error>;
     fn drop_user(&self, username: &str) -> Result<Output, Error>;
     fn info_user(&self, username: &str) -> Result<Output, Error>;
     fn show_users(&self) -> Result<Output, Error>;
     fn verify_user(&self, username: &str, password: &str) -> Result<bool, Error>;
+    //keep_session のセッション以外の、ユーザーのセッションと API トークンを失効させる
+    fn change_password(
+        &self,
+        username: &str,
+        old_password: &str,
+        new_password: &str,
+        keep_session: Option<&str>,
+    ) -> Result<Output, Error>;
+    //ユーザーのセッションと API トークンをすべて失効させる
+    fn reset_password(
+        &self,
+        username: &str,
+        new_password: &str,
+        must_change_password: bool,
+    ) -> Result<Output, Error>;
+    fn must_change_password(&self, username: &str) -> Result<bool, Error>;
+    //ログイン試行の制限
+    //待ちが必要なら残り秒数を返す。無ければ試行を失敗として数える（成功したら record_login_success で戻す）
+    fn begin_login(&self, username: &str, ip: &str) -> Result<Option<u64>, Error>;
+    fn record_login_success(&self, username: &str, ip: &str) -> Result<(), Error>;
+    fn unlock_user(&self, username: &str) -> Result<bool, Error>;
+
+    //セッション系
+    fn create_session(&self, username: &str) -> Result<String, Error>;
+    fn session_user(&self, token: &str) -> Result<Option<String>, Error>;
+    fn drop_session(&self, token: &str) -> Result<Option<String>, Error>;
+    fn show_sessions(&self) -> Result<Vec<Session>, Error>;
+    fn kill_session(&self, id: &str) -> Result<Output, Error>;
+    fn kill_user_sessions(&self, username: &str) -> Result<usize, Error>;
+    fn sweep_sessions(&self, now: u64) -> Result<usize, Error>;
+    fn create_token(
+        &self,
+        username: &str,
+        token_name: &str,
+        expires_at: Option<u64>,
+        scope: TokenScope,
+    ) -> Result<(String, ApiToken), Error>;
+    fn token_user(&self, token: &str) -> Result<Option<ApiToken>, Error>;
+    fn get_token(&self, id: &str) -> Result<ApiToken, Error>;
+    fn show_tokens(&self, username: Option<&str>) -> Result<Vec<ApiToken>, Error>;
+    fn revoke_token(&self, id: &str) -> Result<Output, Error>;
+
+    //ロール操作系
+    fn create_role(&self, rolename: &str, privileges: &Privileges) -> Result<Output, Error>;
+    fn drop_role(&self, rolename: &str) -> Result<Output, Error>;
+    fn grant_role(&self, username: &str, rolename: &str) -> Result<Output, Error>;
+    fn revoke_role(&self, username: &str, rolename: &str) -> Result<Output, Error>;
+    fn show_roles(&self) -> Result<Output, Error>;
+    fn user_roles(&self, username: &str) -> Result<Vec<String>, Error>;
+    fn role_privileges(&self, roles: &[String]) -> Result<Vec<Privileges>, Error>;
+
+    //監査ログ
+    //実行前に追記してキーを返し、実行後に同じキーへ結果を書く
+    fn append_audit(&self, entry: &AuditEntry) -> Result<Vec<u8>, Error>;
+    fn finish_audit(&self, key: &[u8], entry: &AuditEntry) -> Result<(), Error>;
+    fn show_audit(
+        &self,
+        username: Option<&str>,
+        spacename: Option<&str>,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, Error>;
 }
@@ -0,0 +1,262 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use regex::Regex;
+use serde_json::Number;
+
+use crate::{
+    error::Error,
+    io::{ValueEntry, tools::convert::convert_value},
+    json::input::{KeyConstraint, KeyType},
+};
+
+// 検証のたびにコンパイルしないよう、パターンごとに Regex を持っておく
+static REGEX_CACHE: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+// キャッシュの上限。超えたら作り直す
+const REGEX_CACHE_LIMIT: usize = 256;
+
+fn cached_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut cache = REGEX_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(re) = cache.get(pattern) {
+        return Ok(re.clone());
+    }
+    let re = Regex::new(pattern)?;
+    if cache.len() >= REGEX_CACHE_LIMIT {
+        cache.clear();
+    }
+    cache.insert(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
+// min / max と比べる数値。整数（TIMESTAMP はミリ秒）は f64 に丸めずに比べる
+enum Comparable {
+    Int(i64),
+    Float(f64),
+}
+
+fn comparable(value: &ValueEntry) -> Option<Comparable> {
+    match value {
+        ValueEntry::INT(i) => Some(Comparable::Int(*i as i64)),
+        ValueEntry::INT64(i) => Some(Comparable::Int(*i)),
+        ValueEntry::TIMESTAMP(t) => Some(Comparable::Int(*t)),
+        _ => value.as_f64().map(Comparable::Float),
+    }
+}
+
+// value と境界値 bound の大小。NaN は None
+fn compare(value: &Comparable, bound: &Number) -> Option<Ordering> {
+    match value {
+        Comparable::Int(v) => match bound.as_i64() {
+            Some(b) => Some(v.cmp(&b)),
+            None => {
+                // 整数に収まらない境界は f64 で比べる（小数の境界は整数との間に等号が成り立たない）
+                let b = bound.as_f64()?;
+                if b.fract() == 0.0 && b.abs() < i64::MAX as f64 {
+                    Some(v.cmp(&(b as i64)))
+                } else if (*v as f64) < b {
+                    Some(Ordering::Less)
+                } else {
+                    Some(Ordering::Greater)
+                }
+            }
+        },
+        Comparable::Float(v) => v.partial_cmp(&bound.as_f64()?),
+    }
+}
+
+// 境界値同士の大小（CreateKey 時の min <= max の検証用）
+fn compare_bounds(a: &Number, b: &Number) -> Option<Ordering> {
+    match (a.as_i64(), b.as_i64()) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+}
+
+/// Key 作成時に制約そのものが Key の型に対して妥当か検証する
+pub fn validate_constraint(
+    keyname: &str,
+    keytype: KeyType,
+    constraint: &KeyConstraint,
+) -> Result<(), Error> {
+    let invalid = |reason: String| Error::InvalidConstraint {
+        key_name: keyname.to_string(),
+        reason,
+        location: "io::tools::constraint::validate_constraint",
+    };
+
+    let ordered = keytype.is_numeric() || keytype == KeyType::TIMESTAMP;
+    if (constraint.min.is_some() || constraint.max.is_some()) && !ordered {
+        return Err(invalid(format!(
+            "min/max is not supported for {:?}",
+            keytype
+        )));
+    }
+    if let (Some(min), Some(max)) = (&constraint.min, &constraint.max)
+        && compare_bounds(min, max) == Some(Ordering::Greater)
+    {
+        return Err(invalid(format!("min {} is greater than max {}", min, max)));
+    }
+    if (constraint.max_length.is_some() || constraint.regex.is_some()) && keytype != KeyType::TEXT {
+        return Err(invalid(format!(
+            "max_length/regex is only supported for TEXT, not {:?}",
+            keytype
+        )));
+    }
+    if let Some(pattern) = &constraint.regex {
+        cached_regex(pattern).map_err(|e| invalid(e.to_string()))?;
+    }
+    if let Some(allowed) = &constraint.allowed
+        && let Some(v) = allowed.iter().find(|v| v.keytype() != keytype)
+    {
+        return Err(invalid(format!(
+            "allowed value {:?} is not {:?}",
+            v, keytype
+        )));
+    }
+    if let Some(default) = &constraint.default {
+        if default.keytype() != keytype {
+            return Err(invalid(format!(
+                "default {:?} is not {:?}",
+                default, keytype
+            )));
+        }
+        check_constraint(keyname, constraint, default)
+            .map_err(|_| invalid("default violates the constraint".to_string()))?;
+    }
+    Ok(())
+}
+
+/// AlterKeyType 用に制約を新しい型へ移す。allowed / default が変換できず、
+/// または新しい型に対して妥当でなければ None
+pub fn convert_constraint(
+    keyname: &str,
+    constraint: &KeyConstraint,
+    to: KeyType,
+) -> Option<KeyConstraint> {
+    let allowed = match &constraint.allowed {
+        Some(allowed) => Some(
+            allowed
+                .iter()
+                .map(|v| convert_value(v, to))
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        None => None,
+    };
+    let default = match &constraint.default {
+        Some(default) => Some(convert_value(default, to)?),
+        None => None,
+    };
+    let converted = KeyConstraint {
+        allowed,
+        default,
+        ..constraint.clone()
+    };
+    validate_constraint(keyname, to, &converted).ok()?;
+    Some(converted)
+}
+
+/// 書き込まれる値が制約を満たすか検証する
+pub fn check_constraint(
+    keyname: &str,
+    constraint: &KeyConstraint,
+    value: &ValueEntry,
+) -> Result<(), Error> {
+    let violation = |reason: String| Error::ConstraintViolation {
+        key_name: keyname.to_string(),
+        reason,
+        location: "io::tools::constraint::check_constraint",
+    };
+
+    if let Some(n) = comparable(value) {
+        if let Some(min) = &constraint.min {
+            match compare(&n, min) {
+                None => return Err(violation("NaN is outside min/max".to_string())),
+                Some(Ordering::Less) => {
+                    return Err(violation(format!("{:?} is less than min {}", value, min)));
+                }
+                _ => {}
+            }
+        }
+        if let Some(max) = &constraint.max {
+            match compare(&n, max) {
+                None => return Err(violation("NaN is outside min/max".to_string())),
+                Some(Ordering::Greater) => {
+                    return Err(violation(format!(
+                        "{:?} is greater than max {}",
+                        value, max
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let ValueEntry::TEXT(text) = value {
+        if let Some(max_length) = constraint.max_length
+            && text.chars().count() > max_length
+        {
+            return Err(violation(format!(
+                "text is longer than max_length {}",
+                max_length
+            )));
+        }
+        if let Some(pattern) = &constraint.regex {
+            let re = cached_regex(pattern).map_err(|e| violation(e.to_string()))?;
+            if !re.is_match(text) {
+                return Err(violation(format!("text does not match /{}/", pattern)));
+            }
+        }
+    }
+
+    if let Some(allowed) = &constraint.allowed
+        && !allowed.contains(value)
+    {
+        return Err(violation(format!("{:?} is not an allowed value", value)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(min: Number, max: Number) -> KeyConstraint {
+        KeyConstraint {
+            min: Some(min),
+            max: Some(max),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nan_is_rejected() {
+        let c = range(Number::from(0), Number::from(10));
+        assert!(check_constraint("k", &c, &ValueEntry::DOUBLE(f64::NAN)).is_err());
+        assert!(check_constraint("k", &c, &ValueEntry::DOUBLE(5.0)).is_ok());
+    }
+
+    // 2^53 を超える INT64 も f64 に丸めずに比べる
+    #[test]
+    fn int64_bounds_are_exact() {
+        let max = (1i64 << 53) + 1;
+        let c = range(Number::from(0), Number::from(max));
+        assert!(check_constraint("k", &c, &ValueEntry::INT64(max)).is_ok());
+        assert!(check_constraint("k", &c, &ValueEntry::INT64(max + 1)).is_err());
+    }
+
+    #[test]
+    fn fractional_bounds_with_integers() {
+        let c = range(
+            Number::from_f64(1.5).unwrap(),
+            Number::from_f64(3.5).unwrap(),
+        );
+        assert!(check_constraint("k", &c, &ValueEntry::INT(1)).is_err());
+        assert!(check_constraint("k", &c, &ValueEntry::INT(2)).is_ok());
+        assert!(check_constraint("k", &c, &ValueEntry::INT(3)).is_ok());
+        assert!(check_constraint("k", &c, &ValueEntry::INT(4)).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+use crate::{io::ValueEntry, json::input::KeyType};
+
+// AlterKeyType 用の値変換
+// 情報を失う変換（小数の切り捨て、範囲外の整数など）は None を返す
+pub fn convert_value(value: &ValueEntry, to: KeyType) -> Option<ValueEntry> {
+    if value.keytype() == to {
+        return Some(value.clone());
+    }
+
+    match to {
+        KeyType::TEXT => Some(ValueEntry::TEXT(to_text(value))),
+        KeyType::BOOLEAN => match value {
+            ValueEntry::TEXT(s) => s.trim().parse().ok().map(ValueEntry::BOOLEAN),
+            ValueEntry::JSON(serde_json::Value::Bool(b)) => Some(ValueEntry::BOOLEAN(*b)),
+            _ => match to_i64(value)? {
+                0 => Some(ValueEntry::BOOLEAN(false)),
+                1 => Some(ValueEntry::BOOLEAN(true)),
+                _ => None,
+            },
+        },
+        KeyType::INT => i32::try_from(to_i64(value)?).ok().map(ValueEntry::INT),
+        KeyType::INT64 => to_i64(value).map(ValueEntry::INT64),
+        KeyType::TIMESTAMP => to_i64(value).map(ValueEntry::TIMESTAMP),
+        KeyType::FLOAT => {
+            let f = to_f64(value)?;
+            // f32 に収まらない値は inf になるため失敗扱い
+            let narrowed = f as f32;
+            (narrowed.is_finite() || !f.is_finite()).then_some(ValueEntry::FLOAT(narrowed))
+        }
+        KeyType::DOUBLE => to_f64(value).map(ValueEntry::DOUBLE),
+        KeyType::JSON => match value {
+            ValueEntry::TEXT(s) => serde_json::from_str(s).ok().map(ValueEntry::JSON),
+            _ => json_of(value).map(ValueEntry::JSON),
+        },
+        KeyType::BLOB => match value {
+            ValueEntry::TEXT(s) => Some(ValueEntry::BLOB(s.as_bytes().to_vec())),
+            _ => None,
+        },
+    }
+}
+
+fn to_text(value: &ValueEntry) -> String {
+    match value {
+        ValueEntry::TEXT(s) => s.clone(),
+        ValueEntry::BOOLEAN(b) => b.to_string(),
+        ValueEntry::INT(i) => i.to_string(),
+        ValueEntry::FLOAT(f) => f.to_string(),
+        ValueEntry::INT64(i) => i.to_string(),
+        ValueEntry::DOUBLE(f) => f.to_string(),
+        ValueEntry::TIMESTAMP(t) => t.to_string(),
+        ValueEntry::JSON(v) => v.to_string(),
+        ValueEntry::BLOB(b) => STANDARD.encode(b),
+    }
+}
+
+fn to_i64(value: &ValueEntry) -> Option<i64> {
+    match value {
+        ValueEntry::TEXT(s) => s.trim().parse().ok(),
+        ValueEntry::BOOLEAN(b) => Some(*b as i64),
+        ValueEntry::INT(i) => Some(*i as i64),
+        ValueEntry::INT64(i) | ValueEntry::TIMESTAMP(i) => Some(*i),
+        ValueEntry::FLOAT(f) => float_to_i64(*f as f64),
+        ValueEntry::DOUBLE(f) => float_to_i64(*f),
+        ValueEntry::JSON(v) => v.as_i64(),
+        ValueEntry::BLOB(_) => None,
+    }
+}
+
+// 小数部を持つ値や範囲外の値は変換しない
+fn float_to_i64(f: f64) -> Option<i64> {
+    (f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64).then_some(f as i64)
+}
+
+fn to_f64(value: &ValueEntry) -> Option<f64> {
+    match value {
+        ValueEntry::TEXT(s) => s.trim().parse().ok(),
+        ValueEntry::JSON(v) => v.as_f64(),
+        ValueEntry::TIMESTAMP(_) | ValueEntry::BOOLEAN(_) | ValueEntry::BLOB(_) => None,
+        _ => value.as_f64(),
+    }
+}
+
+fn json_of(value: &ValueEntry) -> Option<serde_json::Value> {
+    match value {
+        ValueEntry::BOOLEAN(b) => Some(serde_json::Value::Bool(*b)),
+        ValueEntry::INT(i) => Some((*i).into()),
+        ValueEntry::INT64(i) | ValueEntry::TIMESTAMP(i) => Some((*i).into()),
+        ValueEntry::FLOAT(f) => serde_json::Number::from_f64(*f as f64).map(Into::into),
+        ValueEntry::DOUBLE(f) => serde_json::Number::from_f64(*f).map(Into::into),
+        _ => None,
+    }
+}
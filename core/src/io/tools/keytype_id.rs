@@ -6,15 +6,26 @@ pub fn keytype_id(keytype: KeyType) -> u8 {
         KeyType::BOOLEAN => 2,
         KeyType::TEXT => 3,
         KeyType::FLOAT => 4,
+        KeyType::INT64 => 5,
+        KeyType::DOUBLE => 6,
+        KeyType::TIMESTAMP => 7,
+        KeyType::JSON => 8,
+        KeyType::BLOB => 9,
     }
 }
 
-pub fn id_keytype(id: u8) -> KeyType {
+// 未知のIDは None（壊れたレコードでも panic させない）
+pub fn id_keytype(id: u8) -> Option<KeyType> {
     match id {
-        1 => KeyType::INT,
-        2 => KeyType::BOOLEAN,
-        3 => KeyType::TEXT,
-        4 => KeyType::FLOAT,
-        _ => panic!("ありえん"), // デフォルト
+        1 => Some(KeyType::INT),
+        2 => Some(KeyType::BOOLEAN),
+        3 => Some(KeyType::TEXT),
+        4 => Some(KeyType::FLOAT),
+        5 => Some(KeyType::INT64),
+        6 => Some(KeyType::DOUBLE),
+        7 => Some(KeyType::TIMESTAMP),
+        8 => Some(KeyType::JSON),
+        9 => Some(KeyType::BLOB),
+        _ => None,
     }
 }
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use crate::config::LoginConfig;
+
+// 1つのユーザー名または IP の失敗状況。時刻は UNIX ミリ秒
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: u64,
+    blocked_until: u64,
+}
+
+// /login の失敗回数を数え、次に試せるまでの待ち時間を決める（メモリ上のみ）
+pub struct LoginThrottle {
+    config: LoginConfig,
+    users: HashMap<String, Attempts>,
+    ips: HashMap<String, Attempts>,
+}
+
+impl LoginThrottle {
+    pub fn new(config: LoginConfig) -> Self {
+        LoginThrottle {
+            config,
+            users: HashMap::new(),
+            ips: HashMap::new(),
+        }
+    }
+
+    // 待ちが必要なら残り秒数を返す
+    pub fn retry_after(&self, user: &str, ip: &str, now: u64) -> Option<u64> {
+        let blocked_until = [self.users.get(user), self.ips.get(ip)]
+            .into_iter()
+            .flatten()
+            .map(|a| a.blocked_until)
+            .max()?;
+        (blocked_until > now).then(|| (blocked_until - now).div_ceil(1000))
+    }
+
+    // 待ちが無ければ、パスワードを検証する前に失敗として数えておく
+    // 確認と記録を同じロックの中で行うので、同時に送られた試行もすべて数えられる
+    pub fn begin_attempt(&mut self, user: &str, ip: &str, now: u64) -> Option<u64> {
+        if let Some(secs) = self.retry_after(user, ip, now) {
+            return Some(secs);
+        }
+        self.record_failure(user, ip, now);
+        None
+    }
+
+    fn record_failure(&mut self, user: &str, ip: &str, now: u64) {
+        self.forget_idle(now);
+        let config = &self.config;
+        let user_entry = self.users.entry(user.to_string()).or_default();
+        block(
+            user_entry,
+            config,
+            config.free_attempts,
+            config.lockout_threshold,
+            now,
+        );
+        let ip_entry = self.ips.entry(ip.to_string()).or_default();
+        block(
+            ip_entry,
+            config,
+            config.ip_free_attempts,
+            config.ip_lockout_threshold,
+            now,
+        );
+    }
+
+    // 成功したユーザー名の失敗は忘れる。IP の失敗は他のユーザー名を試す攻撃のため残し、
+    // begin_attempt で数えた分だけ戻す
+    pub fn record_success(&mut self, user: &str, ip: &str) {
+        self.users.remove(user);
+        if let Some(attempts) = self.ips.get_mut(ip) {
+            attempts.failures = attempts.failures.saturating_sub(1);
+        }
+    }
+
+    // UnlockUser 用。ロックされていたら true
+    pub fn unlock_user(&mut self, user: &str, now: u64) -> bool {
+        self.users
+            .remove(user)
+            .is_some_and(|a| a.blocked_until > now)
+    }
+
+    // lockout_secs より前に最後に失敗し、ロックも切れたものは数え直す
+    fn forget_idle(&mut self, now: u64) {
+        let idle = self.config.lockout_secs * 1000;
+        let keep = |a: &mut Attempts| a.blocked_until > now || now - a.last_failure < idle;
+        self.users.retain(|_, a| keep(a));
+        self.ips.retain(|_, a| keep(a));
+    }
+}
+
+fn block(
+    attempts: &mut Attempts,
+    config: &LoginConfig,
+    free_attempts: u32,
+    lockout_threshold: u32,
+    now: u64,
+) {
+    attempts.failures += 1;
+    attempts.last_failure = now;
+
+    let wait_secs = if attempts.failures >= lockout_threshold {
+        config.lockout_secs
+    } else if attempts.failures > free_attempts {
+        let doublings = (attempts.failures - free_attempts - 1).min(32);
+        config
+            .backoff_base_secs
+            .saturating_mul(1 << doublings)
+            .min(config.backoff_max_secs)
+    } else {
+        return;
+    };
+    attempts.blocked_until = attempts.blocked_until.max(now + wait_secs * 1000);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1000;
+
+    // ユーザー名だけを数えたいときは毎回違う IP から試す
+    fn fail_from_many_ips(throttle: &mut LoginThrottle, user: &str, n: u32, now: u64) {
+        for i in 0..n {
+            assert_eq!(
+                throttle.begin_attempt(user, &format!("192.0.2.{i}"), now),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_after_the_free_attempts() {
+        let mut throttle = LoginThrottle::new(LoginConfig::default());
+        let mut now = 1_000_000;
+        fail_from_many_ips(&mut throttle, "bob", 3, now);
+        assert_eq!(throttle.retry_after("bob", "198.51.100.1", now), None);
+
+        // 4回目から 1, 2, 4 秒
+        for wait in [1, 2, 4] {
+            assert_eq!(throttle.begin_attempt("bob", "198.51.100.1", now), None);
+            assert_eq!(
+                throttle.begin_attempt("bob", "198.51.100.2", now),
+                Some(wait)
+            );
+            now += wait * SEC;
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped_and_lockout_starts_at_the_threshold() {
+        let config = LoginConfig {
+            backoff_max_secs: 2,
+            ..LoginConfig::default()
+        };
+        let mut throttle = LoginThrottle::new(config);
+        let mut now = 1_000_000;
+        for _ in 0..9 {
+            assert_eq!(throttle.begin_attempt("bob", "198.51.100.1", now), None);
+            let wait = throttle.retry_after("bob", "", now).unwrap_or(0);
+            assert!(wait <= 2);
+            now += wait * SEC;
+        }
+        // 10回目でロック
+        assert_eq!(throttle.begin_attempt("bob", "198.51.100.1", now), None);
+        assert_eq!(throttle.retry_after("bob", "", now), Some(900));
+        // 待っている間の試行は数えない
+        assert_eq!(throttle.begin_attempt("bob", "", now + SEC), Some(899));
+    }
+
+    #[test]
+    fn one_ip_trying_many_users_is_throttled() {
+        let mut throttle = LoginThrottle::new(LoginConfig::default());
+        let now = 1_000_000;
+        for i in 0..11 {
+            assert_eq!(
+                throttle.begin_attempt(&format!("user{i}"), "198.51.100.1", now),
+                None
+            );
+        }
+        assert_eq!(
+            throttle.begin_attempt("alice", "198.51.100.1", now),
+            Some(1)
+        );
+        assert_eq!(throttle.begin_attempt("alice", "198.51.100.2", now), None);
+    }
+
+    #[test]
+    fn success_forgets_the_user_but_keeps_other_ip_failures() {
+        let mut throttle = LoginThrottle::new(LoginConfig::default());
+        let now = 1_000_000;
+        fail_from_many_ips(&mut throttle, "bob", 3, now);
+        assert_eq!(throttle.begin_attempt("eve", "198.51.100.1", now), None);
+
+        // begin_attempt で数えた分だけ IP の回数を戻す
+        assert_eq!(throttle.begin_attempt("bob", "198.51.100.1", now), None);
+        throttle.record_success("bob", "198.51.100.1");
+        assert_eq!(throttle.retry_after("bob", "198.51.100.2", now), None);
+        assert!(!throttle.users.contains_key("bob"));
+        assert_eq!(throttle.ips["198.51.100.1"].failures, 1);
+    }
+
+    #[test]
+    fn unlock_user_reports_whether_it_was_locked() {
+        let mut throttle = LoginThrottle::new(LoginConfig::default());
+        let now = 1_000_000;
+        assert!(!throttle.unlock_user("bob", now));
+
+        fail_from_many_ips(&mut throttle, "bob", 4, now);
+        assert!(throttle.unlock_user("bob", now));
+        assert_eq!(throttle.begin_attempt("bob", "198.51.100.1", now), None);
+    }
+
+    #[test]
+    fn idle_failures_are_forgotten() {
+        let mut throttle = LoginThrottle::new(LoginConfig::default());
+        let now = 1_000_000;
+        fail_from_many_ips(&mut throttle, "bob", 3, now);
+
+        // lockout_secs 経てば 3回の失敗は数え直し
+        let later = now + 900 * SEC;
+        assert_eq!(throttle.begin_attempt("bob", "198.51.100.1", later), None);
+        assert_eq!(throttle.users["bob"].failures, 1);
+    }
+}
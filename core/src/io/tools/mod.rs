@@ -1,5 +1,13 @@
-use rand::prelude;
-
+pub mod base64_bytes;
+pub mod constraint;
+pub mod convert;
 pub mod key_bytes;
 pub mod keytype_id;
+pub mod login_throttle;
+pub mod prefix;
 pub mod range;
+pub mod role;
+#[cfg(test)]
+pub mod temp_storage;
+pub mod time;
+pub mod token;
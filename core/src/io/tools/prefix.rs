@@ -0,0 +1,63 @@
+use lmdb::Cursor;
+
+/// prefix で始まるレコードだけを順に返す（空の prefix なら全件）
+pub fn scan_prefix<'txn, 'c, C>(
+    cursor: &'c mut C,
+    prefix: &'c [u8],
+) -> impl Iterator<Item = (&'txn [u8], &'txn [u8])> + 'c
+where
+    C: Cursor<'txn>,
+    'txn: 'c,
+{
+    scan_from(cursor, prefix).take_while(move |(k, _)| k.starts_with(prefix))
+}
+
+/// start 以上のキーのレコードを順に返す（空の start なら全件）
+/// lmdb の iter_from は該当キーが無いと panic するため、SET_RANGE の結果を見てから走査する
+pub fn scan_from<'txn, 'c, C>(
+    cursor: &'c mut C,
+    start: &[u8],
+) -> impl Iterator<Item = (&'txn [u8], &'txn [u8])> + 'c
+where
+    C: Cursor<'txn>,
+    'txn: 'c,
+{
+    // LMDB は長さ0のキーでの SET_RANGE を受け付けない
+    let first = if start.is_empty() {
+        cursor.get(None, None, lmdb_sys::MDB_FIRST)
+    } else {
+        cursor.get(Some(start), None, lmdb_sys::MDB_SET_RANGE)
+    }
+    .ok()
+    .and_then(|(k, v)| k.map(|k| (k, v)));
+    let rest = first.is_some().then(|| cursor.iter());
+
+    first.into_iter().chain(rest.into_iter().flatten())
+}
+
+/// end 未満のキーのレコードを逆順に返す（空の end なら全件）
+pub fn scan_before<'txn, 'c, C>(
+    cursor: &'c mut C,
+    end: &[u8],
+) -> impl Iterator<Item = (&'txn [u8], &'txn [u8])> + 'c
+where
+    C: Cursor<'txn>,
+    'txn: 'c,
+{
+    // end 以上の最初のキーの1つ前。end 以上のキーが無ければ最後のキー
+    let last = if !end.is_empty() && cursor.get(Some(end), None, lmdb_sys::MDB_SET_RANGE).is_ok() {
+        cursor.get(None, None, lmdb_sys::MDB_PREV)
+    } else {
+        cursor.get(None, None, lmdb_sys::MDB_LAST)
+    };
+    let mut next = last.ok().and_then(|(k, v)| k.map(|k| (k, v)));
+
+    std::iter::from_fn(move || {
+        let record = next?;
+        next = cursor
+            .get(None, None, lmdb_sys::MDB_PREV)
+            .ok()
+            .and_then(|(k, v)| k.map(|k| (k, v)));
+        Some(record)
+    })
+}
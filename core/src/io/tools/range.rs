@@ -1,15 +1,11 @@
-use std::{collections::HashSet, result};
+use std::collections::HashSet;
 
 use kasane_logic::{
-    function::{
-        line::line,
-        point::{self, point},
-        triangle::triangle,
-    },
-    id::{SpaceTimeId, pure::PureSpaceTimeId},
+    function::{line::line, point::point, triangle::triangle},
+    id::{DimensionRange, SpaceTimeId, pure::PureSpaceTimeId},
 };
 
-use crate::json::input::Range;
+use crate::{error::Error, json::input::Range};
 
 pub fn range(rng: Range) -> Result<Vec<Vec<u8>>, String> {
     let mut result: Vec<Vec<u8>> = Vec::new();
@@ -65,30 +61,36 @@ fn ids_to_bitmask(ids: HashSet<SpaceTimeId>) -> Vec<Vec<u8>> {
 }
 
 fn id_to_bitmask(ids: SpaceTimeId) -> Vec<Vec<u8>> {
-    let mut result = vec![];
-    for id in ids.pure() {
-        let mut bits = Vec::with_capacity(1 + (id.z as usize) * 3);
-        bits.push(if id.f >= 0 { 1 } else { 0 });
-        for shift in 0..id.z {
-            bits.push(if is_even_after(id.x as i32, shift.into()) {
-                0
-            } else {
-                1
-            });
-            bits.push(if is_even_after(id.y as i32, shift.into()) {
-                0
-            } else {
-                1
-            });
-            bits.push(if is_even_after(id.f.abs() as i32, shift.into()) {
-                0
-            } else {
-                1
-            });
-        }
-        result.push(bits);
+    ids.pure().iter().map(pure_to_bitmask).collect()
+}
+
+/// 単一セルをビット列にする
+/// 先頭は f の符号、以降は上位ビットから (x, y, f) の順に並べる
+pub fn pure_to_bitmask(id: &PureSpaceTimeId) -> Vec<u8> {
+    let mut bits = Vec::with_capacity(1 + (id.z as usize) * 3);
+    bits.push(if id.f >= 0 { 1 } else { 0 });
+    // 負の f は !f (= -f-1) で表すと、上位セルも同じ符号のまま前方一致になる
+    let f_bits = if id.f >= 0 { id.f } else { !id.f };
+    // 上位ビットから並べることで、ビット列の前方一致が親子関係を表すようにする
+    for level in 0..id.z {
+        let shift = id.z - 1 - level;
+        bits.push(if is_even_after(id.x as i32, shift.into()) {
+            0
+        } else {
+            1
+        });
+        bits.push(if is_even_after(id.y as i32, shift.into()) {
+            0
+        } else {
+            1
+        });
+        bits.push(if is_even_after(f_bits, shift.into()) {
+            0
+        } else {
+            1
+        });
     }
-    result
+    bits
 }
 
 /// zビット目以降を右シフトして偶奇判定
@@ -96,7 +98,7 @@ fn is_even_after(dim: i32, z: u32) -> bool {
     ((dim >> z) & 1) == 0
 }
 
-fn dedup_bitmasks(mut masks: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+pub fn dedup_bitmasks(mut masks: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
     // 短い順にソート
     masks.sort_by_key(|m| m.len());
 
@@ -178,8 +180,9 @@ pub fn bitmask_to_id(bits: &[u8]) -> PureSpaceTimeId {
     let mut y: u32 = 0;
     let mut f_abs: u32 = 0;
 
-    for shift in 0..z {
-        let idx = 1 + (shift as usize) * 3;
+    for level in 0..z {
+        let idx = 1 + (level as usize) * 3;
+        let shift = z - 1 - level;
         let bx = bits[idx];
         let by = bits[idx + 1];
         let bf = bits[idx + 2];
@@ -198,7 +201,7 @@ pub fn bitmask_to_id(bits: &[u8]) -> PureSpaceTimeId {
     let f = if sign == 1 {
         f_abs as i32
     } else {
-        -(f_abs as i32)
+        !(f_abs as i32)
     };
 
     PureSpaceTimeId {
@@ -210,3 +213,134 @@ pub fn bitmask_to_id(bits: &[u8]) -> PureSpaceTimeId {
         t: 0,
     }
 }
+
+/// フォーマット0（下位ビットから並べ、負の f は絶対値で持つ）のビット列を読む
+/// 旧形式の value DB を移行するときだけ使う
+pub fn legacy_bitmask_to_id(bits: &[u8]) -> Option<PureSpaceTimeId> {
+    if bits.is_empty() || !(bits.len() - 1).is_multiple_of(3) {
+        return None;
+    }
+    let z = ((bits.len() - 1) / 3) as u8;
+
+    let mut x: u32 = 0;
+    let mut y: u32 = 0;
+    let mut f_abs: u32 = 0;
+    for shift in 0..z {
+        let idx = 1 + (shift as usize) * 3;
+        x |= (bits[idx] as u32) << shift;
+        y |= (bits[idx + 1] as u32) << shift;
+        f_abs |= (bits[idx + 2] as u32) << shift;
+    }
+    let f = if bits[0] == 1 {
+        f_abs as i32
+    } else {
+        -(f_abs as i32)
+    };
+
+    Some(PureSpaceTimeId {
+        z,
+        f,
+        x,
+        y,
+        i: 0,
+        t: 0,
+    })
+}
+
+/// ビット列を単一セルの SpaceTimeId にする
+pub fn bitmask_to_stid(bits: &[u8]) -> Result<SpaceTimeId, Error> {
+    let id = bitmask_to_id(bits);
+    SpaceTimeId::new(
+        id.z,
+        DimensionRange::Single(id.f),
+        DimensionRange::Single(id.x),
+        DimensionRange::Single(id.y),
+        id.i,
+        DimensionRange::Any,
+    )
+    .map_err(|message| Error::RangeError { message })
+}
+
+/// ビット列を "z/f/x/y_i/t" 形式の空間ID文字列にする
+pub fn bitmask_to_id_string(bits: &[u8]) -> Result<String, Error> {
+    bitmask_to_stid(bits).map(|id| id.to_string())
+}
+
+/// bitmask_to_id_string の逆変換（単一セルのみ対応）
+pub fn id_string_to_bitmask(s: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Invalid spatial id '{}'", s);
+    let (space, time) = s.split_once('_').unwrap_or((s, "0/-"));
+    let parts: Vec<&str> = space.split('/').collect();
+    let [z, f, x, y] = parts[..] else {
+        return Err(invalid());
+    };
+    let i = time.split('/').next().unwrap_or("0");
+
+    let id = SpaceTimeId::new(
+        z.parse().map_err(|_| invalid())?,
+        DimensionRange::Single(f.parse().map_err(|_| invalid())?),
+        DimensionRange::Single(x.parse().map_err(|_| invalid())?),
+        DimensionRange::Single(y.parse().map_err(|_| invalid())?),
+        i.parse().map_err(|_| invalid())?,
+        DimensionRange::Any,
+    )?;
+    id_to_bitmask(id).pop().ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(z: u8, f: i32, x: u32, y: u32) -> PureSpaceTimeId {
+        PureSpaceTimeId {
+            z,
+            f,
+            x,
+            y,
+            i: 0,
+            t: 0,
+        }
+    }
+
+    #[test]
+    fn bitmask_round_trip() {
+        for (z, f, x, y) in [
+            (0, 0, 0, 0),
+            (1, 1, 1, 0),
+            (1, -1, 0, 1),
+            (5, 13, 7, 30),
+            (5, -16, 31, 0),
+            (12, -2048, 4095, 1234),
+            (20, 524287, 1048575, 3),
+        ] {
+            let id = cell(z, f, x, y);
+            assert_eq!(bitmask_to_id(&pure_to_bitmask(&id)), id);
+        }
+    }
+
+    #[test]
+    fn id_string_round_trip() {
+        for s in ["3/5/2/7_0/-", "4/-3/15/0_0/-", "0/0/0/0_0/-"] {
+            let bits = id_string_to_bitmask(s).unwrap();
+            assert_eq!(bitmask_to_id_string(&bits).unwrap(), s);
+        }
+    }
+
+    // 親セルのビット列は子セルのビット列の前方一致になる
+    #[test]
+    fn parent_is_prefix_of_child() {
+        for (z, f, x, y) in [(6, 21, 40, 63), (6, -21, 1, 2), (6, -1, 0, 0)] {
+            let child = pure_to_bitmask(&cell(z, f, x, y));
+            let parent = pure_to_bitmask(&cell(z - 2, f >> 2, x >> 2, y >> 2));
+            assert!(child.starts_with(&parent));
+        }
+    }
+
+    #[test]
+    fn legacy_layout_is_decoded() {
+        // z=3, x=0b110, y=0b001, f=-0b011 を旧形式（下位ビットから、f は絶対値）で並べたもの
+        let legacy = [0, 0, 1, 1, 1, 0, 1, 1, 0, 0];
+        assert_eq!(legacy_bitmask_to_id(&legacy), Some(cell(3, -3, 6, 1)));
+        assert_eq!(legacy_bitmask_to_id(&[1, 0]), None);
+    }
+}
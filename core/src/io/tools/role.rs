@@ -0,0 +1,85 @@
+use crate::json::input::{
+    AllOrChoose, CommandDatabase, CommandKey, CommandSpace, KeyPrivilege, Privileges, ShareLevel,
+    SpacePrivilege,
+};
+
+/// 組み込みロール。role DB には保存せず、作成・削除もできない
+pub const BUILTIN_ROLES: [&str; 4] = [ADMIN_ROLE, "writer", "reader", LEGACY_ROLE];
+/// ユーザー・セッション・ロールの管理もできるロール
+pub const ADMIN_ROLE: &str = "admin";
+/// roles を省略して作ったユーザーのロール
+pub const DEFAULT_ROLE: &str = "writer";
+/// ロール導入前から居るユーザーのロール。導入前と同じく全 Space を読み書きできる
+pub const LEGACY_ROLE: &str = "legacy";
+
+/// 組み込みロールの権限
+pub fn builtin_role(name: &str) -> Option<Privileges> {
+    match name {
+        // legacy は admin と同じ権限。ユーザー・セッション・ロールの管理は admin ロールだけができる
+        ADMIN_ROLE | LEGACY_ROLE => Some(Privileges {
+            database: Some(AllOrChoose::All),
+            spaces: vec![SpacePrivilege {
+                target_space: AllOrChoose::All,
+                command: AllOrChoose::All,
+            }],
+            keys: vec![KeyPrivilege {
+                target_space: AllOrChoose::All,
+                target_key: AllOrChoose::All,
+                command: AllOrChoose::All,
+            }],
+        }),
+        // Space を作れるだけで、書き込めるのは所有する Space と共有された Space
+        "writer" => Some(Privileges {
+            database: Some(AllOrChoose::Choose(vec![
+                CommandDatabase::CreateSpace,
+                CommandDatabase::CloneSpace,
+                CommandDatabase::ShowSpaces,
+                CommandDatabase::Version,
+            ])),
+            spaces: Vec::new(),
+            keys: Vec::new(),
+        }),
+        "reader" => Some(Privileges {
+            database: Some(AllOrChoose::Choose(vec![
+                CommandDatabase::ShowSpaces,
+                CommandDatabase::Version,
+            ])),
+            ..space_privileges(AllOrChoose::All, ShareLevel::Read)
+        }),
+        _ => None,
+    }
+}
+
+/// Space の共有で与える権限。Read は reader ロールと同じ読み取り系のコマンド
+pub fn space_privileges(target_space: AllOrChoose<Vec<String>>, level: ShareLevel) -> Privileges {
+    let (space_command, key_command) = match level {
+        ShareLevel::Read => (
+            AllOrChoose::Choose(vec![
+                CommandSpace::ExportSpace,
+                CommandSpace::InfoSpace,
+                CommandSpace::ShowKeys,
+            ]),
+            AllOrChoose::Choose(vec![
+                CommandKey::SelectValue,
+                CommandKey::SelectPyramid,
+                CommandKey::ValueHistory,
+                CommandKey::InfoKey,
+                CommandKey::ShowValues,
+                CommandKey::FilterValue,
+            ]),
+        ),
+        ShareLevel::Write => (AllOrChoose::All, AllOrChoose::All),
+    };
+    Privileges {
+        database: None,
+        spaces: vec![SpacePrivilege {
+            target_space: target_space.clone(),
+            command: space_command,
+        }],
+        keys: vec![KeyPrivilege {
+            target_space,
+            target_key: AllOrChoose::All,
+            command: key_command,
+        }],
+    }
+}
@@ -0,0 +1,75 @@
+use std::{ops::Deref, path::PathBuf, sync::Arc};
+
+use uuid::Uuid;
+
+use crate::{config::Config, io::full::Storage};
+
+// テスト用の一時ディレクトリ。drop で中身ごと消す
+pub struct TempDir(PathBuf);
+
+impl Default for TempDir {
+    fn default() -> Self {
+        let path = std::env::temp_dir().join(format!("kasane-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl TempDir {
+    pub fn path(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// 一時ディレクトリに開いた Storage。storage を先に閉じてからディレクトリを消す
+pub struct TempStorage {
+    storage: Arc<Storage>,
+    _dir: TempDir,
+}
+
+impl Default for TempStorage {
+    fn default() -> Self {
+        Self::with_config(Config::default())
+    }
+}
+
+impl TempStorage {
+    pub fn with_config(config: Config) -> Self {
+        Self::open(TempDir::default(), config)
+    }
+
+    // 既にデータを書いたディレクトリを開く（移行のテスト等）
+    pub fn open(dir: TempDir, mut config: Config) -> Self {
+        config.storage.path = Some(dir.path().clone());
+        TempStorage {
+            storage: Arc::new(Storage::new(&config).unwrap()),
+            _dir: dir,
+        }
+    }
+
+    // 閉じてから同じディレクトリを開き直す（再起動のテスト等）
+    pub fn reopen(self, config: Config) -> Self {
+        let TempStorage { storage, _dir } = self;
+        drop(storage);
+        Self::open(_dir, config)
+    }
+
+    // コマンド関数に渡す Arc
+    pub fn shared(&self) -> Arc<Storage> {
+        self.storage.clone()
+    }
+}
+
+impl Deref for TempStorage {
+    type Target = Storage;
+
+    fn deref(&self) -> &Storage {
+        &self.storage
+    }
+}
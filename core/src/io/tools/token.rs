@@ -0,0 +1,23 @@
+use rand::{Rng, distributions::Alphanumeric, rngs::OsRng};
+use sha2::{Digest, Sha256};
+
+/// API トークンの形式: 接頭辞 + 英数字。接頭辞で JWT と区別する
+pub const API_TOKEN_PREFIX: &str = "ksn_";
+pub const API_TOKEN_LEN: usize = 40;
+
+/// トークンの SHA-256 を16進文字列で返す。LMDB にはトークンそのものではなくこの値を保存する
+pub fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// OS の乱数で英数字 len 文字の文字列を作る（生成パスワード・API トークン用）
+pub fn random_token(len: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
@@ -12,6 +12,19 @@ pub enum AllOrChoose<T> {
     All,
 }
 
+impl<T> AllOrChoose<Vec<T>> {
+    // All か、選んだものに item が含まれるか
+    pub fn includes<Q: ?Sized>(&self, item: &Q) -> bool
+    where
+        T: PartialEq<Q>,
+    {
+        match self {
+            AllOrChoose::All => true,
+            AllOrChoose::Choose(list) => list.iter().any(|t| t == item),
+        }
+    }
+}
+
 // ---------------------- Space管理 ----------------------
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,12 +37,89 @@ pub struct DropSpace {
     pub space_name: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameSpace {
+    pub space_name: String,
+    pub new_space_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CloneSpace {
+    pub space_name: String,
+    pub new_space_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportSpace {
+    pub space_name: String,
+}
+
+// data は ExportSpace が出力した NDJSON。Space が無ければ作成する
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportSpace {
+    pub space_name: String,
+    pub data: String,
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+// 既存の Key / 値と衝突したときの扱い
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+pub enum OnConflict {
+    #[default]
+    Error,
+    Skip,
+    Overwrite,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateKey {
     pub space_name: String,
     pub key_name: String,
     pub key_type: KeyType,
     pub key_mode: KeyMode,
+    #[serde(default)]
+    pub pyramid: Option<Pyramid>,
+    #[serde(default)]
+    pub history: bool,
+    #[serde(default)]
+    pub constraint: Option<KeyConstraint>,
+}
+
+//Key に保存される値の制約。insert / patch / update 時に検証される
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct KeyConstraint {
+    //数値・TIMESTAMP の下限と上限（両端を含む）。整数は丸めずに比較する
+    #[serde(default)]
+    pub min: Option<serde_json::Number>,
+    #[serde(default)]
+    pub max: Option<serde_json::Number>,
+    //TEXT の最大文字数
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    //TEXT が一致すべき正規表現
+    #[serde(default)]
+    pub regex: Option<String>,
+    //カテゴリ値として許可される値の集合
+    #[serde(default)]
+    pub allowed: Option<Vec<ValueEntry>>,
+    //UpdateValue で値が省略されたときに使う値。無ければ値の省略はエラー
+    #[serde(default)]
+    pub default: Option<ValueEntry>,
+}
+
+//書き込み時に同一トランザクションで更新される集約済みズームレベル
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Pyramid {
+    pub zooms: Vec<u8>,
+    pub aggregations: Vec<Aggregation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Avg,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
@@ -44,6 +134,21 @@ pub enum KeyType {
     BOOLEAN,
     TEXT,
     FLOAT,
+    INT64,
+    DOUBLE,
+    TIMESTAMP,
+    JSON,
+    BLOB,
+}
+
+impl KeyType {
+    // Sum/Avg などの集約ができる型
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            KeyType::INT | KeyType::FLOAT | KeyType::INT64 | KeyType::DOUBLE
+        )
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -52,6 +157,37 @@ pub struct DropKey {
     pub key_name: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameKey {
+    pub space_name: String,
+    pub key_name: String,
+    pub new_key_name: String,
+}
+
+// 既存の Key へ値をコピーする。to_space_name 省略時は同じ Space、
+// range 省略時は全ての値が対象。コピー先の既存値は上書きされる
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CopyKey {
+    pub space_name: String,
+    pub key_name: String,
+    #[serde(default)]
+    pub to_space_name: Option<String>,
+    pub to_key_name: String,
+    #[serde(default)]
+    pub range: Option<Range>,
+}
+
+// 変換できない値がある場合、drop_failed が true ならその値を削除して続行し、
+// false なら何も変更せずに失敗した値を報告する
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlterKeyType {
+    pub space_name: String,
+    pub key_name: String,
+    pub key_type: KeyType,
+    #[serde(default)]
+    pub drop_failed: bool,
+}
+
 // ---------------------- Value管理 ----------------------
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -60,6 +196,12 @@ pub struct InsertValue {
     pub key_name: String,
     pub range: Range,
     pub value: ValueEntry,
+    //有効期限（秒）。expires_at と両方指定した場合は expires_at を優先
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    //有効期限（UNIXミリ秒）
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -68,15 +210,22 @@ pub struct PatchValue {
     pub key_name: String,
     pub range: Range,
     pub value: ValueEntry,
+    //有効期限（秒）。expires_at と両方指定した場合は expires_at を優先
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    //有効期限（UNIXミリ秒）
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
-// #[derive(Debug, Serialize, Deserialize, Clone)]
-// pub struct UpdateValue {
-//     pub space_name: String,
-//     pub key_name: String,
-//     pub range: Range,
-//     pub value: ValueEntry,
-// }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateValue {
+    pub space_name: String,
+    pub key_name: String,
+    pub range: Range,
+    #[serde(default)]
+    pub value: Option<ValueEntry>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeleteValue {
@@ -94,6 +243,58 @@ pub struct SelectValue {
     pub center: bool,
     pub id_string: bool,
     pub id_pure: bool,
+    //指定した時刻(UNIXミリ秒)時点の値を履歴から復元する
+    #[serde(default)]
+    pub as_of: Option<u64>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    //Czml の色分け
+    #[serde(default)]
+    pub color_ramp: Option<ColorRamp>,
+}
+
+// SelectValue / ShowValues の出力形式
+// GeoJSON はセルの底面ポリゴンか中心点を Feature として返す
+// Czml はセルごとの box エンティティを返す
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Value,
+    GeoJsonPolygon,
+    GeoJsonPoint,
+    Czml,
+}
+
+// key_name の数値を stops の間で線形補間して色にする
+// key_name 省略時は最初の Key、stops 省略時は値の最小（青）から最大（赤）
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ColorRamp {
+    #[serde(default)]
+    pub key_name: Option<String>,
+    #[serde(default)]
+    pub stops: Vec<ColorStop>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ColorStop {
+    pub value: f64,
+    //RGBA
+    pub color: [u8; 4],
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ValueHistory {
+    pub space_name: String,
+    pub key_name: String,
+    pub range: Range,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectPyramid {
+    pub space_name: String,
+    pub key_name: String,
+    pub zoom: u8,
+    pub range: Range,
 }
 
 // ---------------------- Range & Function ----------------------
@@ -230,18 +431,210 @@ pub struct InfoSpace {
     pub space_name: String,
 }
 
+// Space を他のユーザーかロールに共有する。実行できるのは所有者と admin
+// user_name と role_name のどちらか一方を指定する。同じ相手への共有は level を置き換える
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShareSpace {
+    pub space_name: String,
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub role_name: Option<String>,
+    pub level: ShareLevel,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnshareSpace {
+    pub space_name: String,
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub role_name: Option<String>,
+}
+
+// Read は Space 内の読み取り系コマンド、Write は Space 内の全コマンド
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShareLevel {
+    Read,
+    Write,
+}
+
+// Space の共有先（InfoSpace と space_acl DB で使う）
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SpaceShare {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role_name: Option<String>,
+    pub level: ShareLevel,
+}
+
+// CSV の各行を Spot に変換して値を挿入する（1行目はヘッダ）
+// 値列が空の行は Key の default を使う
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportCsv {
+    pub space_name: String,
+    pub key_name: String,
+    pub data: String,
+    pub lat_column: String,
+    pub lng_column: String,
+    #[serde(default)]
+    pub alt_column: Option<String>,
+    pub value_column: String,
+    pub zoom: u8,
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    //1トランザクションあたりの行数
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+}
+
+// ESRI ASCII グリッドの各セルの中心を zoom の空間IDに変換して値を挿入する
+// 1つの空間IDに入る複数のセルは平均する。Key は FLOAT か DOUBLE
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportAsciiGrid {
+    pub space_name: String,
+    pub key_name: String,
+    pub data: String,
+    pub zoom: u8,
+    #[serde(default)]
+    pub altitude: f64,
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    //1トランザクションあたりの空間ID数
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+}
+
+// GeoJSON の各 Feature を zoom のセルで覆い、property の値を挿入する
+// 高さ方向は altitude_min..=altitude_max [m] の f で覆う
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportGeoJson {
+    pub space_name: String,
+    pub key_name: String,
+    //FeatureCollection
+    pub data: Value,
+    pub property: String,
+    pub zoom: u8,
+    #[serde(default)]
+    pub altitude_min: f64,
+    #[serde(default)]
+    pub altitude_max: f64,
+    #[serde(default)]
+    pub on_overlap: OnOverlap,
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    //1トランザクションあたりのセル数
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+}
+
+// 同じ取り込みの中で Feature 同士が同じセルを覆ったときの扱い
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+pub enum OnOverlap {
+    #[default]
+    Error,
+    First,
+    Last,
+}
+
+// RFC 7946 の Geometry（座標は [経度, 緯度, 高度?]）
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum GeoJsonGeometry {
+    Point(Vec<f64>),
+    MultiPoint(Vec<Vec<f64>>),
+    LineString(Vec<Vec<f64>>),
+    MultiLineString(Vec<Vec<Vec<f64>>>),
+    Polygon(Vec<Vec<Vec<f64>>>),
+    MultiPolygon(Vec<Vec<Vec<Vec<f64>>>>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeoJsonFeature {
+    pub geometry: GeoJsonGeometry,
+    #[serde(default)]
+    pub properties: Option<serde_json::Map<String, Value>>,
+}
+
+// POST /export の本文。range 省略時は Key の全ての値を出力する
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportValues {
+    pub space_name: String,
+    pub key_names: Vec<String>,
+    #[serde(default)]
+    pub range: Option<Range>,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    //parquet feature を有効にしたビルドのみ
+    Parquet,
+}
+
+// GET /tiles/{space}/{key}/{z}/{x}/{y}.mvt の引数
+// floor_min / floor_max はタイルのズームレベルでの f の範囲（両端含む）
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectTile {
+    pub space_name: String,
+    pub key_name: String,
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+    pub floor_min: Option<i32>,
+    pub floor_max: Option<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ShowValues {
     pub space_name: String,
     pub key_name: String,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub color_ramp: Option<ColorRamp>,
 }
 
 // ---------------------- User管理 ----------------------
 
+// roles 省略時は writer ロールを付ける
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateUser {
     pub user_name: String,
     pub password: String,
+    #[serde(default)]
+    pub roles: Option<Vec<String>>,
+}
+
+// 実行ユーザー自身のパスワードを変更する
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChangePassword {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+// admin が他のユーザーのパスワードを設定し直す
+// must_change_password 省略時は次回ログイン時の変更を求める
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResetPassword {
+    pub user_name: String,
+    pub new_password: String,
+    #[serde(default = "default_true")]
+    pub must_change_password: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// ログイン失敗によるロックを解除する
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnlockUser {
+    pub user_name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -254,6 +647,55 @@ pub struct InfoUser {
     pub user_name: String,
 }
 
+// ---------------------- セッション管理 ----------------------
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KillSession {
+    pub session_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KillUserSessions {
+    pub user_name: String,
+}
+
+// API トークンを発行する。user_name で他のユーザーのトークンを作れるのは admin だけ
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateToken {
+    pub token_name: String,
+    #[serde(default)]
+    pub user_name: Option<String>,
+    //省略時は無期限
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+    #[serde(default)]
+    pub scope: TokenScope,
+}
+
+// トークンで実行できるコマンドの範囲。省略した項目は制限しない
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TokenScope {
+    #[serde(default)]
+    pub spaces: Option<Vec<String>>,
+    #[serde(default)]
+    pub categories: Option<Vec<CommandCategory>>,
+}
+
+// Command の分類（process のディスパッチのまとまりと同じ）
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandCategory {
+    Database,
+    Key,
+    Value,
+    User,
+    Session,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevokeToken {
+    pub token_id: String,
+}
+
 // ---------------------- 権限管理 ----------------------
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -266,6 +708,9 @@ pub struct GrantDatabase {
 pub enum CommandDatabase {
     CreateSpace,
     DropSpace,
+    RenameSpace,
+    CloneSpace,
+    ImportSpace,
     ShowSpaces,
     Version,
 }
@@ -281,6 +726,9 @@ pub struct GrantSpacePrivilege {
 pub enum CommandSpace {
     CreateKey,
     DropKey,
+    RenameKey,
+    AlterKeyType,
+    ExportSpace,
     InfoSpace,
     ShowKeys,
 }
@@ -293,13 +741,16 @@ pub struct GrantKeyPrivilege {
     pub command: AllOrChoose<Vec<CommandKey>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum CommandKey {
     InsertValue,
     PatchValue,
     UpdateValue,
+    DeleteValue,
     DropKey,
     SelectValue,
+    SelectPyramid,
+    ValueHistory,
     InfoKey,
     ShowValues,
     FilterValue,
@@ -326,6 +777,76 @@ pub struct RevokeKeyPrivilege {
     pub command: AllOrChoose<Vec<CommandKey>>,
 }
 
+// ---------------------- ロール管理 ----------------------
+
+// ロールが持つ権限。Grant* と同じ Database / Space / Key の3段階
+// ユーザーの権限は付与されたロールの和になる
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Privileges {
+    #[serde(default)]
+    pub database: Option<AllOrChoose<Vec<CommandDatabase>>>,
+    #[serde(default)]
+    pub spaces: Vec<SpacePrivilege>,
+    #[serde(default)]
+    pub keys: Vec<KeyPrivilege>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SpacePrivilege {
+    pub target_space: AllOrChoose<Vec<String>>,
+    pub command: AllOrChoose<Vec<CommandSpace>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyPrivilege {
+    pub target_space: AllOrChoose<Vec<String>>,
+    pub target_key: AllOrChoose<Vec<String>>,
+    pub command: AllOrChoose<Vec<CommandKey>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateRole {
+    pub role_name: String,
+    #[serde(default)]
+    pub privileges: Privileges,
+}
+
+// 付与されているユーザーからも外す
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DropRole {
+    pub role_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrantRole {
+    pub user_name: String,
+    pub role_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevokeRole {
+    pub user_name: String,
+    pub role_name: String,
+}
+
+// ---------------------- 監査ログ ----------------------
+
+// 書き込み系コマンドの記録を新しい順に返す（admin のみ）
+// from / to は UNIX ミリ秒（to は含まない）。limit 件（省略時は 1000 件）まで新しいものから残す
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShowAudit {
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub space_name: Option<String>,
+    #[serde(default)]
+    pub from: Option<u64>,
+    #[serde(default)]
+    pub to: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
 // ---------------------- Packet & Command ----------------------
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -334,23 +855,37 @@ pub enum Command {
     //データベース操作系
     CreateSpace(CreateSpace),
     DropSpace(DropSpace),
+    RenameSpace(RenameSpace),
+    CloneSpace(CloneSpace),
+    ExportSpace(ExportSpace),
+    ImportSpace(ImportSpace),
     InfoSpace(InfoSpace),
+    ShareSpace(ShareSpace),
+    UnshareSpace(UnshareSpace),
     ShowSpaces,
     Version,
 
     //Key操作系
     CreateKey(CreateKey),
     DropKey(DropKey),
+    RenameKey(RenameKey),
+    AlterKeyType(AlterKeyType),
+    CopyKey(CopyKey),
     ShowKeys(ShowKeys),
     InfoKey(InfoKey),
 
     //Value操作系
     InsertValue(InsertValue),
     PatchValue(PatchValue),
-    //UpdateValue(UpdateValue),
+    UpdateValue(UpdateValue),
     DeleteValue(DeleteValue),
     SelectValue(SelectValue),
     ShowValues(ShowValues),
+    SelectPyramid(SelectPyramid),
+    ImportCsv(ImportCsv),
+    ImportGeoJson(ImportGeoJson),
+    ImportAsciiGrid(ImportAsciiGrid),
+    ValueHistory(ValueHistory),
 
     //ツール系
     //Transaction(Vec<Command>),
@@ -358,8 +893,29 @@ pub enum Command {
     //ユーザー操作系
     CreateUser(CreateUser),
     DropUser(DropUser),
+    ChangePassword(ChangePassword),
+    ResetPassword(ResetPassword),
+    UnlockUser(UnlockUser),
     InfoUser(InfoUser),
     ShowUsers,
+
+    //セッション・トークン操作系
+    ShowSessions,
+    KillSession(KillSession),
+    KillUserSessions(KillUserSessions),
+    CreateToken(CreateToken),
+    ShowTokens,
+    RevokeToken(RevokeToken),
+
+    //ロール操作系
+    CreateRole(CreateRole),
+    DropRole(DropRole),
+    GrantRole(GrantRole),
+    RevokeRole(RevokeRole),
+    ShowRoles,
+
+    //監査系
+    ShowAudit(ShowAudit),
     // //権限付与系
     // GrantDatabase(GrantDatabase),
     // GrantSpacePrivilege(GrantSpacePrivilege),
@@ -373,7 +929,9 @@ pub enum Command {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Packet {
-    pub session: String,
+    //Authorization: Bearer で認証する場合は省略できる
+    #[serde(default)]
+    pub session: Option<String>,
     pub command: Vec<Command>,
 }
 
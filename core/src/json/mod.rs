@@ -1,3 +1,4 @@
 /// The Interface-Input module is responsible for reading input strings. It offers functions that return the input as a `String`, supporting various input sources.
-pub mod input;
+pub mod input;
+pub mod ndjson;
 pub mod output;
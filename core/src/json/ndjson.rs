@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    io::{KeyOption, ValueEntry},
+    json::input::{KeyMode, KeyType},
+};
+
+// ExportSpace / ImportSpace の NDJSON 1行分
+// Key の行が先、その Key の値の行が後に並ぶ
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SpaceRecord {
+    Key {
+        key_name: String,
+        key_type: KeyType,
+        key_mode: KeyMode,
+        #[serde(flatten)]
+        option: KeyOption,
+    },
+    Value {
+        key_name: String,
+        id: String,
+        value: ValueEntry,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+    },
+}
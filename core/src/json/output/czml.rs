@@ -0,0 +1,59 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// Cesium の CZML パケット。先頭は id が "document" のパケット
+#[derive(Serialize)]
+pub struct Packet {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    //"開始/終了" の ISO 8601 区間
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+    #[serde(rename = "box", skip_serializing_if = "Option::is_none")]
+    pub cuboid: Option<Cuboid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<Map<String, Value>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    //[経度, 緯度, 高度]
+    pub cartographic_degrees: [f64; 3],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cuboid {
+    pub dimensions: Dimensions,
+    pub material: Material,
+    pub outline: bool,
+    pub outline_color: Color,
+}
+
+#[derive(Serialize)]
+pub struct Dimensions {
+    //東西, 南北, 高さ [m]
+    pub cartesian: [f64; 3],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Material {
+    pub solid_color: SolidColor,
+}
+
+#[derive(Serialize)]
+pub struct SolidColor {
+    pub color: Color,
+}
+
+#[derive(Serialize)]
+pub struct Color {
+    pub rgba: [u8; 4],
+}
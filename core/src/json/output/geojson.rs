@@ -0,0 +1,23 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// RFC 7946 の FeatureCollection（座標は [経度, 緯度]）
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point([f64; 2]),
+    Polygon(Vec<Vec<[f64; 2]>>),
+}
@@ -1,13 +1,15 @@
-use std::collections::HashMap;
-
 use kasane_logic::id::{SpaceTimeId, coordinates::Point};
 use serde::Serialize;
 
 use crate::{
-    io::ValueEntry,
-    json::input::{CommandDatabase, CommandKey, CommandSpace},
+    io::{AuditEntry, ValueEntry},
+    json::input::{KeyConstraint, Privileges, Pyramid, SpaceShare, TokenScope},
 };
 
+pub mod czml;
+pub mod geojson;
+pub mod mvt;
+
 #[derive(Serialize)]
 pub struct ShowSpaces {
     pub spacenames: Vec<String>,
@@ -22,6 +24,16 @@ pub struct Version {
 pub struct InfoSpace {
     pub spacename: String,
     pub keynames: Vec<InfoKey>,
+    //所有者の記録が無い Space は None
+    pub owner: Option<String>,
+    pub shares: Vec<SpaceShare>,
+}
+
+#[derive(Serialize)]
+pub struct ImportSpace {
+    pub keys_created: usize,
+    pub values_written: usize,
+    pub values_skipped: usize,
 }
 
 #[derive(Serialize)]
@@ -29,6 +41,18 @@ pub struct InfoKey {
     pub keyname: String,
     pub keytype: String,
     pub keymode: String,
+    pub pyramid: Option<Pyramid>,
+    pub history: bool,
+    pub constraint: Option<Box<KeyConstraint>>,
+}
+
+#[derive(Serialize)]
+pub struct AlterKeyType {
+    pub applied: bool,
+    pub converted: usize,
+    pub failed: Vec<String>,
+    //新しい型に引き継げず外した制約
+    pub dropped_constraint: Option<Box<KeyConstraint>>,
 }
 
 #[derive(Serialize)]
@@ -44,6 +68,61 @@ pub struct Value {
     pub id_string: String,
     pub value: Vec<(std::string::String, ValueEntry)>,
 }
+
+#[derive(Serialize)]
+pub struct PyramidValue {
+    pub id: SpaceTimeId,
+    pub center: Point,
+    pub id_string: String,
+    pub count: Option<u64>,
+    pub sum: Option<f64>,
+    pub avg: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryValue {
+    pub id_string: String,
+    pub timestamp: u64,
+    pub user: String,
+    pub old: Option<ValueEntry>,
+    pub new: Option<ValueEntry>,
+}
+
+#[derive(Serialize)]
+pub struct ImportRows {
+    pub rows_total: usize,
+    pub rows_imported: usize,
+    pub rows_skipped: usize,
+    pub errors: Vec<RowError>,
+}
+
+// ImportGeoJson の結果。errors の row は 1 始まりの Feature 番号
+#[derive(Serialize)]
+pub struct ImportFeatures {
+    pub features_total: usize,
+    pub cells_imported: usize,
+    pub cells_skipped: usize,
+    pub errors: Vec<RowError>,
+}
+
+// ImportAsciiGrid の結果。errors の row は北から数えた 1 始まりのグリッド行番号
+#[derive(Serialize)]
+pub struct ImportGrid {
+    pub cells_total: usize,
+    pub cells_nodata: usize,
+    pub ids_total: usize,
+    pub ids_imported: usize,
+    pub ids_skipped: usize,
+    pub errors: Vec<RowError>,
+}
+
+// row は 1 始まりのデータ行番号
+#[derive(Serialize)]
+pub struct RowError {
+    pub row: usize,
+    pub message: String,
+}
+
 #[derive(Serialize)]
 pub struct ShowUsers {
     pub users: Vec<String>,
@@ -52,11 +131,81 @@ pub struct ShowUsers {
 #[derive(Serialize)]
 pub struct InfoUser {
     pub user_name: String,
+    pub roles: Vec<String>,
     // database_command: Vec<CommandDatabase>,
     // space_command: Vec<InfoUserSpace>,
     // key_commnad: Vec<InfoUserKey>,
 }
 
+// locked は解除前にロックされていたか
+#[derive(Serialize)]
+pub struct UnlockUser {
+    pub user_name: String,
+    pub locked: bool,
+}
+
+// ShowSessions の1件。時刻は UNIX ミリ秒
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub user_name: String,
+    pub created_at: u64,
+    pub last_access: u64,
+}
+
+#[derive(Serialize)]
+pub struct ShowSessions {
+    pub sessions: Vec<SessionInfo>,
+}
+
+#[derive(Serialize)]
+pub struct KillUserSessions {
+    pub user_name: String,
+    pub sessions_killed: usize,
+}
+
+// CreateToken の結果。token はこの応答でしか返さない
+#[derive(Serialize)]
+pub struct CreatedToken {
+    pub token_id: String,
+    pub token: String,
+    pub expires_at: Option<u64>,
+}
+
+// ShowTokens の1件。時刻は UNIX ミリ秒
+#[derive(Serialize)]
+pub struct TokenInfo {
+    pub token_id: String,
+    pub token_name: String,
+    pub user_name: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub scope: TokenScope,
+}
+
+#[derive(Serialize)]
+pub struct ShowTokens {
+    pub tokens: Vec<TokenInfo>,
+}
+
+// ShowRoles の1件。builtin は admin / writer / reader
+#[derive(Serialize)]
+pub struct RoleInfo {
+    pub role_name: String,
+    pub builtin: bool,
+    pub privileges: Privileges,
+}
+
+#[derive(Serialize)]
+pub struct ShowRoles {
+    pub roles: Vec<RoleInfo>,
+}
+
+#[derive(Serialize)]
+pub struct ShowAudit {
+    pub entries: Vec<AuditEntry>,
+}
+
 #[derive(Serialize)]
 pub struct InfoUserSpace {
     space_name: String,
@@ -80,16 +229,38 @@ pub enum Output {
     InfoSpace(InfoSpace),
     ShowSpaces(ShowSpaces),
     Version(Version),
+    ImportSpace(ImportSpace),
 
     //Key操作系
     Showkeys(Showkeys),
     InfoKey(InfoKey),
+    AlterKeyType(AlterKeyType),
 
     //Value操作系
     SelectValue(Vec<Value>),
     ShowValues(Vec<Value>),
+    GeoJson(geojson::FeatureCollection),
+    Czml(Vec<czml::Packet>),
+    SelectPyramid(Vec<PyramidValue>),
+    ValueHistory(Vec<HistoryValue>),
+    ImportRows(ImportRows),
+    ImportFeatures(ImportFeatures),
+    ImportGrid(ImportGrid),
 
     //ユーザー操作系
     InfoUser(InfoUser),
     ShowUsers(ShowUsers),
+    UnlockUser(UnlockUser),
+
+    //セッション・トークン操作系
+    ShowSessions(ShowSessions),
+    KillUserSessions(KillUserSessions),
+    CreateToken(CreatedToken),
+    ShowTokens(ShowTokens),
+
+    //ロール操作系
+    ShowRoles(ShowRoles),
+
+    //監査系
+    ShowAudit(ShowAudit),
 }
@@ -0,0 +1,153 @@
+// Mapbox Vector Tile (v2) の最小限のエンコーダ
+// https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+
+use std::{collections::HashMap, hash::Hash};
+
+pub const EXTENT: u32 = 4096;
+
+pub enum MvtValue {
+    String(String),
+    Double(f64),
+    Int(i64),
+    Bool(bool),
+}
+
+// タイル座標 (0..EXTENT) の矩形ポリゴン
+pub struct MvtFeature {
+    pub id: u64,
+    pub rect: [u32; 4], // [x0, y0, x1, y1]
+    pub properties: Vec<(String, MvtValue)>,
+}
+
+pub fn encode_tile(layer_name: &str, features: &[MvtFeature]) -> Vec<u8> {
+    let mut keys: Table<&str> = Table::default();
+    let mut values: Table<Vec<u8>> = Table::default();
+
+    let mut layer = Vec::new();
+    put_bytes(&mut layer, 1, layer_name.as_bytes());
+
+    for feature in features {
+        let mut tags = Vec::new();
+        for (key, value) in &feature.properties {
+            let key_index = keys.index_of(key.as_str());
+            let value_index = values.index_of(encode_value(value));
+            put_varint(&mut tags, key_index as u64);
+            put_varint(&mut tags, value_index as u64);
+        }
+
+        let mut f = Vec::new();
+        put_key(&mut f, 1, 0);
+        put_varint(&mut f, feature.id);
+        put_bytes(&mut f, 2, &tags);
+        put_key(&mut f, 3, 0);
+        put_varint(&mut f, 3); // POLYGON
+        put_bytes(&mut f, 4, &rect_geometry(feature.rect));
+        put_bytes(&mut layer, 2, &f);
+    }
+
+    for key in keys.items {
+        put_bytes(&mut layer, 3, key.as_bytes());
+    }
+    for value in values.items {
+        put_bytes(&mut layer, 4, &value);
+    }
+    put_key(&mut layer, 5, 0);
+    put_varint(&mut layer, EXTENT as u64);
+    put_key(&mut layer, 15, 0);
+    put_varint(&mut layer, 2);
+
+    let mut tile = Vec::new();
+    put_bytes(&mut tile, 3, &layer);
+    tile
+}
+
+// レイヤーの keys / values テーブル。同じ要素には同じ添字を返す
+struct Table<T> {
+    items: Vec<T>,
+    index: HashMap<T, usize>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Table {
+            items: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Table<T> {
+    fn index_of(&mut self, item: T) -> usize {
+        if let Some(&i) = self.index.get(&item) {
+            return i;
+        }
+        self.items.push(item.clone());
+        self.index.insert(item, self.items.len() - 1);
+        self.items.len() - 1
+    }
+}
+
+fn encode_value(value: &MvtValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match value {
+        MvtValue::String(s) => put_bytes(&mut buf, 1, s.as_bytes()),
+        MvtValue::Double(d) => {
+            put_key(&mut buf, 3, 1);
+            buf.extend_from_slice(&d.to_le_bytes());
+        }
+        MvtValue::Int(i) => {
+            put_key(&mut buf, 6, 0);
+            put_varint(&mut buf, zigzag(*i));
+        }
+        MvtValue::Bool(b) => {
+            put_key(&mut buf, 7, 0);
+            put_varint(&mut buf, *b as u64);
+        }
+    }
+    buf
+}
+
+// 外周は面積が正になる向き（y 軸下向きで時計回り）
+fn rect_geometry([x0, y0, x1, y1]: [u32; 4]) -> Vec<u8> {
+    let points = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)];
+    let mut buf = Vec::new();
+    let (mut cx, mut cy) = (0i64, 0i64);
+    for (i, (px, py)) in points.iter().enumerate() {
+        match i {
+            0 => put_varint(&mut buf, command(1, 1)), // MoveTo
+            1 => put_varint(&mut buf, command(2, 3)), // LineTo x3
+            _ => {}
+        }
+        put_varint(&mut buf, zigzag(*px as i64 - cx));
+        put_varint(&mut buf, zigzag(*py as i64 - cy));
+        (cx, cy) = (*px as i64, *py as i64);
+    }
+    put_varint(&mut buf, command(7, 1)); // ClosePath
+    buf
+}
+
+fn command(id: u64, count: u64) -> u64 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn put_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    put_varint(buf, (field << 3) | wire_type);
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u64, data: &[u8]) {
+    put_key(buf, field, 2);
+    put_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
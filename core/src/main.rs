@@ -1,47 +1,76 @@
-use actix_web::{App, HttpResponse, HttpServer, Responder, middleware::Logger, post, web};
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer, Responder, get, http::header, middleware::Logger,
+    post, web, web::Bytes,
+};
+use clap::Parser;
 use flexi_logger::{Cleanup, Criterion, FileSpec, Logger as FlexiLogger, Naming};
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
-};
-use tokio::sync::{Mutex, mpsc, oneshot};
-use uuid::Uuid;
+use std::{io::Write, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, oneshot};
 
 use crate::{
-    command::process,
+    auth::{Caller, JwtVerifier},
+    bootstrap::bootstrap_admin,
+    command::{
+        export_space::export_space,
+        export_values::{export_error, export_values},
+        process,
+        select_tile::select_tile,
+        tools::{
+            audit::redacted_command,
+            chunk_writer::ChunkWriter,
+            privilege::{
+                Required, check_privileges, required_privileges, resolve_roles, select_keys,
+            },
+        },
+    },
+    config::{Cli, Config},
     error::Error,
-    io::{StorageTrait, full::Storage},
+    io::{
+        StorageTrait,
+        full::Storage,
+        tools::{
+            time::now_millis,
+            token::{API_TOKEN_PREFIX, token_hash},
+        },
+    },
     json::{
-        input::{Packet, parser},
+        input::{
+            Command, CommandCategory, CommandKey, ExportFormat, ExportSpace, ExportValues,
+            ImportAsciiGrid, ImportCsv, OnConflict, Packet, SelectTile, parser,
+        },
         output::Output,
     },
+    proxy::TrustedProxies,
 };
+pub mod auth;
+pub mod bootstrap;
 pub mod command;
+pub mod config;
 pub mod error;
 pub mod io;
 pub mod json;
+pub mod proxy;
 
 // ==========================
 // 設定
 // ==========================
-const MAX_SESSIONS: usize = 100;
-const SESSION_TIMEOUT: Duration = Duration::from_secs(3600); // 1時間
-const MAX_LOG_SIZE: u64 = 10_000_000; // 10MB
+// その他の値は config.rs（設定ファイル・コマンドライン）で指定する
+// セッションの最終アクセス時刻の書き込みと期限切れの掃除の間隔
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+// 監査ログに残すリクエスト ID のヘッダ。無ければサーバーが UUID を振る
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
 
+// セッション本体は Storage が持つ
 #[derive(Clone)]
 struct AppState {
-    sessions: Arc<Mutex<HashMap<String, Session>>>,
-}
-
-#[derive(Clone)]
-struct Session {
-    username: String,
-    created_at: Instant,
-    last_access: Instant, // 最後アクセス時刻を追加
+    session_timeout: Duration,
+    //JWT の鍵が設定されていなければ None
+    jwt: Option<Arc<JwtVerifier>>,
+    trusted_proxies: TrustedProxies,
 }
 
 #[derive(Deserialize)]
@@ -54,6 +83,8 @@ struct LoginRequest {
 struct LoginResponse {
     session_id: String,
     expires_in_secs: u64,
+    //true なら ChangePassword 以外は実行できない
+    must_change_password: bool,
 }
 
 #[derive(Clone)]
@@ -61,38 +92,77 @@ struct JobSender {
     tx: mpsc::Sender<Job>,
 }
 
-struct Job {
-    cmd: crate::json::input::Command,
-    storage: Arc<Storage>,
-    resp: oneshot::Sender<Result<Output, Error>>,
+enum Job {
+    Command {
+        cmd: Box<Command>,
+        caller: Caller,
+        storage: Arc<Storage>,
+        resp: oneshot::Sender<Result<Output, Error>>,
+    },
+    // エクスポートのストリーミングなど process を通らない処理。終わるまでワーカーを占有する
+    Blocking(Box<dyn FnOnce() + Send>),
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // ==========================
+    // 設定読み込み
+    // ==========================
+    let config = match Config::load(Cli::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let jwt = match JwtVerifier::new(&config.jwt) {
+        Ok(jwt) => jwt,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let trusted_proxies = match TrustedProxies::new(&config.server.trusted_proxies) {
+        Ok(proxies) => proxies,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        }
+    };
+
     // ==========================
     // ログ設定
     // ==========================
-    FlexiLogger::try_with_str("info")
+    FlexiLogger::try_with_str(&config.log.level)
         .unwrap()
-        .log_to_file(FileSpec::default().directory("logs"))
+        .log_to_file(FileSpec::default().directory(&config.log.dir))
         .rotate(
-            Criterion::Size(MAX_LOG_SIZE),
+            Criterion::Size(config.log.max_size),
             Naming::Timestamps,
-            Cleanup::KeepLogFiles(5),
+            Cleanup::KeepLogFiles(config.log.keep_files),
         )
         .start()
         .unwrap();
 
     info!("Server starting...");
+    info!("Config: {:?}", config);
 
-    let storage = Arc::new(Storage::new(None).unwrap());
-    let (tx, rx) = mpsc::channel::<Job>(1000);
+    let storage =
+        Arc::new(Storage::new(&config).map_err(|e| std::io::Error::other(e.to_string()))?);
+    if let Err(e) = bootstrap_admin(&storage, &config.admin) {
+        error!("Admin bootstrap failed: {}", e);
+        eprintln!("Admin bootstrap failed: {}", e);
+        std::process::exit(2);
+    }
+    let (tx, rx) = mpsc::channel::<Job>(config.server.job_queue_size);
     let job_sender = JobSender { tx };
 
     let rx = Arc::new(tokio::sync::Mutex::new(rx));
-    let cpu_cores = num_cpus::get();
+    let workers = config.server.workers();
 
-    for _ in 0..cpu_cores {
+    for _ in 0..workers {
         let rx = Arc::clone(&rx);
         tokio::spawn(async move {
             loop {
@@ -100,46 +170,123 @@ async fn main() -> std::io::Result<()> {
                     let mut guard = rx.lock().await;
                     guard.recv().await
                 };
-                if let Some(job) = job_opt {
-                    let storage = job.storage.clone();
-                    let cmd = job.cmd.clone();
-
-                    let resp = tokio::task::spawn_blocking(move || process(cmd, storage)).await;
-
-                    let _ = match resp {
-                        Ok(r) => job.resp.send(r),
-                        Err(_) => job.resp.send(Err(Error::QueueReceiveError {
-                            location: "spawn_blocking",
-                        })),
-                    };
-                } else {
-                    break;
+                match job_opt {
+                    Some(Job::Command {
+                        cmd,
+                        caller,
+                        storage,
+                        resp,
+                    }) => {
+                        let r =
+                            tokio::task::spawn_blocking(move || process(*cmd, storage, &caller))
+                                .await;
+
+                        let _ = match r {
+                            Ok(r) => resp.send(r),
+                            Err(_) => resp.send(Err(Error::QueueReceiveError {
+                                location: "spawn_blocking",
+                            })),
+                        };
+                    }
+                    Some(Job::Blocking(f)) => {
+                        if tokio::task::spawn_blocking(f).await.is_err() {
+                            error!("Blocking job panicked");
+                        }
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    // 期限切れの値をワーカーとは別に定期的に掃除する
+    {
+        let storage = storage.clone();
+        let expiry_sweep_interval = config.server.expiry_sweep_interval();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(expiry_sweep_interval);
+            loop {
+                interval.tick().await;
+                let storage = storage.clone();
+                let resp =
+                    tokio::task::spawn_blocking(move || storage.purge_expired(now_millis())).await;
+                match resp {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(n)) => info!("Purged {} expired values", n),
+                    Ok(Err(e)) => error!("Expiry sweep failed: {}", e),
+                    Err(_) => error!("Expiry sweep task panicked"),
+                }
+            }
+        });
+    }
+
+    // セッションの掃除
+    {
+        let storage = storage.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let storage = storage.clone();
+                let resp =
+                    tokio::task::spawn_blocking(move || storage.sweep_sessions(now_millis())).await;
+                match resp {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(n)) => info!("Removed {} expired sessions", n),
+                    Ok(Err(e)) => error!("Session sweep failed: {}", e),
+                    Err(_) => error!("Session sweep task panicked"),
                 }
             }
         });
     }
 
     let app_state = AppState {
-        sessions: Arc::new(Mutex::new(HashMap::new())),
+        session_timeout: config.server.session_timeout(),
+        jwt: jwt.map(Arc::new),
+        trusted_proxies,
     };
+    let max_upload_size = config.server.max_upload_size;
+    let max_request_size = config.server.max_request_size;
+    let app_storage = storage.clone();
 
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .app_data(web::Data::new(job_sender.clone()))
-            .app_data(web::Data::new(storage.clone()))
+            .app_data(web::Data::new(app_storage.clone()))
             .app_data(web::Data::new(app_state.clone()))
+            .app_data(web::JsonConfig::default().limit(max_request_size))
+            .app_data(web::PayloadConfig::new(max_request_size))
             .service(execute_json)
+            .service(select)
             .service(login)
+            .service(logout)
+            .service(tile)
+            // ファイル本文を受け取るルートだけ大きな上限を使う
+            .service(
+                web::scope("/import")
+                    .app_data(web::PayloadConfig::new(max_upload_size))
+                    .service(import_csv_file)
+                    .service(import_grid_file),
+            )
+            .service(export)
+            .service(export_space_file)
     })
-    .workers(cpu_cores)
-    .bind(("127.0.0.1", 8080))?
+    .workers(workers)
+    .bind((config.server.bind.as_str(), config.server.port))?
     .run()
-    .await
+    .await?;
+
+    // 停止前に延長したセッションの期限を書き込む
+    if let Err(e) = storage.sweep_sessions(now_millis()) {
+        error!("Session sweep failed: {}", e);
+    }
+    Ok(())
 }
 
 #[post("/execute")]
 async fn execute_json(
+    req: HttpRequest,
     payload: web::Json<Value>,
     job_sender: web::Data<JobSender>,
     storage: web::Data<Arc<Storage>>,
@@ -154,104 +301,773 @@ async fn execute_json(
         }
     };
 
-    // セッションチェック
-    let mut sessions = state.sessions.lock().await;
-    let valid_session = if let Some(session) = sessions.get_mut(&packet.session) {
-        if session.last_access.elapsed() < SESSION_TIMEOUT {
-            session.last_access = Instant::now();
-            true
-        } else {
-            warn!("Session expired: {}", packet.session);
-            sessions.remove(&packet.session);
-            false
-        }
-    } else {
-        warn!("Invalid session used: {}", packet.session);
-        false
+    let caller = match authenticate(&req, &state, &storage, packet.session.as_deref()) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
     };
 
-    if !valid_session {
-        return HttpResponse::Unauthorized().body("Invalid or expired session");
-    }
-
     // コマンド処理
     let mut results = Vec::new();
 
     for cmd in packet.command.clone() {
-        info!("Execute command: {:?}", cmd);
-
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let job = Job {
-            cmd,
-            storage: storage.get_ref().clone(),
-            resp: resp_tx,
-        };
-
-        if let Err(_) = job_sender.tx.send(job).await {
-            error!("Failed to send job to queue");
-            results.push(Err(Error::QueueSendError {
+        // パスワードや一括投入の本文はログに出さない
+        info!(
+            "Execute command: {} ({})",
+            redacted_command(&cmd),
+            caller.request_id
+        );
+        // トークンのスコープ外のコマンドは実行しない
+        if !caller.allows(&cmd) {
+            results.push(Err(Error::PermissionDenied {
+                user_name: caller.user.clone(),
                 location: "execute_json",
             }));
             continue;
         }
+        results.push(run_command(&job_sender, &storage, cmd, &caller).await);
+    }
+
+    HttpResponse::Ok().json(results)
+}
 
-        match resp_rx.await {
-            Ok(res) => results.push(res),
-            Err(_) => {
-                error!("Failed to receive job result");
-                results.push(Err(Error::QueueReceiveError {
-                    location: "execute_json",
-                }))
+#[derive(Deserialize)]
+struct SelectRequest {
+    #[serde(default)]
+    session: Option<String>,
+    command: Command,
+}
+
+// SelectValue / ShowValues の結果を [{"Ok": ...}] で包まずに返す
+// GeoJSON の format では FeatureCollection をそのまま application/geo+json で、CZML はパケットの配列で返す
+#[post("/select")]
+async fn select(
+    http: HttpRequest,
+    req: web::Json<SelectRequest>,
+    job_sender: web::Data<JobSender>,
+    storage: web::Data<Arc<Storage>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let caller = match authenticate(&http, &state, &storage, req.session.as_deref()) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
+    };
+    let cmd = req.into_inner().command;
+    if !matches!(cmd, Command::SelectValue(_) | Command::ShowValues(_)) {
+        return HttpResponse::BadRequest().json(Error::ParseError {
+            message: "Only SelectValue and ShowValues are accepted".to_string(),
+            location: "select",
+        });
+    }
+    info!(
+        "Execute command: {} ({})",
+        redacted_command(&cmd),
+        caller.request_id
+    );
+    if !caller.allows(&cmd) {
+        return HttpResponse::Forbidden().json(Error::PermissionDenied {
+            user_name: caller.user,
+            location: "select",
+        });
+    }
+
+    match run_command(&job_sender, &storage, cmd, &caller).await {
+        Ok(Output::GeoJson(collection)) => match serde_json::to_string(&collection) {
+            Ok(body) => HttpResponse::Ok()
+                .content_type("application/geo+json")
+                .body(body),
+            Err(e) => {
+                error!("GeoJSON serialization failed: {}", e);
+                HttpResponse::InternalServerError().finish()
             }
+        },
+        Ok(Output::Czml(packets)) => HttpResponse::Ok().json(packets),
+        Ok(Output::SelectValue(values) | Output::ShowValues(values)) => {
+            HttpResponse::Ok().json(values)
+        }
+        Ok(output) => HttpResponse::Ok().json(output),
+        Err(e @ (Error::SpaceNotFound { .. } | Error::KeyNotFound { .. })) => {
+            HttpResponse::NotFound().json(e)
+        }
+        Err(e @ (Error::PermissionDenied { .. } | Error::PasswordChangeRequired { .. })) => {
+            HttpResponse::Forbidden().json(e)
         }
+        Err(e) => HttpResponse::BadRequest().json(e),
     }
+}
 
-    HttpResponse::Ok().json(results)
+// コマンドをワーカーのキューに投入して結果を待つ
+async fn run_command(
+    job_sender: &JobSender,
+    storage: &Arc<Storage>,
+    cmd: Command,
+    caller: &Caller,
+) -> Result<Output, Error> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let job = Job::Command {
+        cmd: Box::new(cmd),
+        caller: caller.clone(),
+        storage: storage.clone(),
+        resp: resp_tx,
+    };
+
+    if job_sender.tx.send(job).await.is_err() {
+        error!("Failed to send job to queue");
+        return Err(Error::QueueSendError {
+            location: "run_command",
+        });
+    }
+
+    match resp_rx.await {
+        Ok(res) => res,
+        Err(_) => {
+            error!("Failed to receive job result");
+            Err(Error::QueueReceiveError {
+                location: "run_command",
+            })
+        }
+    }
+}
+
+// セッション ID は URL に載せず Authorization: Bearer で認証する
+#[derive(Deserialize)]
+struct CsvQuery {
+    space_name: String,
+    key_name: String,
+    lat_column: String,
+    lng_column: String,
+    alt_column: Option<String>,
+    value_column: String,
+    zoom: u8,
+    #[serde(default)]
+    on_conflict: OnConflict,
+    batch_size: Option<usize>,
+}
+
+// CSV ファイルを本文としてそのまま受け取る ImportCsv（/import スコープ）
+#[post("/csv")]
+async fn import_csv_file(
+    req: HttpRequest,
+    body: String,
+    query: web::Query<CsvQuery>,
+    job_sender: web::Data<JobSender>,
+    storage: web::Data<Arc<Storage>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let caller = match authenticate(&req, &state, &storage, None) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
+    };
+
+    let q = query.into_inner();
+    let cmd = Command::ImportCsv(ImportCsv {
+        space_name: q.space_name,
+        key_name: q.key_name,
+        data: body,
+        lat_column: q.lat_column,
+        lng_column: q.lng_column,
+        alt_column: q.alt_column,
+        value_column: q.value_column,
+        zoom: q.zoom,
+        on_conflict: q.on_conflict,
+        batch_size: q.batch_size,
+    });
+    info!(
+        "Execute command: ImportCsv (upload) by {} ({})",
+        caller.user, caller.request_id
+    );
+    if !caller.allows(&cmd) {
+        return HttpResponse::Forbidden().json(Error::PermissionDenied {
+            user_name: caller.user,
+            location: "import_csv_file",
+        });
+    }
+
+    HttpResponse::Ok().json(vec![run_command(&job_sender, &storage, cmd, &caller).await])
+}
+
+// CsvQuery と同じく Authorization: Bearer で認証する
+#[derive(Deserialize)]
+struct GridQuery {
+    space_name: String,
+    key_name: String,
+    zoom: u8,
+    #[serde(default)]
+    altitude: f64,
+    #[serde(default)]
+    on_conflict: OnConflict,
+    batch_size: Option<usize>,
+}
+
+// .asc ファイルを本文としてそのまま受け取る ImportAsciiGrid（/import スコープ）
+#[post("/grid")]
+async fn import_grid_file(
+    req: HttpRequest,
+    body: String,
+    query: web::Query<GridQuery>,
+    job_sender: web::Data<JobSender>,
+    storage: web::Data<Arc<Storage>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let caller = match authenticate(&req, &state, &storage, None) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
+    };
+
+    let q = query.into_inner();
+    let cmd = Command::ImportAsciiGrid(ImportAsciiGrid {
+        space_name: q.space_name,
+        key_name: q.key_name,
+        data: body,
+        zoom: q.zoom,
+        altitude: q.altitude,
+        on_conflict: q.on_conflict,
+        batch_size: q.batch_size,
+    });
+    info!(
+        "Execute command: ImportAsciiGrid (upload) by {} ({})",
+        caller.user, caller.request_id
+    );
+    if !caller.allows(&cmd) {
+        return HttpResponse::Forbidden().json(Error::PermissionDenied {
+            user_name: caller.user,
+            location: "import_grid_file",
+        });
+    }
+
+    HttpResponse::Ok().json(vec![run_command(&job_sender, &storage, cmd, &caller).await])
+}
+
+#[derive(Deserialize)]
+struct ExportRequest {
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(flatten)]
+    export: ExportValues,
+}
+
+// SelectValue の結果を CSV / Parquet としてストリーミングで返す
+#[post("/export")]
+async fn export(
+    http: HttpRequest,
+    req: web::Json<ExportRequest>,
+    job_sender: web::Data<JobSender>,
+    storage: web::Data<Arc<Storage>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let caller = match authenticate(&http, &state, &storage, req.session.as_deref()) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = password_change_required(&storage, &caller.user) {
+        return resp;
+    }
+    if !caller.allows_space(CommandCategory::Value, &req.export.space_name) {
+        return HttpResponse::Forbidden().json(Error::PermissionDenied {
+            user_name: caller.user,
+            location: "export",
+        });
+    }
+    let required = select_keys(&req.export.space_name, &req.export.key_names);
+    if let Some(resp) = privileges_denied(&storage, &caller, &required, "export") {
+        return resp;
+    }
+
+    let export = req.into_inner().export;
+    info!("Export {:?} by {}", export, caller.user);
+    let content_type = match export.format {
+        ExportFormat::Csv => "text/csv",
+        ExportFormat::Parquet => "application/vnd.apache.parquet",
+    };
+
+    let storage = storage.get_ref().clone();
+    stream_export(&job_sender, content_type, move |out| {
+        export_values(export, storage, out)
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct ExportSpaceRequest {
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(flatten)]
+    export: ExportSpace,
+}
+
+// ExportSpace の NDJSON をストリーミングで返す
+#[post("/export/space")]
+async fn export_space_file(
+    http: HttpRequest,
+    req: web::Json<ExportSpaceRequest>,
+    job_sender: web::Data<JobSender>,
+    storage: web::Data<Arc<Storage>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let caller = match authenticate(&http, &state, &storage, req.session.as_deref()) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = password_change_required(&storage, &caller.user) {
+        return resp;
+    }
+    let v = req.into_inner().export;
+    let cmd = Command::ExportSpace(v.clone());
+    if !caller.allows(&cmd) {
+        return HttpResponse::Forbidden().json(Error::PermissionDenied {
+            user_name: caller.user,
+            location: "export_space_file",
+        });
+    }
+    if let Some(resp) = privileges_denied(
+        &storage,
+        &caller,
+        &required_privileges(&cmd),
+        "export_space_file",
+    ) {
+        return resp;
+    }
+
+    info!(
+        "Export space {} by {} ({})",
+        v.space_name, caller.user, caller.request_id
+    );
+    let storage = storage.get_ref().clone();
+    stream_export(&job_sender, "application/x-ndjson", move |out| {
+        export_space(v, storage, out)
+    })
+    .await
+}
+
+// write をワーカーのキューで実行し、書き出された内容をチャンクごとにレスポンスへ流す
+async fn stream_export(
+    job_sender: &JobSender,
+    content_type: &'static str,
+    write: impl FnOnce(&mut ChunkWriter) -> Result<(), Error> + Send + 'static,
+) -> HttpResponse {
+    let (tx, mut rx) = mpsc::channel::<Result<Bytes, Error>>(16);
+    let job = Job::Blocking(Box::new(move || {
+        let mut out = ChunkWriter::new(tx.clone());
+        let result = write(&mut out).and_then(|_| out.flush().map_err(export_error));
+        if let Err(e) = result {
+            let _ = tx.blocking_send(Err(e));
+        }
+    }));
+    if job_sender.tx.send(job).await.is_err() {
+        error!("Failed to send job to queue");
+        return HttpResponse::InternalServerError().json(Error::QueueSendError {
+            location: "stream_export",
+        });
+    }
+
+    // 最初のチャンクより前のエラー（Key が無い等）は通常のエラーレスポンスにする
+    let first = match rx.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e @ (Error::SpaceNotFound { .. } | Error::KeyNotFound { .. }))) => {
+            return HttpResponse::NotFound().json(e);
+        }
+        Some(Err(e)) => return HttpResponse::BadRequest().json(e),
+        None => Bytes::new(),
+    };
+
+    let stream = futures_util::stream::unfold((Some(first), rx), |(first, mut rx)| async move {
+        let item = match first {
+            Some(chunk) => Some(Ok(chunk)),
+            None => rx.recv().await,
+        }?;
+        let item = item.map_err(|e| {
+            error!("Export failed while streaming: {}", e);
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        });
+        Some((item, (None, rx)))
+    });
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(stream)
+}
+
+// process を通らないエンドポイント用。パスワード変更待ちのユーザーは拒否する
+fn password_change_required(storage: &Storage, user: &str) -> Option<HttpResponse> {
+    match storage.must_change_password(user) {
+        // JWT のユーザーは user DB に無いことがある
+        Ok(false) | Err(Error::UserNotFound { .. }) => None,
+        Ok(true) => Some(
+            HttpResponse::Forbidden().json(Error::PasswordChangeRequired {
+                user_name: user.to_string(),
+            }),
+        ),
+        Err(e) => Some(HttpResponse::Forbidden().json(e)),
+    }
+}
+
+// process を通らないエンドポイント用。ロールの権限が足りなければ拒否する
+fn privileges_denied(
+    storage: &Storage,
+    caller: &Caller,
+    required: &[Required],
+    location: &'static str,
+) -> Option<HttpResponse> {
+    resolve_roles(storage, caller)
+        .and_then(|caller| check_privileges(storage, &caller, required, location))
+        .err()
+        .map(|e| HttpResponse::Forbidden().json(e))
+}
+
+// 認証した実行者に、クライアントが X-Request-Id を付けていればその ID を使う
+fn authenticate(
+    req: &HttpRequest,
+    state: &AppState,
+    storage: &Storage,
+    session: Option<&str>,
+) -> Result<Caller, HttpResponse> {
+    let mut caller = resolve_caller(req, state, storage, session)?;
+    if let Some(id) = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+    {
+        caller.request_id = id.to_string();
+    }
+    Ok(caller)
+}
+
+// Authorization: Bearer（API トークンか JWT）、なければセッションで認証する
+fn resolve_caller(
+    req: &HttpRequest,
+    state: &AppState,
+    storage: &Storage,
+    session: Option<&str>,
+) -> Result<Caller, HttpResponse> {
+    let Some(token) = bearer_token(req) else {
+        let Some(session) = session else {
+            return Err(HttpResponse::Unauthorized().body("Missing credentials"));
+        };
+        return match session_user(storage, session) {
+            Some(user) => Ok(Caller {
+                session_id: Some(token_hash(session)),
+                ..Caller::new(&user)
+            }),
+            None => Err(HttpResponse::Unauthorized().body("Invalid or expired session")),
+        };
+    };
+
+    if token.starts_with(API_TOKEN_PREFIX) {
+        return match storage.token_user(token) {
+            Ok(Some(token)) => Ok(Caller {
+                scope: Some(token.scope),
+                ..Caller::new(&token.user_name)
+            }),
+            Ok(None) => {
+                warn!("Invalid or expired API token used");
+                Err(HttpResponse::Unauthorized().body("Invalid or expired token"))
+            }
+            Err(e) => {
+                error!("API token lookup failed: {}", e);
+                Err(HttpResponse::InternalServerError().finish())
+            }
+        };
+    }
+
+    let Some(jwt) = &state.jwt else {
+        return Err(HttpResponse::Unauthorized().body("Invalid or expired token"));
+    };
+    jwt.verify(token).map_err(|e| {
+        warn!("JWT rejected: {}", e);
+        HttpResponse::Unauthorized().body("Invalid token")
+    })
+}
+
+// ログインの試行回数を数える接続元。信用するプロキシ経由なら X-Forwarded-For から取る
+fn client_ip(req: &HttpRequest, proxies: &TrustedProxies) -> String {
+    let Some(peer) = req.peer_addr() else {
+        return String::new();
+    };
+    let forwarded_for: Vec<&str> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    proxies.client_ip(peer.ip(), &forwarded_for).to_string()
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// セッションIDを検証してユーザー名を返す（期限切れのセッションは Storage が破棄する）
+fn session_user(storage: &Storage, session_id: &str) -> Option<String> {
+    match storage.session_user(session_id) {
+        Ok(Some(user)) => Some(user),
+        Ok(None) => {
+            warn!("Invalid or expired session used");
+            None
+        }
+        Err(e) => {
+            error!("Session lookup failed: {}", e);
+            None
+        }
+    }
+}
+
+// URL はアクセスログや Referer に残るため、セッション ID はクエリで受け取らない
+// 地図クライアントには CreateToken で期限とスコープを絞った API トークンを渡す
+#[derive(Deserialize)]
+struct TileQuery {
+    floor_min: Option<i32>,
+    floor_max: Option<i32>,
+}
+
+#[get("/tiles/{space}/{key}/{z}/{x}/{y}.mvt")]
+async fn tile(
+    path: web::Path<(String, String, u8, u32, u32)>,
+    query: web::Query<TileQuery>,
+    storage: web::Data<Arc<Storage>>,
+    job_sender: web::Data<JobSender>,
+    state: web::Data<AppState>,
+    http: HttpRequest,
+) -> impl Responder {
+    let caller = match authenticate(&http, &state, &storage, None) {
+        Ok(caller) => caller,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = password_change_required(&storage, &caller.user) {
+        return resp;
+    }
+
+    let (space_name, key_name, z, x, y) = path.into_inner();
+    if !caller.allows_space(CommandCategory::Value, &space_name) {
+        return HttpResponse::Forbidden().json(Error::PermissionDenied {
+            user_name: caller.user,
+            location: "tile",
+        });
+    }
+    let required = [Required::Key(
+        &space_name,
+        &key_name,
+        CommandKey::SelectValue,
+    )];
+    if let Some(resp) = privileges_denied(&storage, &caller, &required, "tile") {
+        return resp;
+    }
+    let req = SelectTile {
+        space_name,
+        key_name,
+        z,
+        x,
+        y,
+        floor_min: query.floor_min,
+        floor_max: query.floor_max,
+    };
+    let storage = storage.get_ref().clone();
+
+    // 他の重い処理と同じくワーカーのキューで実行する
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let job = Job::Blocking(Box::new(move || {
+        let _ = resp_tx.send(select_tile(req, storage));
+    }));
+    if job_sender.tx.send(job).await.is_err() {
+        error!("Failed to send job to queue");
+        return HttpResponse::InternalServerError()
+            .json(Error::QueueSendError { location: "tile" });
+    }
+
+    match resp_rx.await {
+        Ok(Ok(body)) => HttpResponse::Ok()
+            .content_type("application/vnd.mapbox-vector-tile")
+            .body(body),
+        Ok(Err(e @ (Error::SpaceNotFound { .. } | Error::KeyNotFound { .. }))) => {
+            HttpResponse::NotFound().json(e)
+        }
+        Ok(Err(e)) => HttpResponse::BadRequest().json(e),
+        Err(_) => {
+            error!("Failed to receive tile result");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
 }
 
 #[post("/login")]
 async fn login(
+    http: HttpRequest,
     data: web::Data<AppState>,
     req: web::Json<LoginRequest>,
     storage: web::Data<Arc<Storage>>,
 ) -> impl Responder {
-    info!("Login attempt: {}", req.username);
+    let ip = client_ip(&http, &data.trusted_proxies);
+    info!("Login attempt: {} from {}", req.username, ip);
+
+    // 待ち時間中はパスワードを検証しない
+    if let Ok(Some(retry_after_secs)) = storage.begin_login(&req.username, &ip) {
+        warn!(
+            "Login throttled: {} from {} (retry after {}s)",
+            req.username, ip, retry_after_secs
+        );
+        return HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after_secs.to_string()))
+            .json(Error::TooManyLoginAttempts { retry_after_secs });
+    }
 
     if !storage
-        .get_ref()
-        .clone()
         .verify_user(&req.username, &req.password)
         .unwrap_or(false)
     {
-        warn!("Login failed: {}", req.username);
+        // 失敗は begin_login で数えてある
+        warn!("Login failed: {} from {}", req.username, ip);
         return HttpResponse::Unauthorized().body("Invalid credentials");
     }
-
-    let mut sessions = data.sessions.lock().await;
-
-    // 古いセッションを削除
-    sessions.retain(|_, session| session.last_access.elapsed() < SESSION_TIMEOUT);
-
-    if sessions.len() >= MAX_SESSIONS {
-        error!("Session limit reached. Login denied for {}", req.username);
-        return HttpResponse::TooManyRequests().body("Session limit reached");
+    if let Err(e) = storage.record_login_success(&req.username, &ip) {
+        error!("Failed to record login success: {}", e);
     }
 
-    // 新しい session_id 発行
-    let session_id = Uuid::new_v4().to_string();
-    let now = Instant::now();
-    let session = Session {
-        username: req.username.clone(),
-        created_at: now,
-        last_access: now,
+    let session_id = match storage.create_session(&req.username) {
+        Ok(session_id) => session_id,
+        Err(Error::SessionLimitReached { .. }) => {
+            error!("Session limit reached. Login denied for {}", req.username);
+            return HttpResponse::TooManyRequests().body("Session limit reached");
+        }
+        Err(e) => {
+            error!("Session creation failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
     };
 
-    sessions.insert(session_id.clone(), session);
-
-    info!("Login success: {}", req.username);
+    info!("Login success: {} from {}", req.username, ip);
 
     HttpResponse::Ok().json(LoginResponse {
         session_id,
-        expires_in_secs: SESSION_TIMEOUT.as_secs(),
+        expires_in_secs: data.session_timeout.as_secs(),
+        must_change_password: storage.must_change_password(&req.username).unwrap_or(false),
     })
 }
+
+#[derive(Deserialize)]
+struct LogoutRequest {
+    session: String,
+}
+
+#[post("/logout")]
+async fn logout(req: web::Json<LogoutRequest>, storage: web::Data<Arc<Storage>>) -> impl Responder {
+    match storage.drop_session(&req.session) {
+        Ok(Some(user)) => {
+            info!("Logout: {}", user);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::Unauthorized().body("Invalid or expired session"),
+        Err(e) => {
+            error!("Logout failed: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, io::tools::temp_storage::TempStorage};
+    use actix_web::{http::StatusCode, test};
+
+    const PROXY: &str = "10.0.0.1:40000";
+
+    // IP ごとの無料試行を 1 回にして、IP で数えているかを見やすくする
+    fn storage() -> TempStorage {
+        let mut config = Config::default();
+        config.login.ip_free_attempts = 1;
+        let storage = TempStorage::with_config(config);
+        storage.create_user("bob", "bobpassword1", &[]).unwrap();
+        storage
+    }
+
+    async fn try_login(
+        storage: &TempStorage,
+        peer: &str,
+        forwarded_for: Option<&str>,
+        username: &str,
+        password: &str,
+    ) -> StatusCode {
+        let state = AppState {
+            session_timeout: Duration::from_secs(60),
+            jwt: None,
+            trusted_proxies: TrustedProxies::new(&["10.0.0.0/8".to_string()]).unwrap(),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(storage.shared()))
+                .app_data(web::Data::new(state))
+                .service(login),
+        )
+        .await;
+        let mut req = test::TestRequest::post()
+            .uri("/login")
+            .peer_addr(peer.parse().unwrap())
+            .set_json(serde_json::json!({ "username": username, "password": password }));
+        if let Some(ip) = forwarded_for {
+            req = req.insert_header(("x-forwarded-for", ip));
+        }
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn clients_behind_a_trusted_proxy_are_throttled_by_forwarded_ip() {
+        let storage = storage();
+        let client_a = Some("198.51.100.7");
+        assert_eq!(
+            try_login(&storage, PROXY, client_a, "nobody1", "x").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            try_login(&storage, PROXY, client_a, "nobody2", "x").await,
+            StatusCode::UNAUTHORIZED
+        );
+        // A は待ち。正しいパスワードでも検証しない
+        assert_eq!(
+            try_login(&storage, PROXY, client_a, "bob", "bobpassword1").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // 同じプロキシを通る別のクライアントは巻き込まない
+        assert_eq!(
+            try_login(&storage, PROXY, Some("203.0.113.5"), "bob", "bobpassword1").await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn forwarded_for_from_an_untrusted_peer_is_ignored() {
+        let storage = storage();
+        // 信頼しない相手からの X-Forwarded-For では他人の IP を名乗れない
+        for username in ["nobody1", "nobody2"] {
+            assert_eq!(
+                try_login(
+                    &storage,
+                    "192.0.2.1:40000",
+                    Some("198.51.100.7"),
+                    username,
+                    "x"
+                )
+                .await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(
+            try_login(
+                &storage,
+                "192.0.2.1:40000",
+                Some("203.0.113.5"),
+                "bob",
+                "bobpassword1"
+            )
+            .await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            try_login(&storage, PROXY, Some("198.51.100.7"), "bob", "bobpassword1").await,
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::net::IpAddr;
+
+// server.trusted_proxies。この中の接続元からのリクエストだけ X-Forwarded-For を見る
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    nets: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    // "10.0.0.1" や "10.0.0.0/8"、"fd00::/8" を受け付ける
+    pub fn new(entries: &[String]) -> Result<Self, String> {
+        let nets = entries
+            .iter()
+            .map(|entry| {
+                let invalid = || format!("Invalid server.trusted_proxies entry '{}'", entry);
+                let (addr, len) = match entry.split_once('/') {
+                    Some((addr, len)) => (addr, Some(len)),
+                    None => (entry.as_str(), None),
+                };
+                let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+                let max = if addr.is_ipv4() { 32 } else { 128 };
+                let len = match len {
+                    Some(len) => len.trim().parse::<u8>().map_err(|_| invalid())?,
+                    None => max,
+                };
+                if len > max {
+                    return Err(invalid());
+                }
+                Ok((addr, len))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TrustedProxies { nets })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|&(net, len)| match (net, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), len as usize)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), len as usize)
+            }
+            _ => false,
+        })
+    }
+
+    // 接続元が信用するプロキシなら、X-Forwarded-For を右から辿って最初の信用しないアドレスを返す
+    // 左側はクライアントが自由に書けるため、信用するプロキシが付けた分より先は見ない
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: &[&str]) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.contains(client) {
+            return client;
+        }
+        for hop in forwarded_for.iter().rev().flat_map(|h| h.rsplit(',')) {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip.to_canonical();
+            if !self.contains(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], bits: usize) -> bool {
+    let bytes = bits / 8;
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    let rest = bits % 8;
+    rest == 0 || (a[bytes] ^ b[bytes]) >> (8 - rest) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(entries: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let p = proxies(&["10.0.0.1"]);
+        assert_eq!(
+            p.client_ip(ip("203.0.113.9"), &["198.51.100.1"]),
+            ip("203.0.113.9")
+        );
+        assert_eq!(
+            TrustedProxies::default().client_ip(ip("10.0.0.1"), &["1.2.3.4"]),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_read_from_the_right() {
+        let p = proxies(&["10.0.0.0/8"]);
+        // 左端はクライアントが偽装した値
+        assert_eq!(
+            p.client_ip(ip("10.0.0.1"), &["1.1.1.1, 198.51.100.7, 10.2.3.4"]),
+            ip("198.51.100.7")
+        );
+        // ヘッダが複数あっても順につなげて読む
+        assert_eq!(
+            p.client_ip(ip("10.0.0.1"), &["1.1.1.1", "198.51.100.7"]),
+            ip("198.51.100.7")
+        );
+        // 読めない値より先は見ない
+        assert_eq!(
+            p.client_ip(ip("10.0.0.1"), &["1.1.1.1, garbage, 10.9.9.9"]),
+            ip("10.9.9.9")
+        );
+        assert_eq!(p.client_ip(ip("10.0.0.1"), &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn cidr_prefixes() {
+        let p = proxies(&["192.168.4.0/22", "fd00::/8"]);
+        assert!(p.contains(ip("192.168.7.255")));
+        assert!(!p.contains(ip("192.168.8.0")));
+        assert!(p.contains(ip("fd12::1")));
+        assert!(p.contains(ip("::ffff:192.168.5.1")));
+        assert!(TrustedProxies::new(&["10.0.0.0/33".to_string()]).is_err());
+        assert!(TrustedProxies::new(&["proxy.local".to_string()]).is_err());
+    }
+}